@@ -0,0 +1,237 @@
+use alloc::vec::Vec;
+use core::mem;
+
+use crate::crypto::{SignedBody, SignedData};
+use crate::string::SizedCString;
+use crate::{CytrynaError, CytrynaResult, FromBytes};
+
+use derivative::Derivative;
+
+/// Type of public key stored in a certificate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublicKeyType {
+    Rsa4096,
+    Rsa2048,
+    Ecc,
+}
+
+impl PublicKeyType {
+    /// Returns size of the public key itself, modulus for RSA keys and point for ECC ones
+    #[must_use]
+    pub const fn key_size(self) -> usize {
+        match self {
+            Self::Rsa4096 => 0x200,
+            Self::Rsa2048 => 0x100,
+            Self::Ecc => 0x3c,
+        }
+    }
+    /// Parses key type stored big-endian at the start of a certificate body
+    fn from_body(body: &[u8]) -> CytrynaResult<Self> {
+        let raw = body.get(..4).ok_or(CytrynaError::SliceTooSmall)?;
+        Self::try_from(u32::from_be_bytes(raw.try_into().unwrap()))
+    }
+    /// Returns size of public key data stored in a certificate, including exponent and padding
+    const fn stored_size(self) -> usize {
+        match self {
+            Self::Rsa4096 | Self::Rsa2048 => self.key_size() + 0x4 + 0x34,
+            Self::Ecc => self.key_size() + 0x3c,
+        }
+    }
+}
+
+impl TryFrom<u32> for PublicKeyType {
+    type Error = CytrynaError;
+
+    fn try_from(value: u32) -> CytrynaResult<Self> {
+        match value {
+            0 => Ok(Self::Rsa4096),
+            1 => Ok(Self::Rsa2048),
+            2 => Ok(Self::Ecc),
+            _ => Err(CytrynaError::EnumValueOutOfRange("cert::PublicKeyType")),
+        }
+    }
+}
+
+/// Certificate body, excluding signature and issuer
+/// <https://www.3dbrew.org/wiki/Certificates>
+#[derive(Derivative)]
+#[derivative(Debug)]
+#[repr(C)]
+pub struct CertificateInner {
+    key_type: [u8; 0x4],
+    name: SizedCString<0x40>,
+    expiration: [u8; 0x4],
+    #[derivative(Debug = "ignore")]
+    public_key: [u8],
+}
+
+impl FromBytes for CertificateInner {
+    fn min_size() -> usize {
+        0x48
+    }
+    fn bytes_ok(bytes: &[u8]) -> CytrynaResult<()> {
+        if bytes.len() < Self::min_size() {
+            return Err(CytrynaError::SliceTooSmall);
+        }
+        let key_type = PublicKeyType::from_body(bytes)?;
+        if bytes.len() < Self::min_size() + key_type.stored_size() {
+            return Err(CytrynaError::SliceTooSmall);
+        }
+        Ok(())
+    }
+    fn cast(bytes: &[u8]) -> &Self {
+        // DST length is the length of public key data, not the whole body
+        unsafe { mem::transmute(&bytes[..bytes.len() - Self::min_size()]) }
+    }
+}
+
+impl SignedBody for CertificateInner {
+    /// Signature covers the whole body, up to the end of public key padding
+    fn signed_size(bytes: &[u8]) -> usize {
+        Self::min_size() + PublicKeyType::from_body(bytes).map_or(0, |t| t.stored_size())
+    }
+}
+
+/// A type alias for convienency
+pub type Certificate<'a> = SignedData<'a, CertificateInner>;
+
+impl<'a> Certificate<'a> {
+    /// Returns name of this certificate, like "CA00000003"
+    #[must_use]
+    pub fn name(&self) -> &SizedCString<0x40> {
+        &self.data().name
+    }
+    /// Returns type of the public key stored in this certificate
+    pub fn key_type(&self) -> CytrynaResult<PublicKeyType> {
+        PublicKeyType::from_body(&self.data().key_type)
+    }
+    /// Returns raw public key, modulus for RSA keys and point for ECC ones
+    pub fn public_key_bytes(&self) -> CytrynaResult<&[u8]> {
+        Ok(&self.data().public_key[..self.key_type()?.key_size()])
+    }
+    /// Returns the public exponent of RSA keys, ECC keys don't have one so zero is returned
+    pub fn exponent(&self) -> CytrynaResult<u32> {
+        match self.key_type()? {
+            PublicKeyType::Ecc => Ok(0),
+            ty => {
+                let exponent = &self.data().public_key[ty.key_size()..][..4];
+                Ok(u32::from_be_bytes(exponent.try_into().unwrap()))
+            }
+        }
+    }
+    /// Checks if this certificate is the one a given signature issuer, like
+    /// "Root-CA00000003-XS0000000c", refers to
+    #[must_use]
+    pub fn is_issuer(&self, issuer: &str) -> bool {
+        let Some((parent, name)) = issuer.rsplit_once('-') else {
+            return false;
+        };
+        trim_nul(self.issuer().data()) == parent.as_bytes()
+            && trim_nul(self.name().data()) == name.as_bytes()
+    }
+}
+
+fn trim_nul(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    &bytes[..len]
+}
+
+/// Returns size of certificate at the start of a byte slice, judging by its signature and key
+/// types
+fn cert_size(bytes: &[u8]) -> CytrynaResult<usize> {
+    let sig_type = bytes.get(..4).ok_or(CytrynaError::SliceTooSmall)?;
+    let sig_size = match u32::from_be_bytes(sig_type.try_into().unwrap()) {
+        0x10003 => 0x200 + 0x3c,
+        0x10004 => 0x100 + 0x3c,
+        0x10005 => 0x3c + 0x40,
+        _ => return Err(CytrynaError::InvalidMagic),
+    };
+    let body = 0x4 + sig_size + 0x40;
+    let key_type = PublicKeyType::from_body(bytes.get(body..).unwrap_or_default())?;
+
+    Ok(body + CertificateInner::min_size() + key_type.stored_size())
+}
+
+/// Certificates stored back to back, like in CIA certificate chain
+#[derive(Debug)]
+pub struct CertificateChain<'a> {
+    certs: Vec<Certificate<'a>>,
+}
+
+impl<'a> CertificateChain<'a> {
+    /// Parses all certificates in a byte slice, fails if there's any data that isn't a
+    /// certificate
+    pub fn from_bytes(mut bytes: &'a [u8]) -> CytrynaResult<Self> {
+        let mut certs = Vec::new();
+        let mut offset = 0;
+        while !bytes.is_empty() {
+            let size = cert_size(bytes).map_err(|e| e.with_context("certificate", offset))?;
+            let cert = bytes.get(..size).ok_or(CytrynaError::SliceTooSmall)?;
+            certs.push(Certificate::from_bytes(cert)?);
+            bytes = &bytes[size..];
+            offset += size;
+        }
+        Ok(Self { certs })
+    }
+    /// Returns all certificates in the order they are stored in
+    #[must_use]
+    pub fn certs(&self) -> &[Certificate<'a>] {
+        &self.certs
+    }
+    /// Finds the certificate a given signature issuer refers to
+    #[must_use]
+    pub fn by_issuer(&self, issuer: &str) -> Option<&Certificate<'a>> {
+        self.certs.iter().find(|cert| cert.is_issuer(issuer))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{CertificateChain, PublicKeyType};
+    use crate::crypto::{build_envelope, SignatureType};
+    use crate::tests::TEST_MODULUS;
+    use crate::CytrynaError;
+
+    /// Makes an unsigned RSA-2048 certificate holding TEST_MODULUS
+    pub(crate) fn test_cert(sig_type: SignatureType, issuer: &str, name: &str) -> Vec<u8> {
+        let mut body = vec![0u8; 0x48];
+        body[..4].copy_from_slice(&1u32.to_be_bytes());
+        body[0x4..0x4 + name.len()].copy_from_slice(name.as_bytes());
+        body.extend_from_slice(&TEST_MODULUS);
+        body.extend_from_slice(&0x10001u32.to_be_bytes());
+        body.resize(body.len() + 0x34, 0);
+        build_envelope(sig_type, &[], issuer, &body).unwrap()
+    }
+
+    #[test]
+    fn public_key_bytes() {
+        let mut chain = test_cert(SignatureType::Rsa4096Sha256, "Root", "CA00000003");
+        chain.extend(test_cert(
+            SignatureType::Rsa2048Sha256,
+            "Root-CA00000003",
+            "XS0000000c",
+        ));
+        let chain = CertificateChain::from_bytes(&chain).unwrap();
+        assert_eq!(chain.certs().len(), 2);
+
+        let ca = chain.by_issuer("Root-CA00000003").unwrap();
+        assert_eq!(ca.key_type().unwrap(), PublicKeyType::Rsa2048);
+        assert_eq!(ca.public_key_bytes().unwrap().len(), 0x100);
+        assert_eq!(ca.public_key_bytes().unwrap(), TEST_MODULUS);
+        assert_eq!(ca.exponent().unwrap(), 0x10001);
+
+        assert!(chain.by_issuer("Root-CA00000003-XS0000000c").is_some());
+        assert!(chain.by_issuer("Root-CA00000004").is_none());
+    }
+
+    #[test]
+    fn trailing_garbage() {
+        let mut chain = test_cert(SignatureType::Rsa4096Sha256, "Root", "CA00000003");
+        let len = chain.len();
+        chain.extend_from_slice(&[0xff; 0x10]);
+        assert!(matches!(
+            CertificateChain::from_bytes(&chain),
+            Err(CytrynaError::ParseError { context: "certificate", offset, .. }) if offset == len
+        ));
+    }
+}
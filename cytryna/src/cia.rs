@@ -1,12 +1,27 @@
-use core::mem;
+#[cfg(feature = "ncch")]
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::{mem, ptr, slice};
+use core::ops::Deref;
+#[cfg(feature = "std")]
+use std::fs::{self, File};
+#[cfg(feature = "std")]
+use std::io::Read;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
 
-use crate::crypto::aes128_ctr::*;
+use crate::cert::CertificateChain;
+use crate::crypto::{aes128_ctr::*, KeyBag, KeyIndex};
+use crate::hash::sha256;
+#[cfg(feature = "ncch")]
+use crate::ncch::{Ncch, NcchHeader};
 #[cfg(feature = "smdh")]
 use crate::smdh::Smdh;
 use crate::ticket::Ticket;
-use crate::titleid::{MaybeTitleId, TitleId};
+use crate::titleid::{Category, MaybeTitleId, TitleId};
 use crate::tmd::{self, ContentIndex, Tmd};
-use crate::{CytrynaError, CytrynaResult, VecOrSlice, FromBytes};
+use crate::{CytrynaError, CytrynaResult, VecOrSlice, FromBytes, Verify};
 
 use derivative::Derivative;
 use memoffset::span_of;
@@ -20,6 +35,11 @@ const fn align(what: u32) -> usize {
     }
 }
 
+/// Same as [align], for the content size which doesn't fit in u32 for large titles
+fn align_content_size(what: u64) -> Option<usize> {
+    usize::try_from(what).ok()?.checked_next_multiple_of(0x40)
+}
+
 /// CIA Header data
 /// <https://www.3dbrew.org/wiki/CIA#CIA_Header>
 #[derive(Derivative, Clone)]
@@ -40,8 +60,12 @@ pub struct CiaHeader {
 assert_eq_size!([u8; 0x2020], CiaHeader);
 
 const HDR_PAD: usize = align(mem::size_of::<CiaHeader>() as u32) - mem::size_of::<CiaHeader>();
+/// Offset of data following the header in a CIA file
+const DATA_OFFSET: usize = mem::size_of::<CiaHeader>() + HDR_PAD;
 
 /// CIA data
+///
+/// Has to be aligned like CiaHeader to be parsed
 #[repr(C)]
 pub struct Cia {
     header: CiaHeader,
@@ -54,12 +78,17 @@ impl FromBytes for Cia {
         mem::size_of::<CiaHeader>()
     }
     fn cast(bytes: &[u8]) -> &Cia {
-        unsafe { mem::transmute(bytes) }
+        // DST length is the length of trailing data, not the whole file
+        let data_len = bytes.len().saturating_sub(mem::size_of::<CiaHeader>() + HDR_PAD);
+        unsafe { mem::transmute(&bytes[..data_len]) }
     }
     fn bytes_ok(bytes: &[u8]) -> CytrynaResult<()> {
+        if bytes.as_ptr().align_offset(mem::align_of::<CiaHeader>()) != 0 {
+            return Err(CytrynaError::BadAlign);
+        }
         let hdr_size_span = span_of!(CiaHeader, hdr_size);
         let hdr_size = u32::from_le_bytes(bytes[hdr_size_span].try_into().unwrap());
-        if hdr_size != mem::size_of::<CiaHeader>() as u32 {
+        if hdr_size != Cia::HEADER_SIZE {
             return Err(CytrynaError::InvalidHeaderSize);
         }
 
@@ -68,57 +97,325 @@ impl FromBytes for Cia {
 }
 
 impl Cia {
+    /// Size of CIA header, as stored in its `hdr_size` field
+    pub const HEADER_SIZE: u32 = mem::size_of::<CiaHeader>() as u32;
+    /// Reads a whole CIA file into memory, aligned so that it can be parsed in place
+    #[cfg(feature = "std")]
+    pub fn read_from(path: &Path) -> CytrynaResult<OwnedCia> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        let mut buf = vec![0u64; len.div_ceil(mem::size_of::<u64>())];
+        // SAFETY: u64 buffer is at least len bytes long and any bytes are a valid u64
+        let bytes = unsafe { slice::from_raw_parts_mut(buf.as_mut_ptr().cast::<u8>(), len) };
+        file.read_exact(bytes)?;
+        Cia::from_bytes(bytes)?;
+        Ok(OwnedCia { buf, len })
+    }
+    /// Returns the builder for creating CIA files
+    #[must_use]
+    pub fn builder() -> CiaBuilder {
+        CiaBuilder {
+            ty: 0,
+            version: 0,
+            cert_chain: Vec::new(),
+            ticket: None,
+            tmd: None,
+            contents: Vec::new(),
+            meta: None,
+            ticket_gap: 0,
+            tmd_gap: 0,
+        }
+    }
     /// Returns a reference to CIA header
     #[must_use]
     pub fn header(&self) -> &CiaHeader {
         &self.header
     }
-    /// Returns a referene to certificate chain region as a slice.
-    /// Return type of this function will be changed when CertificateChain struct is added.
+    /// Checks if the header declares a certificate chain
+    #[must_use]
+    pub fn has_cert_chain(&self) -> bool {
+        self.header.cert_size != 0
+    }
+    /// Checks if the header declares a ticket
+    #[must_use]
+    pub fn has_ticket(&self) -> bool {
+        self.header.ticket_size != 0
+    }
+    /// Checks if the header declares a title metadata
+    #[must_use]
+    pub fn has_tmd(&self) -> bool {
+        self.header.tmd_size != 0
+    }
+    /// Checks if the header declares a Meta region
+    #[must_use]
+    pub fn has_meta(&self) -> bool {
+        self.header.meta_size != 0
+    }
+    /// Returns a referene to certificate chain region as a slice, see [Cia::cert_chain] for
+    /// parsed certificates
     ///
     /// <https://www.3dbrew.org/wiki/CIA#Certificate_Chain>
     #[must_use]
     pub fn cert_chain_region(&self) -> &[u8] {
         &self.data[..align(self.header.cert_size)]
     }
+    /// Returns certificates stored in certificate chain region
+    pub fn cert_chain(&self) -> CytrynaResult<CertificateChain<'_>> {
+        CertificateChain::from_bytes(self.region(0, self.header.cert_size)?)
+    }
     /// Returns a reference to Ticket region
     pub fn ticket_region(&self) -> CytrynaResult<Ticket> {
-        let offset = align(self.header.cert_size);
-        Ticket::from_bytes(&self.data[offset..][..align(self.header.ticket_size)])
+        let offset = self.ticket_offset()?;
+        self.region(offset, self.header.ticket_size)
+            .and_then(Ticket::from_bytes)
+            .map_err(|e| e.with_context("ticket", DATA_OFFSET + offset))
     }
     /// Returns a reference to Title metadata region
     pub fn tmd_region(&self) -> CytrynaResult<Tmd> {
-        let offset =
-            align(self.header.cert_size) + align(self.header.ticket_size);
-        //Some(unsafe { mem::transmute(&self.data[offset..][..align(self.header.tmd_size)]) })
-        Tmd::from_bytes(&self.data[offset..][..align(self.header.tmd_size)])
+        let offset = self.tmd_offset()?;
+        self.region(offset, self.header.tmd_size)
+            .and_then(Tmd::from_bytes)
+            .map_err(|e| e.with_context("tmd", DATA_OFFSET + offset))
     }
     /// Returns an iterator over contents
     pub fn content_region(&self) -> CytrynaResult<ContentRegionIter> {
-        let offset = align(self.header.cert_size)
-            + align(self.header.ticket_size)
-            + align(self.header.tmd_size);
         let title_key = self.ticket_region()?.title_key()?;
         let tmd = self.tmd_region()?;
         Ok(ContentRegionIter {
             tmd,
             title_key,
-            buf: &self.data[offset..][..align(self.header.content_size as u32)],
+            buf: self.region(self.content_offset()?, self.header.content_size as u32)?,
+            content_index: &self.header.content_index,
             offset: 0,
             chunk_idx: 0,
         })
     }
+    /// Checks if content with a given content index is stored in this CIA, optional contents
+    /// listed in title metadata may be missing
+    #[must_use]
+    pub fn has_content(&self, index: u16) -> bool {
+        content_present(&self.header.content_index, index)
+    }
+    /// Returns the (decrypted) content with a given content index, if this CIA has it
+    pub fn content_by_index(&self, idx: ContentIndex) -> CytrynaResult<Option<ContentRegion<'_>>> {
+        let mut iter = self.content_region()?;
+        while let Some(next) = iter.next_chunk() {
+            let (chunk, buf) = next?;
+            // only the content that is looked for gets decrypted
+            if chunk.index() == idx as u16 {
+                return iter.decrypt(chunk, buf).map(Some);
+            }
+        }
+        Ok(None)
+    }
+    /// Returns the (decrypted) main content, usually the executable NCCH
+    pub fn main_content(&self) -> CytrynaResult<Option<ContentRegion<'_>>> {
+        self.content_by_index(ContentIndex::Main)
+    }
+    fn region(&self, offset: usize, size: u32) -> CytrynaResult<&[u8]> {
+        self.data
+            .get(offset..)
+            .and_then(|data| data.get(..size as usize))
+            .ok_or(CytrynaError::SliceTooSmall)
+    }
+    /// Finds start of signed data (ticket or TMD) at a given offset, skipping over any extra
+    /// zero padding some tools put between regions
+    fn signed_region_offset(&self, mut offset: usize) -> CytrynaResult<usize> {
+        loop {
+            let block = self
+                .data
+                .get(offset..)
+                .map(|data| &data[..data.len().min(0x40)])
+                .filter(|block| !block.is_empty())
+                .ok_or(CytrynaError::InvalidRegionPosition)?;
+
+            if matches!(block, [0x00, 0x01, 0x00, 0x03..=0x05, ..]) {
+                return Ok(offset);
+            } else if block.iter().all(|v| *v == 0) {
+                offset += 0x40;
+            } else {
+                return Err(CytrynaError::InvalidRegionPosition);
+            }
+        }
+    }
+    fn ticket_offset(&self) -> CytrynaResult<usize> {
+        self.signed_region_offset(align(self.header.cert_size))
+    }
+    fn tmd_offset(&self) -> CytrynaResult<usize> {
+        self.signed_region_offset(self.ticket_offset()? + align(self.header.ticket_size))
+    }
+    fn content_offset(&self) -> CytrynaResult<usize> {
+        Ok(self.tmd_offset()? + align(self.header.tmd_size))
+    }
+    /// Returns offsets of all regions in this CIA, and padding before each one of them
+    pub fn layout(&self) -> CytrynaResult<CiaLayout> {
+        let region = |offset: usize, size: usize, prev: &CiaRegionLayout| {
+            let gap = (DATA_OFFSET + offset)
+                .checked_sub(prev.offset + prev.size)
+                .ok_or(CytrynaError::InvalidRegionPosition)?;
+            Ok::<_, CytrynaError>(CiaRegionLayout {
+                offset: DATA_OFFSET + offset,
+                size,
+                gap,
+            })
+        };
+
+        let header = CiaRegionLayout {
+            offset: 0,
+            size: mem::size_of::<CiaHeader>(),
+            gap: 0,
+        };
+        let cert_chain = region(0, self.header.cert_size as usize, &header)?;
+        let ticket = region(self.ticket_offset()?, self.header.ticket_size as usize, &cert_chain)?;
+        let tmd = region(self.tmd_offset()?, self.header.tmd_size as usize, &ticket)?;
+        let content = region(self.content_offset()?, self.header.content_size as usize, &tmd)?;
+        let meta = if self.header.meta_size != 0 {
+            let offset = align_content_size(self.header.content_size)
+                .and_then(|size| size.checked_add(self.content_offset().ok()?))
+                .ok_or(CytrynaError::InvalidRegionPosition)?;
+            Some(region(offset, self.header.meta_size as usize, &content)?)
+        } else {
+            None
+        };
+
+        Ok(CiaLayout {
+            cert_chain,
+            ticket,
+            tmd,
+            content,
+            meta,
+        })
+    }
+    /// Returns keys needed for decrypting contents of this CIA
+    pub fn required_keys(&self) -> CytrynaResult<Vec<KeyIndex>> {
+        let encrypted = self
+            .tmd_region()?
+            .content_chunks()
+            .iter()
+            .any(|c| c.ty().contains(tmd::ContentType::ENCRYPTED));
+
+        if encrypted {
+            Ok(vec![KeyIndex::CommonN(self.ticket_region()?.key_index())])
+        } else {
+            Ok(Vec::new())
+        }
+    }
+    /// Returns keys needed for decrypting contents of this CIA that aren't in a given KeyBag
+    pub fn missing_keys(&self, bag: &KeyBag) -> CytrynaResult<Vec<KeyIndex>> {
+        let mut keys = self.required_keys()?;
+        keys.retain(|idx| match idx {
+            KeyIndex::CommonN(n) => bag.common_key(*n).is_err(),
+            _ => bag.get_key(idx.clone()).is_err(),
+        });
+        Ok(keys)
+    }
+    /// Writes all (decrypted) contents into a directory, named by their content index and
+    /// extension of their kind. Returns paths of written files
+    #[cfg(feature = "std")]
+    pub fn extract_to(&self, dir: &Path) -> CytrynaResult<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for region in self.content_region()? {
+            let region = region?;
+            let name = format!("{:04x}.{}", region.index(), region.kind().extension());
+            let path = dir.join(name);
+            fs::write(&path, region.data())?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+    /// Checks if the title key decrypts the main content into something resembling an NCCH,
+    /// giving early feedback on a wrong common key without decrypting the whole content.
+    ///
+    /// Only the block containing NCCH magic is decrypted, as the first block is a signature that
+    /// can't be told apart from garbage. Unencrypted main content always passes
+    pub fn verify_title_key(&self) -> CytrynaResult<()> {
+        if self.tmd_region()?.title_id()?.category().contains(Category::TWL) {
+            return Err(CytrynaError::Unsupported("title key verification of TWL titles"));
+        }
+        let mut iter = self.content_region()?;
+        let (chunk, buf) = loop {
+            let (chunk, buf) = iter.next_chunk().ok_or(CytrynaError::MissingRegion)??;
+            if chunk.index() == ContentIndex::Main as u16 {
+                break (chunk, buf);
+            }
+        };
+        if !chunk.ty().contains(tmd::ContentType::ENCRYPTED) {
+            return Ok(());
+        }
+
+        // in CBC the previous ciphertext block is the IV of the next one
+        let iv = buf.get(0xf0..0x100).ok_or(CytrynaError::SliceTooSmall)?;
+        let block = buf.get(0x100..0x110).ok_or(CytrynaError::SliceTooSmall)?;
+        let mut out = [0u8; 0x10];
+        Aes128CbcDec::new(&iter.title_key.into(), iv.into())
+            .decrypt_padded_b2b_mut::<NoPadding>(block, &mut out)
+            .unwrap();
+
+        if out[..4] == *b"NCCH" {
+            Ok(())
+        } else {
+            Err(CytrynaError::DecryptionFailed)
+        }
+    }
+    /// Checks if SHA-256 hashes of all (decrypted) contents match hashes in their content chunks
+    ///
+    /// Fails with InvalidLength if a content marked as present in content index isn't stored in
+    /// this CIA, or is cut short
+    pub fn verify_content(&self) -> CytrynaResult<bool> {
+        let tmd = self.tmd_region()?;
+        let chunks: Vec<_> = tmd
+            .content_chunks()
+            .iter()
+            .filter(|c| self.has_content(c.index()))
+            .collect();
+        let present = self
+            .header
+            .content_index
+            .iter()
+            .map(|bits| bits.count_ones() as usize)
+            .sum();
+        if chunks.len() != present {
+            return Err(CytrynaError::InvalidLength {
+                what: "content list",
+                actual: chunks.len(),
+                expected: present,
+            });
+        }
+
+        let mut hashed = 0;
+        for (region, chunk) in self.content_region()?.zip(&chunks) {
+            if sha256(region?.data()) != *chunk.hash() {
+                return Ok(false);
+            }
+            hashed += 1;
+        }
+        if hashed != chunks.len() {
+            return Err(CytrynaError::InvalidLength {
+                what: "content list",
+                actual: hashed,
+                expected: chunks.len(),
+            });
+        }
+        Ok(true)
+    }
+    /// If CIA has a Meta region, returns title IDs of titles it depends on
+    #[must_use]
+    pub fn meta_dependencies(&self) -> Option<Vec<TitleId>> {
+        self.meta_region().map(|meta| meta.dependencies_iter().collect())
+    }
     /// If CIA has a Meta region, returns a reference to it, otherwise None is returned
     #[must_use]
     pub fn meta_region(&self) -> Option<&MetaRegion> {
         if self.header.meta_size != 0 {
-            let offset = align(self.header.cert_size)
-                + align(self.header.ticket_size)
-                + align(self.header.tmd_size)
-                + align(self.header.content_size as u32);
-            assert_eq!(self.header.meta_size as usize, mem::size_of::<MetaRegion>());
+            let offset = self
+                .content_offset()
+                .ok()?
+                .checked_add(align_content_size(self.header.content_size)?)?;
+            if self.header.meta_size as usize != mem::size_of::<MetaRegion>() {
+                return None;
+            }
             unsafe {
-                let ptr = self.data[offset..][..align(self.header.meta_size)].as_ptr();
+                let ptr = self.region(offset, self.header.meta_size).ok()?.as_ptr();
                 Some((ptr as *const MetaRegion).as_ref().unwrap())
             }
         } else {
@@ -127,10 +424,276 @@ impl Cia {
     }
 }
 
+impl Verify for Cia {
+    /// Checks the TMD and hashes of all contents against it
+    fn verify(&self) -> CytrynaResult<()> {
+        // SignedData has an inherent `verify` checking the signature
+        Verify::verify(&self.tmd_region()?)?;
+        if self.verify_content()? {
+            Ok(())
+        } else {
+            Err(CytrynaError::InvalidHash.with_context("content", self.content_offset()?))
+        }
+    }
+}
+
+/// A CIA that owns its data, as returned by [Cia::read_from]
+///
+/// Data is stored in a buffer aligned like CiaHeader and cast to [Cia] on every access
+pub struct OwnedCia {
+    buf: Vec<u64>,
+    len: usize,
+}
+
+impl OwnedCia {
+    /// Returns raw bytes of this CIA
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8] {
+        // SAFETY: buf is at least len bytes long
+        unsafe { slice::from_raw_parts(self.buf.as_ptr().cast::<u8>(), self.len) }
+    }
+}
+
+impl Deref for OwnedCia {
+    type Target = Cia;
+    fn deref(&self) -> &Cia {
+        // already validated when reading
+        Cia::cast(self.as_bytes())
+    }
+}
+
+/// Positions of regions within a CIA file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CiaLayout {
+    pub cert_chain: CiaRegionLayout,
+    pub ticket: CiaRegionLayout,
+    pub tmd: CiaRegionLayout,
+    pub content: CiaRegionLayout,
+    pub meta: Option<CiaRegionLayout>,
+}
+
+impl CiaLayout {
+    /// Value of bytes padding regions to their alignment
+    pub const PADDING: u8 = 0;
+}
+
+/// Position of a single region within a CIA file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CiaRegionLayout {
+    /// Offset from start of the file
+    pub offset: usize,
+    /// Size of the region, without padding
+    pub size: usize,
+    /// Padding between end of previous region and start of this one, including any extra
+    /// padding beyond the usual 0x40 alignment
+    pub gap: usize,
+}
+
+/// Builder for CIA files
+#[derive(Debug, Clone)]
+pub struct CiaBuilder {
+    ty: u16,
+    version: u16,
+    cert_chain: Vec<u8>,
+    ticket: Option<Vec<u8>>,
+    tmd: Option<Vec<u8>>,
+    contents: Vec<(u16, Vec<u8>)>,
+    meta: Option<Vec<u8>>,
+    ticket_gap: usize,
+    tmd_gap: usize,
+}
+
+impl CiaBuilder {
+    /// Creates a builder that rebuilds a given CIA byte-for-byte, contents are kept as they are
+    /// stored, without decrypting them
+    pub fn from_cia(cia: &Cia) -> CytrynaResult<Self> {
+        let layout = cia.layout()?;
+        let region =
+            |region: &CiaRegionLayout| cia.region(region.offset - DATA_OFFSET, region.size as u32);
+        let extra_gap = |region: &CiaRegionLayout| {
+            region.offset - align((region.offset - region.gap) as u32)
+        };
+
+        let tmd = cia.tmd_region()?;
+        let content = region(&layout.content)?;
+        let mut contents = Vec::new();
+        let mut offset = 0;
+        for chunk in tmd.content_chunks() {
+            if !cia.has_content(chunk.index()) {
+                continue;
+            }
+            let size = chunk.size() as usize;
+            let data = content
+                .get(offset..)
+                .and_then(|data| data.get(..size))
+                .ok_or(CytrynaError::SliceTooSmall)?;
+            contents.push((chunk.index(), data.to_vec()));
+            offset += content_align(size);
+        }
+
+        Ok(Self {
+            ty: cia.header.ty,
+            version: cia.header.version,
+            cert_chain: region(&layout.cert_chain)?.to_vec(),
+            ticket: Some(region(&layout.ticket)?.to_vec()),
+            tmd: Some(region(&layout.tmd)?.to_vec()),
+            contents,
+            meta: layout.meta.as_ref().map(region).transpose()?.map(<[u8]>::to_vec),
+            ticket_gap: extra_gap(&layout.ticket),
+            tmd_gap: extra_gap(&layout.tmd),
+        })
+    }
+    /// Sets the certificate chain
+    pub fn with_cert_chain(&mut self, cert_chain: &[u8]) -> &mut Self {
+        self.cert_chain = cert_chain.to_vec();
+        self
+    }
+    /// Sets the ticket
+    pub fn with_ticket(&mut self, ticket: &[u8]) -> &mut Self {
+        self.ticket = Some(ticket.to_vec());
+        self
+    }
+    /// Sets the title metadata
+    pub fn with_tmd(&mut self, tmd: &[u8]) -> &mut Self {
+        self.tmd = Some(tmd.to_vec());
+        self
+    }
+    /// Adds a content with given content index, contents are stored in order they were added
+    /// in, which should match order of content chunks in title metadata
+    pub fn add_content(&mut self, index: u16, data: &[u8]) -> &mut Self {
+        self.contents.push((index, data.to_vec()));
+        self
+    }
+    /// Sets the Meta region, it has to be exactly as big as MetaRegion struct
+    pub fn with_meta(&mut self, meta: &[u8]) -> &mut Self {
+        self.meta = Some(meta.to_vec());
+        self
+    }
+    /// Leaves out the Meta region, as it isn't required for installing a title
+    pub fn without_meta(&mut self) -> &mut Self {
+        self.meta = None;
+        self
+    }
+    /// Drops extra padding between regions kept by [CiaBuilder::from_cia], so that every
+    /// region is only aligned to 0x40 bytes
+    pub fn without_extra_padding(&mut self) -> &mut Self {
+        self.ticket_gap = 0;
+        self.tmd_gap = 0;
+        self
+    }
+    /// Returns the Meta region, creating a zeroed out one if there isn't one
+    fn meta_mut(&mut self) -> CytrynaResult<&mut Vec<u8>> {
+        let meta = self
+            .meta
+            .get_or_insert_with(|| vec![0; mem::size_of::<MetaRegion>()]);
+        if meta.len() != mem::size_of::<MetaRegion>() {
+            return Err(CytrynaError::InvalidLength {
+                what: "meta region",
+                actual: meta.len(),
+                expected: mem::size_of::<MetaRegion>(),
+            });
+        }
+        Ok(meta)
+    }
+    /// Sets the icon stored in Meta region, creating a zeroed out Meta region if there isn't
+    /// one. Icon has to be exactly as big as an SMDH
+    pub fn with_meta_icon(&mut self, icon: &[u8]) -> CytrynaResult<&mut Self> {
+        MetaRegion::validate_icon(icon)?;
+        self.meta_mut()?[span_of!(MetaRegion, icon)].copy_from_slice(icon);
+        Ok(self)
+    }
+    /// Sets the dependency list stored in Meta region, creating a zeroed out Meta region if
+    /// there isn't one. At most [MetaRegion::MAX_DEPENDENCIES] title IDs can be stored
+    pub fn with_meta_dependencies(&mut self, title_ids: &[TitleId]) -> CytrynaResult<&mut Self> {
+        let dependencies = MetaRegion::dependencies_from(title_ids)?;
+        let meta = &mut self.meta_mut()?[span_of!(MetaRegion, dependencies)];
+        for (raw, dependency) in meta.chunks_exact_mut(0x8).zip(dependencies) {
+            raw.copy_from_slice(&dependency.to_bytes());
+        }
+        Ok(self)
+    }
+    /// Builds the CIA
+    pub fn build(&mut self) -> CytrynaResult<Vec<u8>> {
+        let ticket = self.ticket.as_ref().ok_or(CytrynaError::MissingRegion)?;
+        let tmd = self.tmd.as_ref().ok_or(CytrynaError::MissingRegion)?;
+        Ticket::from_bytes(ticket)?;
+
+        let parsed_tmd = Tmd::from_bytes(tmd)?;
+        let chunks = parsed_tmd.content_chunks();
+        // only optional contents can be left out
+        let missing_required = chunks.iter().any(|c| {
+            !c.ty().contains(tmd::ContentType::OPTIONAL)
+                && !self.contents.iter().any(|(idx, _)| *idx == c.index())
+        });
+        if missing_required || self.contents.len() > chunks.len() {
+            return Err(CytrynaError::InvalidLength {
+                what: "content list",
+                actual: self.contents.len(),
+                expected: chunks.len(),
+            });
+        }
+        if let Some(meta) = &self.meta {
+            if meta.len() != mem::size_of::<MetaRegion>() {
+                return Err(CytrynaError::InvalidLength {
+                    what: "meta region",
+                    actual: meta.len(),
+                    expected: mem::size_of::<MetaRegion>(),
+                });
+            }
+        }
+
+        let mut header = CiaHeader {
+            hdr_size: Cia::HEADER_SIZE,
+            ty: self.ty,
+            version: self.version,
+            cert_size: self.cert_chain.len() as u32,
+            ticket_size: ticket.len() as u32,
+            tmd_size: tmd.len() as u32,
+            meta_size: self.meta.as_ref().map_or(0, |m| m.len() as u32),
+            content_size: self
+                .contents
+                .iter()
+                .map(|(_, c)| content_align(c.len()) as u64)
+                .sum(),
+            content_index: [0u8; 0x2000],
+        };
+        for (idx, _) in &self.contents {
+            header.content_index[*idx as usize / 8] |= 0x80 >> (idx % 8);
+        }
+
+        let mut buf = vec![0u8; align(mem::size_of::<CiaHeader>() as u32)];
+        unsafe {
+            let header_ptr = &header as *const CiaHeader as *const u8;
+            ptr::copy_nonoverlapping(header_ptr, buf.as_mut_ptr(), mem::size_of::<CiaHeader>());
+        }
+
+        let mut push_region = |data: &[u8]| {
+            buf.extend_from_slice(data);
+            buf.resize(align(buf.len() as u32), CiaLayout::PADDING);
+        };
+        push_region(&self.cert_chain);
+        push_region(&vec![CiaLayout::PADDING; self.ticket_gap]);
+        push_region(ticket);
+        push_region(&vec![CiaLayout::PADDING; self.tmd_gap]);
+        push_region(tmd);
+        let mut contents = Vec::new();
+        for (_, content) in &self.contents {
+            contents.extend_from_slice(content);
+            contents.resize(content_align(contents.len()), 0);
+        }
+        push_region(&contents);
+        if let Some(meta) = &self.meta {
+            push_region(meta);
+        }
+
+        Ok(buf)
+    }
+}
+
 /// Content region data
 pub struct ContentRegion<'a> {
     data: VecOrSlice<'a, u8>,
-    idx: ContentIndex,
+    index: u16,
 }
 
 impl ContentRegion<'_> {
@@ -139,10 +702,65 @@ impl ContentRegion<'_> {
     pub fn data(&self) -> &[u8] {
         self.data.as_slice()
     }
-    /// Returns the content index of this region
+    /// Returns the raw content index of this region
     #[must_use]
-    pub fn idx(&self) -> ContentIndex {
-        self.idx
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+    /// Returns ContentIndex of this region, fails for DLC contents
+    pub fn idx(&self) -> CytrynaResult<ContentIndex> {
+        ContentIndex::try_from(self.index)
+    }
+    /// Parses this content as an NCCH, fails with BadAlign if the data isn't aligned enough
+    #[cfg(feature = "ncch")]
+    pub fn as_ncch(&self) -> CytrynaResult<&Ncch> {
+        let data = self.data();
+        if data.as_ptr().align_offset(mem::align_of::<NcchHeader>()) != 0 {
+            return Err(CytrynaError::BadAlign);
+        }
+        Ncch::from_slice(data)
+    }
+    /// Parses this content as an NCCH, copying it into a new allocation
+    #[cfg(feature = "ncch")]
+    pub fn into_ncch(self) -> CytrynaResult<Box<Ncch>> {
+        Ncch::from_slice_owned(self.data())
+    }
+    /// Returns the kind of this content, by peeking at its NCCH header
+    #[must_use]
+    pub fn kind(&self) -> ContentKind {
+        let data = self.data();
+        if data.len() < 0x200 || &data[0x100..0x104] != b"NCCH" {
+            return ContentKind::Other;
+        }
+        // executable bit of content type in NCCH flags
+        if data[0x18d] & 0x2 != 0 {
+            ContentKind::Cxi
+        } else {
+            ContentKind::Cfa
+        }
+    }
+}
+
+/// Kind of a CIA content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentKind {
+    /// Executable NCCH
+    Cxi,
+    /// Data-only NCCH, like manuals and DLP children
+    Cfa,
+    /// Anything that isn't an NCCH
+    Other,
+}
+
+impl ContentKind {
+    /// Returns the file extension commonly used for this kind of content
+    #[must_use]
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Cxi => "cxi",
+            Self::Cfa => "cfa",
+            Self::Other => "bin",
+        }
     }
 }
 
@@ -151,35 +769,121 @@ pub struct ContentRegionIter<'a> {
     tmd: Tmd<'a>,
     title_key: [u8; 0x10],
     buf: &'a [u8],
+    content_index: &'a [u8; 0x2000],
     offset: usize,
     chunk_idx: u16,
 }
 
-impl<'a> Iterator for ContentRegionIter<'a> {
-    type Item = ContentRegion<'a>;
+impl<'a> ContentRegionIter<'a> {
+    /// Decrypts next content into a caller-provided scratch buffer instead of allocating a new
+    /// one for every content. Unencrypted contents are borrowed without touching the buffer
+    pub fn decrypt_content_into<'b>(
+        &mut self,
+        scratch: &'b mut [u8],
+    ) -> CytrynaResult<Option<ContentRegion<'b>>>
+    where
+        'a: 'b,
+    {
+        let Some(next) = self.next_chunk() else {
+            return Ok(None);
+        };
+        let (chunk, buf) = next?;
+        let data = if chunk.ty().contains(tmd::ContentType::ENCRYPTED) {
+            block_aligned(buf)?;
+            let out = scratch
+                .get_mut(..buf.len())
+                .ok_or(CytrynaError::SliceTooSmall)?;
+            let iv = content_iv(chunk.index());
+            let out = Aes128CbcDec::new(&self.title_key.into(), &iv.into())
+                .decrypt_padded_b2b_mut::<NoPadding>(buf, out)
+                .unwrap();
+            VecOrSlice::S(out)
+        } else {
+            VecOrSlice::S(buf)
+        };
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let chunks = self.tmd.content_chunks();
-        let chunk = chunks[self.chunk_idx as usize];
-        let idx = chunk.idx();
-        let data;
-
-        if chunk.ty().contains(tmd::ContentType::ENCRYPTED) {
-            let mut iv = [0u8; 0x10];
-            iv[0] = idx as u8;
-            data = VecOrSlice::V(
-                Aes128CbcDec::new(&self.title_key.into(), &iv.into())
-                    .decrypt_padded_vec_mut::<NoPadding>(
-                        &self.buf[self.offset..chunk.size() as usize],
-                    )
-                    .ok()?,
-            );
+        Ok(Some(ContentRegion {
+            data,
+            index: chunk.index(),
+        }))
+    }
+    /// Decrypts a content into a new allocation if it's encrypted, otherwise it's borrowed
+    fn decrypt(&self, chunk: tmd::ContentChunk, buf: &'a [u8]) -> CytrynaResult<ContentRegion<'a>> {
+        let data = if chunk.ty().contains(tmd::ContentType::ENCRYPTED) {
+            block_aligned(buf)?;
+            VecOrSlice::V(
+                Aes128CbcDec::new(&self.title_key.into(), &content_iv(chunk.index()).into())
+                    .decrypt_padded_vec_mut::<NoPadding>(buf)
+                    .unwrap(),
+            )
         } else {
-            data = VecOrSlice::S(&self.buf[self.offset..chunk.size() as usize])
+            VecOrSlice::S(buf)
+        };
+
+        Ok(ContentRegion {
+            data,
+            index: chunk.index(),
+        })
+    }
+    fn next_chunk(&mut self) -> Option<CytrynaResult<(tmd::ContentChunk, &'a [u8])>> {
+        let chunks = self.tmd.content_chunks();
+        // chunks of contents missing from this CIA don't take up any space
+        while !content_present(self.content_index, chunks.get(self.chunk_idx as usize)?.index()) {
+            self.chunk_idx += 1;
         }
+        let chunk = chunks[self.chunk_idx as usize];
+        let size = chunk.size() as usize;
+        let Some(buf) = self.buf.get(self.offset..).and_then(|buf| buf.get(..size)) else {
+            // nothing after a content that is cut short can be found either
+            self.chunk_idx = chunks.len() as u16;
+            return Some(Err(CytrynaError::InvalidLength {
+                what: "content",
+                actual: self.buf.len().saturating_sub(self.offset),
+                expected: size,
+            }));
+        };
 
+        self.offset += content_align(size);
         self.chunk_idx += 1;
-        Some(ContentRegion { data, idx })
+        Some(Ok((chunk, buf)))
+    }
+}
+
+/// Checks the bit of a given content index in the header's content index bitmap
+fn content_present(content_index: &[u8; 0x2000], index: u16) -> bool {
+    content_index[index as usize / 8] & (0x80 >> (index % 8)) != 0
+}
+
+/// Contents are stored back to back, padded to AES block size
+const fn content_align(size: usize) -> usize {
+    size.next_multiple_of(0x10)
+}
+
+/// Encrypted contents have to be a multiple of AES block size, anything else means corruption
+fn block_aligned(buf: &[u8]) -> CytrynaResult<()> {
+    if buf.len() % 0x10 != 0 {
+        return Err(CytrynaError::InvalidLength {
+            what: "encrypted content",
+            actual: buf.len(),
+            expected: content_align(buf.len()),
+        });
+    }
+    Ok(())
+}
+
+/// Content IV is the content index from its chunk record, big-endian, followed by zeroes
+fn content_iv(index: u16) -> [u8; 0x10] {
+    let mut iv = [0u8; 0x10];
+    iv[0..2].copy_from_slice(&index.to_be_bytes());
+    iv
+}
+
+impl<'a> Iterator for ContentRegionIter<'a> {
+    type Item = CytrynaResult<ContentRegion<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.next_chunk()?;
+        Some(next.and_then(|(chunk, buf)| self.decrypt(chunk, buf)))
     }
 }
 
@@ -192,20 +896,60 @@ pub struct MetaRegion {
     _reserved0: [u8; 0x180],
     core_version: u32,
     _reserved1: [u8; 0xfc],
-    icon: [u8; 0x36c0], // mem::size_of::<Smdh>(),
+    icon: [u8; MetaRegion::ICON_SIZE],
 }
 assert_eq_size!([u8; 0x3ac0], MetaRegion);
 
 impl MetaRegion {
+    /// Size of the icon slot, which is the size of an SMDH
+    pub const ICON_SIZE: usize = 0x36c0;
+    /// Checks if icon data fits the icon slot exactly
+    pub fn validate_icon(icon: &[u8]) -> CytrynaResult<()> {
+        if icon.len() != Self::ICON_SIZE {
+            return Err(CytrynaError::InvalidLength {
+                what: "meta icon",
+                actual: icon.len(),
+                expected: Self::ICON_SIZE,
+            });
+        }
+        Ok(())
+    }
+    /// Maximum amount of dependencies stored in Meta region
+    pub const MAX_DEPENDENCIES: usize = 0x30;
+    /// Makes a dependency list out of title IDs, with unused entries zeroed out
+    pub fn dependencies_from(title_ids: &[TitleId]) -> CytrynaResult<[MaybeTitleId; 0x30]> {
+        if title_ids.len() > Self::MAX_DEPENDENCIES {
+            return Err(CytrynaError::InvalidLength {
+                what: "meta dependencies",
+                actual: title_ids.len(),
+                expected: Self::MAX_DEPENDENCIES,
+            });
+        }
+        let mut dependencies = [MaybeTitleId::from_bytes([0; 8]); 0x30];
+        for (dependency, title_id) in dependencies.iter_mut().zip(title_ids) {
+            *dependency = MaybeTitleId::from_bytes(title_id.to_u64().to_le_bytes());
+        }
+        Ok(dependencies)
+    }
     /// Returns dependencies as an array of MaybeTitleId
     #[must_use]
     pub fn dependencies(&self) -> [MaybeTitleId; 0x30] {
         self.dependencies
     }
-    /// Returns an iterator over TitleId structs, skipping dependency fields that aren't used
+    /// Returns an iterator over TitleId structs, skipping dependency fields that aren't used.
+    ///
+    /// Entries that aren't null but don't form a valid TitleId are skipped too, they can still be
+    /// read through [MetaRegion::dependencies]
     pub fn dependencies_iter(&self) -> impl Iterator<Item = TitleId> {
         let copy = self.dependencies;
-        copy.into_iter().filter_map(|v| v.to_titleid().ok())
+        copy.into_iter()
+            .filter(|v| !v.is_null())
+            .filter_map(|v| v.to_titleid().ok())
+    }
+    /// Returns the core version
+    #[must_use]
+    pub fn core_version(&self) -> u32 {
+        u32::from_le(self.core_version)
     }
     /// Returns SMDH data contained in this region
     #[cfg(feature = "smdh")]
@@ -213,3 +957,892 @@ impl MetaRegion {
         Smdh::from_bytes(&self.icon)
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{content_iv, Cia, CiaBuilder, ContentKind, MetaRegion};
+    use core::mem;
+    use crate::crypto::{
+        aes128_ctr::*, build_envelope, tests::test_keybag, KeyBag, KeyIndex, KeyType, SignatureType,
+    };
+    use crate::hash::sha256;
+    use crate::ticket::Ticket;
+    use crate::titleid::TitleId;
+    use crate::tests::verify_any;
+    use crate::tmd::tests::test_tmd;
+    use crate::tmd::ContentIndex;
+    use crate::{CytrynaError, FromBytes};
+
+    /// Makes an RSA-2048 signed ticket
+    pub(crate) fn test_ticket() -> Vec<u8> {
+        build_envelope(SignatureType::Rsa2048Sha256, &[], "", &[0u8; 0x164]).unwrap()
+    }
+
+    /// Makes a CIA with a single unencrypted 0x10 byte content
+    pub(crate) fn minimal_cia() -> Vec<u8> {
+        let tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0, 0x10)]);
+        Cia::builder()
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x10])
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn build_and_read_back() {
+        let tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0, 0x20), (1, 1, 0, 0x10)]);
+        let cia = Cia::builder()
+            .with_cert_chain(&[0xaa; 0x30])
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x20])
+            .add_content(1, &[0x22; 0x10])
+            .build()
+            .unwrap();
+        assert_eq!(cia.len() % 0x40, 0);
+
+        let cia = Cia::from_bytes(&cia).unwrap();
+        assert_eq!(cia.header().content_size, 0x30);
+        assert_eq!(cia.header().content_index[0], 0b1100_0000);
+        assert_eq!(&cia.cert_chain_region()[..0x30], &[0xaa; 0x30]);
+        assert_eq!(cia.tmd_region().unwrap().content_count(), 2);
+        assert!(cia.meta_region().is_none());
+    }
+
+    #[test]
+    fn content_by_index() {
+        test_keybag();
+        let tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0, 0x20), (1, 1, 0, 0x10)]);
+        let cia = Cia::builder()
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x20])
+            .add_content(1, &[0x22; 0x10])
+            .build()
+            .unwrap();
+        let cia = Cia::from_bytes(&cia).unwrap();
+
+        let manual = cia.content_by_index(ContentIndex::Manual).unwrap().unwrap();
+        assert_eq!(manual.idx().unwrap(), ContentIndex::Manual);
+        assert_eq!(manual.data(), &[0x22; 0x10]);
+        let main = cia.main_content().unwrap().unwrap();
+        assert_eq!(main.data(), &[0x11; 0x20]);
+        assert!(cia.content_by_index(ContentIndex::Dlp).unwrap().is_none());
+
+        // contents before the one looked for aren't decrypted, so a broken one doesn't matter
+        let tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0x1, 0x18), (1, 1, 0, 0x10)]);
+        let cia = Cia::builder()
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x18])
+            .add_content(1, &[0x22; 0x10])
+            .build()
+            .unwrap();
+        let cia = Cia::from_bytes(&cia).unwrap();
+        assert!(cia.main_content().is_err());
+        let manual = cia.content_by_index(ContentIndex::Manual).unwrap().unwrap();
+        assert_eq!(manual.data(), &[0x22; 0x10]);
+    }
+
+    #[test]
+    fn dlc_content_indices() {
+        test_keybag();
+        let tmd = test_tmd(
+            0x0004008c00123400,
+            0,
+            &[(0, 0, 0, 0x10), (1, 0x1f, 0, 0x10), (2, 0x102, 0, 0x10)],
+        );
+        let cia = Cia::builder()
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x10])
+            .add_content(0x1f, &[0x22; 0x10])
+            .add_content(0x102, &[0x33; 0x10])
+            .build()
+            .unwrap();
+        let cia = Cia::from_bytes(&cia).unwrap();
+
+        let regions: Vec<_> = cia.content_region().unwrap().map(|r| r.unwrap()).collect();
+        let indices: Vec<_> = regions.iter().map(|r| r.index()).collect();
+        assert_eq!(indices, [0, 0x1f, 0x102]);
+        assert_eq!(regions[2].data(), &[0x33; 0x10]);
+        assert!(matches!(regions[1].idx(), Err(CytrynaError::EnumValueOutOfRange(_))));
+        assert!(cia.verify_content().is_ok());
+    }
+
+    #[test]
+    fn unaligned_cia() {
+        let cia = minimal_cia();
+        let mut unaligned = vec![0u8];
+        unaligned.extend_from_slice(&cia);
+        assert!(matches!(Cia::from_bytes(&unaligned[1..]), Err(CytrynaError::BadAlign)));
+    }
+
+    #[test]
+    fn header_size() {
+        assert_eq!(Cia::HEADER_SIZE, 0x2020);
+
+        let cia = minimal_cia();
+        assert_eq!(cia[..4], Cia::HEADER_SIZE.to_le_bytes());
+    }
+
+    #[test]
+    fn bad_tmd_context() {
+        let mut cia = minimal_cia();
+        let tmd_offset = 0x2040 + test_ticket().len().next_multiple_of(0x40);
+        // claim a bigger TMD than there is data in the file
+        cia[0x10..0x14].copy_from_slice(&0x10000u32.to_le_bytes());
+
+        let err = Cia::from_bytes(&cia).unwrap().tmd_region().unwrap_err();
+        let CytrynaError::ParseError { context, offset, source } = err else {
+            panic!("{err:?}");
+        };
+        assert_eq!(context, "tmd");
+        assert_eq!(offset, tmd_offset);
+        assert!(matches!(*source, CytrynaError::SliceTooSmall));
+    }
+
+    #[test]
+    fn meta_dependencies() {
+        let mut meta = vec![0u8; mem::size_of::<MetaRegion>()];
+        meta[0x0..0x8].copy_from_slice(&0x0004013000001502u64.to_le_bytes());
+        // unused entries in the middle are skipped
+        meta[0x10..0x18].copy_from_slice(&0x0004013000003202u64.to_le_bytes());
+        meta[0x300..0x304].copy_from_slice(&2u32.to_le_bytes());
+
+        let tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0, 0x10)]);
+        let cia = Cia::builder()
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x10])
+            .with_meta(&meta)
+            .build()
+            .unwrap();
+        let cia = Cia::from_bytes(&cia).unwrap();
+        assert_eq!(cia.meta_region().unwrap().core_version(), 2);
+        assert_eq!(
+            cia.meta_dependencies().unwrap(),
+            [
+                TitleId::from_u64(0x0004013000001502).unwrap(),
+                TitleId::from_u64(0x0004013000003202).unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn meta_dependencies_from_title_ids() {
+        let title_ids = [
+            TitleId::from_u64(0x0004013000001502).unwrap(),
+            TitleId::from_u64(0x0004013000003202).unwrap(),
+            TitleId::from_u64(0x0004013000002c02).unwrap(),
+        ];
+        let dependencies = MetaRegion::dependencies_from(&title_ids).unwrap();
+        assert!(dependencies[3..].iter().all(|dep| dep.is_null()));
+        assert!(matches!(
+            MetaRegion::dependencies_from(&[title_ids[0]; 0x31]),
+            Err(CytrynaError::InvalidLength { what: "meta dependencies", actual: 0x31, .. })
+        ));
+
+        let tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0, 0x10)]);
+        let cia = Cia::builder()
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x10])
+            .with_meta_dependencies(&title_ids)
+            .unwrap()
+            .build()
+            .unwrap();
+        let cia = Cia::from_bytes(&cia).unwrap();
+        let meta = cia.meta_region().unwrap();
+        assert_eq!(meta.dependencies(), dependencies);
+        assert_eq!(meta.dependencies_iter().collect::<Vec<_>>(), title_ids);
+    }
+
+    #[cfg(feature = "smdh")]
+    #[test]
+    fn meta_icon() {
+        use crate::smdh::{tests::test_smdh, Language};
+
+        let tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0, 0x10)]);
+        let mut builder = Cia::builder();
+        builder
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x10]);
+        assert!(matches!(
+            builder.with_meta_icon(&[0; 0x100]),
+            Err(CytrynaError::InvalidLength { what: "meta icon", .. })
+        ));
+
+        let smdh = test_smdh();
+        let cia = builder
+            .with_meta_icon(smdh.as_bytes())
+            .unwrap()
+            .build()
+            .unwrap();
+        let cia = Cia::from_bytes(&cia).unwrap();
+        let meta = cia.meta_region().unwrap();
+        assert_eq!(meta.dependencies_iter().count(), 0);
+        let icon = meta.icon().unwrap();
+        assert_eq!(icon.as_bytes(), smdh.as_bytes());
+        assert_eq!(icon.title_strings(Language::English).short, "Short");
+    }
+
+    #[test]
+    fn cert_chain() {
+        use crate::cert::tests::test_cert;
+
+        let mut chain = test_cert(SignatureType::Rsa4096Sha256, "Root", "CA00000003");
+        chain.extend(test_cert(SignatureType::Rsa2048Sha256, "Root-CA00000003", "XS0000000c"));
+        let tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0, 0x10)]);
+        let cia = Cia::builder()
+            .with_cert_chain(&chain)
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x10])
+            .build()
+            .unwrap();
+        let cia = Cia::from_bytes(&cia).unwrap();
+        let chain = cia.cert_chain().unwrap();
+        assert_eq!(chain.certs().len(), 2);
+        assert!(chain.by_issuer("Root-CA00000003-XS0000000c").is_some());
+    }
+
+    #[test]
+    fn present_regions() {
+        let tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0, 0x10)]);
+        let mut builder = Cia::builder();
+        builder
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x10]);
+
+        let cia = builder.build().unwrap();
+        let cia = Cia::from_bytes(&cia).unwrap();
+        assert!(!cia.has_cert_chain());
+        assert!(cia.has_ticket());
+        assert!(cia.has_tmd());
+        assert!(!cia.has_meta());
+
+        let cia = builder
+            .with_cert_chain(&[0xaa; 0x40])
+            .with_meta(&[0; mem::size_of::<MetaRegion>()])
+            .build()
+            .unwrap();
+        let cia = Cia::from_bytes(&cia).unwrap();
+        assert!(cia.has_cert_chain());
+        assert!(cia.has_meta());
+    }
+
+    #[test]
+    fn content_count_mismatch() {
+        let tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0, 0x20)]);
+        let err = Cia::builder()
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, CytrynaError::InvalidLength { actual: 0, expected: 1, .. }));
+    }
+
+    #[test]
+    fn missing_optional_content() {
+        let tmd = test_tmd(
+            0x0004008c00123400,
+            0,
+            &[(0, 0, 0, 0x10), (1, 1, 0x4000, 0x20), (2, 2, 0, 0x10)],
+        );
+        let cia = Cia::builder()
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x10])
+            .add_content(2, &[0x33; 0x10])
+            .build()
+            .unwrap();
+        let cia = Cia::from_bytes(&cia).unwrap();
+        assert!(cia.has_content(0));
+        assert!(!cia.has_content(1));
+        assert!(cia.has_content(2));
+
+        let regions: Vec<_> = cia
+            .content_region()
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(regions.len(), 2);
+        assert_eq!(regions[1].idx().unwrap(), ContentIndex::Dlp);
+        assert_eq!(regions[1].data(), &[0x33; 0x10]);
+
+        // required contents can't be left out
+        let tmd = test_tmd(0x0004008c00123400, 0, &[(0, 0, 0, 0x10), (1, 1, 0, 0x10)]);
+        assert!(Cia::builder()
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x10])
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn partial_dlc() {
+        test_keybag();
+        let mut tmd = test_tmd(
+            0x0004008c00123400,
+            0,
+            &[
+                (0, 0, 0, 0x10),
+                (1, 0x1f, 0x4000, 0x20),
+                (2, 0x20, 0x4000, 0x10),
+                (3, 0x102, 0x4000, 0x10),
+            ],
+        );
+        let chunks = 0x4 + 0x13c + 0x40 + 0x984;
+        let contents = [(0, [0x11; 0x10]), (0x20, [0x33; 0x10]), (0x102, [0x44; 0x10])];
+        for (chunk, (_, data)) in [0, 2, 3].into_iter().zip(&contents) {
+            tmd[chunks + chunk * 0x30 + 0x10..][..0x20].copy_from_slice(&sha256(data));
+        }
+
+        let mut builder = Cia::builder();
+        builder.with_ticket(&test_ticket()).with_tmd(&tmd);
+        for (index, data) in &contents {
+            builder.add_content(*index, data);
+        }
+        let cia = builder.build().unwrap();
+        let cia = Cia::from_bytes(&cia).unwrap();
+        assert!(cia.has_content(0x20) && cia.has_content(0x102));
+        assert!(!cia.has_content(0x1f));
+
+        let regions: Vec<_> = cia.content_region().unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(regions.len(), 3);
+        for (region, (index, data)) in regions.iter().zip(&contents) {
+            assert_eq!(region.index(), *index);
+            assert_eq!(region.data(), data);
+        }
+        assert!(cia.verify_content().unwrap());
+    }
+
+    #[test]
+    fn title_key_plausibility() {
+        test_keybag();
+        let title_key = Ticket::from_bytes(&test_ticket())
+            .unwrap()
+            .title_key()
+            .unwrap();
+        let mut ncch = [0u8; 0x200];
+        ncch[0x100..0x104].copy_from_slice(b"NCCH");
+        let encrypt = |key: [u8; 0x10]| {
+            let mut encrypted = ncch;
+            Aes128CbcEnc::new(&key.into(), &content_iv(0).into())
+                .encrypt_padded_mut::<NoPadding>(&mut encrypted, 0x200)
+                .unwrap();
+            encrypted
+        };
+
+        let tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0x1, 0x200)]);
+        let build = |content: &[u8]| {
+            Cia::builder()
+                .with_ticket(&test_ticket())
+                .with_tmd(&tmd)
+                .add_content(0, content)
+                .build()
+                .unwrap()
+        };
+
+        let cia = build(&encrypt(title_key));
+        assert!(Cia::from_bytes(&cia).unwrap().verify_title_key().is_ok());
+
+        // same as decrypting the title key with a wrong common key
+        let cia = build(&encrypt([0x55; 0x10]));
+        assert!(matches!(
+            Cia::from_bytes(&cia).unwrap().verify_title_key(),
+            Err(CytrynaError::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn decrypt_into_scratch() {
+        test_keybag();
+        let content: Vec<u8> = (0..0x40u8).collect();
+        let tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0x1, 0x40)]);
+        let cia = Cia::builder()
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &content)
+            .build()
+            .unwrap();
+        let cia = Cia::from_bytes(&cia).unwrap();
+
+        let allocated = cia.content_region().unwrap().next().unwrap().unwrap();
+        let mut scratch = vec![0u8; 0x100];
+        for _ in 0..2 {
+            let mut iter = cia.content_region().unwrap();
+            let region = iter.decrypt_content_into(&mut scratch).unwrap().unwrap();
+            assert_eq!(region.data(), allocated.data());
+            assert_ne!(region.data(), &content[..]);
+        }
+
+        let mut iter = cia.content_region().unwrap();
+        let mut small = [0u8; 0x10];
+        let ret = iter.decrypt_content_into(&mut small);
+        assert!(matches!(ret, Err(CytrynaError::SliceTooSmall)));
+    }
+
+    #[test]
+    fn iterate_multiple_contents() {
+        test_keybag();
+        let tmd = test_tmd(
+            0x0004000000123400,
+            0,
+            &[(0, 0, 0, 0x20), (1, 1, 0, 0x18), (2, 2, 0, 0x10)],
+        );
+        let cia = Cia::builder()
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x20])
+            .add_content(1, &[0x22; 0x18])
+            .add_content(2, &[0x33; 0x10])
+            .build()
+            .unwrap();
+        let cia = Cia::from_bytes(&cia).unwrap();
+
+        let regions: Vec<_> = cia
+            .content_region()
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(regions.len(), 3);
+        assert_eq!(regions[0].data(), &[0x11; 0x20]);
+        assert_eq!(regions[1].data(), &[0x22; 0x18]);
+        assert_eq!(regions[2].data(), &[0x33; 0x10]);
+    }
+
+    #[test]
+    fn content_iv_big_endian() {
+        let mut expected = [0u8; 0x10];
+        expected[0] = 0x01;
+        expected[1] = 0x02;
+        assert_eq!(content_iv(0x0102), expected);
+
+        test_keybag();
+        let title_key = Ticket::from_bytes(&test_ticket())
+            .unwrap()
+            .title_key()
+            .unwrap();
+        let mut encrypted = [0x22; 0x10];
+        Aes128CbcEnc::new(&title_key.into(), &content_iv(1).into())
+            .encrypt_padded_mut::<NoPadding>(&mut encrypted, 0x10)
+            .unwrap();
+
+        let tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0, 0x10), (1, 1, 0x1, 0x10)]);
+        let cia = Cia::builder()
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x10])
+            .add_content(1, &encrypted)
+            .build()
+            .unwrap();
+        let cia = Cia::from_bytes(&cia).unwrap();
+        let manual = cia.content_region().unwrap().nth(1).unwrap().unwrap();
+        assert_eq!(manual.data(), &[0x22; 0x10]);
+    }
+
+    #[test]
+    fn decrypt_dlc_content() {
+        test_keybag();
+        let title_key = Ticket::from_bytes(&test_ticket())
+            .unwrap()
+            .title_key()
+            .unwrap();
+        let plain = [0x22; 0x20];
+        let mut iv = [0u8; 0x10];
+        iv[..2].copy_from_slice(&[0x01, 0x02]);
+        let mut encrypted = plain;
+        Aes128CbcEnc::new(&title_key.into(), &iv.into())
+            .encrypt_padded_mut::<NoPadding>(&mut encrypted, 0x20)
+            .unwrap();
+
+        let mut tmd = test_tmd(
+            0x0004008c00123400,
+            0,
+            &[(0, 0, 0, 0x10), (1, 0x102, 0x1, 0x20)],
+        );
+        let chunks = 0x4 + 0x13c + 0x40 + 0x984;
+        tmd[chunks + 0x10..][..0x20].copy_from_slice(&sha256(&[0x11; 0x10]));
+        tmd[chunks + 0x40..][..0x20].copy_from_slice(&sha256(&plain));
+        let cia = Cia::builder()
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x10])
+            .add_content(0x102, &encrypted)
+            .build()
+            .unwrap();
+        let cia = Cia::from_bytes(&cia).unwrap();
+
+        let dlc = cia.content_region().unwrap().nth(1).unwrap().unwrap();
+        assert_eq!(dlc.index(), 0x102);
+        assert_eq!(dlc.data(), &plain);
+        assert!(cia.verify_content().unwrap());
+
+        let mut iter = cia.content_region().unwrap();
+        let mut scratch = [0u8; 0x20];
+        iter.decrypt_content_into(&mut scratch).unwrap();
+        let dlc = iter.decrypt_content_into(&mut scratch).unwrap().unwrap();
+        assert_eq!(dlc.data(), &plain);
+    }
+
+    #[test]
+    fn unaligned_encrypted_content() {
+        test_keybag();
+        let tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0x1, 0x18)]);
+        let cia = Cia::builder()
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x18])
+            .build()
+            .unwrap();
+        let cia = Cia::from_bytes(&cia).unwrap();
+
+        let ret = cia.content_region().unwrap().next().unwrap();
+        assert!(matches!(
+            ret,
+            Err(CytrynaError::InvalidLength { actual: 0x18, expected: 0x20, .. })
+        ));
+        let mut scratch = [0u8; 0x20];
+        let ret = cia.content_region().unwrap().decrypt_content_into(&mut scratch);
+        assert!(matches!(ret, Err(CytrynaError::InvalidLength { .. })));
+    }
+
+    #[test]
+    fn verify_content_hashes() {
+        test_keybag();
+        let title_key = Ticket::from_bytes(&test_ticket())
+            .unwrap()
+            .title_key()
+            .unwrap();
+        let plain = [0x11; 0x20];
+        let mut encrypted = plain;
+        Aes128CbcEnc::new(&title_key.into(), &[0u8; 0x10].into())
+            .encrypt_padded_mut::<NoPadding>(&mut encrypted, 0x20)
+            .unwrap();
+
+        let mut tmd = test_tmd(
+            0x0004000000123400,
+            0,
+            &[(0, 0, 0x1, 0x20), (1, 1, 0, 0x10)],
+        );
+        let chunks = 0x4 + 0x13c + 0x40 + 0x984;
+        tmd[chunks + 0x10..][..0x20].copy_from_slice(&sha256(&plain));
+        tmd[chunks + 0x40..][..0x20].copy_from_slice(&sha256(&[0x22; 0x10]));
+
+        let build = |tmd: &[u8]| {
+            Cia::builder()
+                .with_ticket(&test_ticket())
+                .with_tmd(tmd)
+                .add_content(0, &encrypted)
+                .add_content(1, &[0x22; 0x10])
+                .build()
+                .unwrap()
+        };
+        let cia = build(&tmd);
+        assert!(Cia::from_bytes(&cia).unwrap().verify_content().unwrap());
+
+        tmd[chunks + 0x40] ^= 0x1;
+        let cia = build(&tmd);
+        assert!(!Cia::from_bytes(&cia).unwrap().verify_content().unwrap());
+    }
+
+    #[test]
+    fn verify_truncated_content() {
+        test_keybag();
+        let mut tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0, 0x10), (1, 1, 0, 0x20)]);
+        let chunks = 0x4 + 0x13c + 0x40 + 0x984;
+        tmd[chunks + 0x10..][..0x20].copy_from_slice(&sha256(&[0x11; 0x10]));
+        tmd[chunks + 0x40..][..0x20].copy_from_slice(&sha256(&[0x22; 0x20]));
+        let mut cia = Cia::builder()
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x10])
+            .add_content(1, &[0x22; 0x20])
+            .build()
+            .unwrap();
+        assert!(Cia::from_bytes(&cia).unwrap().verify_content().unwrap());
+
+        // content region ends halfway through the last content
+        let content_size = &mut cia[0x18..0x20];
+        content_size.copy_from_slice(&0x20u64.to_le_bytes());
+        let truncated = Cia::from_bytes(&cia).unwrap();
+        assert!(matches!(
+            truncated.verify_content(),
+            Err(CytrynaError::InvalidLength { what: "content", actual: 0x10, expected: 0x20 })
+        ));
+        assert!(verify_any(truncated).is_err());
+        let regions: Vec<_> = truncated.content_region().unwrap().collect();
+        assert_eq!(regions.len(), 2);
+        assert!(regions[1].is_err());
+
+        // content index marks a content that has no content chunk
+        cia[0x18..0x20].copy_from_slice(&0x30u64.to_le_bytes());
+        cia[0x20] |= 0x20;
+        assert!(matches!(
+            Cia::from_bytes(&cia).unwrap().verify_content(),
+            Err(CytrynaError::InvalidLength { what: "content list", actual: 2, expected: 3 })
+        ));
+    }
+
+    #[test]
+    fn verify_trait() {
+        let mut tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0, 0x10)]);
+        let chunks = 0x4 + 0x13c + 0x40 + 0x984;
+        tmd[chunks + 0x10..][..0x20].copy_from_slice(&sha256(&[0x11; 0x10]));
+        let build = |content: &[u8]| {
+            Cia::builder()
+                .with_ticket(&test_ticket())
+                .with_tmd(&tmd)
+                .add_content(0, content)
+                .build()
+                .unwrap()
+        };
+
+        let cia = build(&[0x11; 0x10]);
+        assert!(verify_any(Cia::from_bytes(&cia).unwrap()).is_ok());
+
+        let cia = build(&[0x22; 0x10]);
+        assert!(matches!(
+            verify_any(Cia::from_bytes(&cia).unwrap()),
+            Err(CytrynaError::ParseError { context: "content", .. })
+        ));
+    }
+
+    #[test]
+    fn required_common_key() {
+        let ticket = Ticket::builder()
+            .with_title_id(TitleId::from_u64(0x0004000000123400).unwrap())
+            .with_title_key([0; 0x10])
+            .with_key_index(1)
+            .build()
+            .unwrap();
+        let tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0x1, 0x10)]);
+        let cia = Cia::builder()
+            .with_ticket(&ticket)
+            .with_tmd(&tmd)
+            .add_content(0, &[0; 0x10])
+            .build()
+            .unwrap();
+        let cia = Cia::from_bytes(&cia).unwrap();
+
+        assert_eq!(cia.required_keys().unwrap(), [KeyIndex::CommonN(1)]);
+        assert_eq!(
+            cia.missing_keys(&KeyBag::new()).unwrap(),
+            [KeyIndex::CommonN(1)]
+        );
+        let mut bag = KeyBag::new();
+        bag.set_key(KeyIndex::CommonN(1), [0; 0x10]);
+        assert!(cia.missing_keys(&bag).unwrap().is_empty());
+
+        let mut bag = KeyBag::new();
+        bag.set_key(KeyIndex::Common(1), [0xc1; 0x10]);
+        assert_eq!(cia.missing_keys(&bag).unwrap(), [KeyIndex::CommonN(1)]);
+        bag.set_key(KeyIndex::Generator, [0x11; 0x10]);
+        bag.set_key(KeyIndex::Slot(0x3d, KeyType::X), [0x3d; 0x10]);
+        assert!(cia.missing_keys(&bag).unwrap().is_empty());
+    }
+
+    #[test]
+    fn extra_padding_between_regions() {
+        let tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0, 0x10)]);
+        let mut cia = Cia::builder()
+            .with_cert_chain(&[0xaa; 0x40])
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x10])
+            .build()
+            .unwrap();
+        let ticket_offset = 0x2040 + 0x40;
+        let tmd_offset = ticket_offset + test_ticket().len().next_multiple_of(0x40);
+
+        // extra padding before ticket and TMD
+        cia.splice(tmd_offset..tmd_offset, [0u8; 0x80]);
+        cia.splice(ticket_offset..ticket_offset, [0u8; 0x40]);
+        let parsed = Cia::from_bytes(&cia).unwrap();
+        assert_eq!(parsed.ticket_region().unwrap().key_index(), 0);
+        assert_eq!(parsed.tmd_region().unwrap().content_count(), 1);
+
+        // garbage where ticket should be
+        cia[ticket_offset] = 0xff;
+        let parsed = Cia::from_bytes(&cia).unwrap();
+        assert!(matches!(
+            parsed.ticket_region(),
+            Err(CytrynaError::InvalidRegionPosition)
+        ));
+    }
+
+    #[test]
+    fn rebuild_from_layout() {
+        let tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0x1, 0x20), (1, 1, 0, 0x18)]);
+        let mut cia = Cia::builder()
+            .with_cert_chain(&[0xaa; 0x30])
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x20])
+            .add_content(1, &[0x22; 0x18])
+            .with_meta(&[0x33; mem::size_of::<MetaRegion>()])
+            .build()
+            .unwrap();
+        cia[0x6] = 0x1;
+        let ticket_offset = 0x2040 + 0x40;
+        let tmd_offset = ticket_offset + test_ticket().len().next_multiple_of(0x40);
+        cia.splice(tmd_offset..tmd_offset, [0u8; 0x80]);
+
+        let parsed = Cia::from_bytes(&cia).unwrap();
+        let layout = parsed.layout().unwrap();
+        assert_eq!(layout.cert_chain.offset, 0x2040);
+        assert_eq!(layout.cert_chain.gap, 0x20);
+        assert_eq!(layout.ticket.offset, ticket_offset);
+        assert_eq!(layout.ticket.gap, 0x10);
+        assert_eq!(layout.tmd.offset, tmd_offset + 0x80);
+        assert_eq!(layout.tmd.gap, 0x80 + tmd_offset - ticket_offset - test_ticket().len());
+        assert_eq!(layout.content.size, 0x40);
+        assert_eq!(layout.meta.unwrap().size, mem::size_of::<MetaRegion>());
+
+        let rebuilt = CiaBuilder::from_cia(parsed).unwrap().build().unwrap();
+        assert_eq!(rebuilt, cia);
+    }
+
+    #[test]
+    fn layout_over_4gib() {
+        let tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0, 0x10)]);
+        let mut cia = Cia::builder()
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x10])
+            .with_meta(&[0x33; mem::size_of::<MetaRegion>()])
+            .build()
+            .unwrap();
+        // content size that gets truncated to 0x10 as u32
+        cia[0x18..0x20].copy_from_slice(&0x1_0000_0010u64.to_le_bytes());
+
+        let layout = Cia::cast(&cia).layout().unwrap();
+        let meta = layout.meta.unwrap();
+        assert_eq!(meta.offset, layout.content.offset + 0x1_0000_0040);
+        assert_eq!(meta.gap, 0x30);
+    }
+
+    #[test]
+    fn trimmed_rebuild() {
+        let mut tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0, 0x10)]);
+        let chunks = 0x4 + 0x13c + 0x40 + 0x984;
+        tmd[chunks + 0x10..][..0x20].copy_from_slice(&sha256(&[0x11; 0x10]));
+        let meta = [0x33; mem::size_of::<MetaRegion>()];
+        let mut cia = Cia::builder()
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &[0x11; 0x10])
+            .with_meta(&meta)
+            .build()
+            .unwrap();
+        let ticket_offset = 0x2040;
+        let tmd_offset = ticket_offset + test_ticket().len().next_multiple_of(0x40);
+        cia.splice(tmd_offset..tmd_offset, [0u8; 0x80]);
+
+        let mut builder = CiaBuilder::from_cia(Cia::from_bytes(&cia).unwrap()).unwrap();
+        let trimmed = builder.without_meta().without_extra_padding().build().unwrap();
+        assert_eq!(trimmed.len(), cia.len() - 0x80 - meta.len());
+        let parsed = Cia::from_bytes(&trimmed).unwrap();
+        assert!(!parsed.has_meta());
+        assert!(verify_any(parsed).is_ok());
+
+        let with_meta = builder.with_meta(&meta).build().unwrap();
+        assert!(Cia::from_bytes(&with_meta).unwrap().has_meta());
+    }
+
+    #[test]
+    fn read_from_file() {
+        let cia = minimal_cia();
+        let path = crate::tests::temp_path("read-from.cia");
+        std::fs::write(&path, &cia).unwrap();
+
+        let owned = Cia::read_from(&path).unwrap();
+        assert_eq!(owned.as_bytes(), &cia[..]);
+        assert_eq!(owned.header().content_size, 0x10);
+        assert_eq!(owned.tmd_region().unwrap().content_count(), 1);
+
+        std::fs::write(&path, &cia[..0x20]).unwrap();
+        assert!(matches!(Cia::read_from(&path), Err(CytrynaError::SliceTooSmall)));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn extract_by_kind() {
+        test_keybag();
+        let ncch = |content_type: u8| {
+            let mut ncch = vec![0u8; 0x200];
+            ncch[0x100..0x104].copy_from_slice(b"NCCH");
+            ncch[0x18d] = content_type;
+            ncch
+        };
+        let tmd = test_tmd(
+            0x0004000000123400,
+            0,
+            &[(0, 0, 0, 0x200), (1, 1, 0, 0x200), (2, 2, 0, 0x10)],
+        );
+        let cia = Cia::builder()
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &ncch(0x1 | 0x2))
+            .add_content(1, &ncch(0x1 | 0x8))
+            .add_content(2, &[0x33; 0x10])
+            .build()
+            .unwrap();
+        let cia = Cia::from_bytes(&cia).unwrap();
+
+        let kinds: Vec<_> = cia
+            .content_region()
+            .unwrap()
+            .map(|r| r.unwrap().kind())
+            .collect();
+        assert_eq!(kinds, [ContentKind::Cxi, ContentKind::Cfa, ContentKind::Other]);
+
+        let dir = crate::tests::temp_path("extract-by-kind");
+        std::fs::create_dir(&dir).unwrap();
+        let paths = cia.extract_to(&dir).unwrap();
+        assert_eq!(
+            paths,
+            [dir.join("0000.cxi"), dir.join("0001.cfa"), dir.join("0002.bin")]
+        );
+        assert_eq!(std::fs::read(&paths[2]).unwrap(), [0x33; 0x10]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "ncch")]
+    #[test]
+    fn main_content_as_ncch() {
+        use crate::ncch::tests::ncch_header;
+
+        test_keybag();
+        let title_key = Ticket::from_bytes(&test_ticket())
+            .unwrap()
+            .title_key()
+            .unwrap();
+        let mut ncch = ncch_header();
+        ncch[0x118..0x120].copy_from_slice(&0x0004000000123400u64.to_le_bytes());
+        Aes128CbcEnc::new(&title_key.into(), &[0u8; 0x10].into())
+            .encrypt_padded_mut::<NoPadding>(&mut ncch, 0x200)
+            .unwrap();
+
+        let tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0x1, 0x200)]);
+        let cia = Cia::builder()
+            .with_ticket(&test_ticket())
+            .with_tmd(&tmd)
+            .add_content(0, &ncch)
+            .build()
+            .unwrap();
+        let cia = Cia::from_bytes(&cia).unwrap();
+
+        let region = cia.content_region().unwrap().next().unwrap().unwrap();
+        let ncch = region.as_ncch().unwrap();
+        assert_eq!(ncch.header().program_id().unwrap().to_u64(), 0x0004000000123400);
+        let ncch = region.into_ncch().unwrap();
+        assert_eq!(ncch.header().program_id().unwrap().to_u64(), 0x0004000000123400);
+    }
+}
@@ -1,31 +1,115 @@
-use std::collections::HashMap;
+#[cfg(not(feature = "std"))]
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 use core::fmt;
 use core::marker::PhantomData;
 use core::mem;
 use core::num;
+use core::slice;
 use core::str::FromStr;
+#[cfg(not(feature = "std"))]
+use core::{
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+#[cfg(feature = "std")]
 use std::sync::OnceLock;
 
+use crate::hash::sha256;
 use crate::string::SizedCString;
+use crate::titleid::TitleId;
 use crate::{CytrynaError, CytrynaResult, FromBytes};
 
-use derive_more::{Display, Error, From};
+#[cfg(feature = "std")]
+use derive_more::Error;
+use derive_more::{Display, From};
+use hex_literal::hex;
+use rsa::{BigUint, Pkcs1v15Sign, RsaPublicKey};
 
 pub mod aes128_ctr {
     pub use aes::cipher::block_padding::NoPadding;
     pub use aes::cipher::BlockDecryptMut;
+    pub use aes::cipher::BlockEncryptMut;
     pub use aes::cipher::KeyIvInit;
     pub use aes::cipher::StreamCipher;
+    pub use aes::cipher::StreamCipherSeek;
     pub type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+    pub type Aes128CbcEnc = cbc::Encryptor<aes::Aes128>;
     pub type Aes128CtrDec = ctr::Ctr128BE<aes::Aes128>;
 }
 
+#[cfg(feature = "std")]
 static KEY_BAG: OnceLock<KeyBag> = OnceLock::new();
+#[cfg(not(feature = "std"))]
+static KEY_BAG: AtomicPtr<KeyBag> = AtomicPtr::new(ptr::null_mut());
+
+/// Retail common keyYs, used with slot 0x3D KeyX to derive common keys
+/// <https://www.3dbrew.org/wiki/AES_Registers#Keyslots>
+static RETAIL_COMMON_KEY_YS: [[u8; 0x10]; 6] = [
+    hex!("D07B337F9CA4385932A2E25723232EB9"),
+    hex!("0C767230F0998F1C46828202FAACBE4C"),
+    hex!("C475CB3AB8C788BB575E12A10907B8A4"),
+    hex!("E486EEE3D0C09C902F6686D4C06F649F"),
+    hex!("ED31BA9C04B067506C4497A35B7804FC"),
+    hex!("5E66998AB4E8931606850FD7A16DD755"),
+];
+
+/// Size of a full ARM9 bootrom dump
+const BOOT9_SIZE: usize = 0x10000;
+/// Size of the protected half of ARM9 bootrom, some dumps only contain that
+const BOOT9_PROT_SIZE: usize = 0x8000;
+/// Offset of keys bootrom initializes keyslots with, in a full dump
+const BOOT9_KEYS_OFFSET: usize = 0xd860;
+
+/// Keys stored by ARM9 bootrom, in order, as (type, first slot, amount of slots sharing it)
+/// <https://www.3dbrew.org/wiki/Bootrom#Keyslot_initialization>
+static BOOT9_KEYS: [(KeyType, u8, u8); 36] = [
+    (KeyType::X, 0x2c, 4),
+    (KeyType::X, 0x30, 4),
+    (KeyType::X, 0x34, 4),
+    (KeyType::X, 0x38, 4),
+    (KeyType::X, 0x3c, 1),
+    (KeyType::X, 0x3d, 1),
+    (KeyType::X, 0x3e, 1),
+    (KeyType::X, 0x3f, 1),
+    (KeyType::Y, 0x04, 1),
+    (KeyType::Y, 0x05, 1),
+    (KeyType::Y, 0x06, 1),
+    (KeyType::Y, 0x07, 1),
+    (KeyType::Y, 0x08, 1),
+    (KeyType::Y, 0x09, 1),
+    (KeyType::Y, 0x0a, 1),
+    (KeyType::Y, 0x0b, 1),
+    (KeyType::N, 0x0c, 4),
+    (KeyType::N, 0x10, 4),
+    (KeyType::N, 0x14, 1),
+    (KeyType::N, 0x15, 1),
+    (KeyType::N, 0x16, 1),
+    (KeyType::N, 0x17, 1),
+    (KeyType::N, 0x18, 4),
+    (KeyType::N, 0x1c, 4),
+    (KeyType::N, 0x20, 4),
+    (KeyType::N, 0x24, 5),
+    (KeyType::N, 0x29, 1),
+    (KeyType::N, 0x2a, 1),
+    (KeyType::N, 0x2b, 1),
+    (KeyType::N, 0x2c, 4),
+    (KeyType::N, 0x30, 4),
+    (KeyType::N, 0x34, 4),
+    (KeyType::N, 0x38, 5),
+    (KeyType::N, 0x3d, 1),
+    (KeyType::N, 0x3e, 1),
+    (KeyType::N, 0x3f, 1),
+];
 
 /// Contains keys used for encrypting/decrypting data
 #[derive(Clone, Debug)]
 pub struct KeyBag {
-    keys: HashMap<KeyIndex, [u8; 0x10]>,
+    keys: BTreeMap<KeyIndex, [u8; 0x10]>,
+    seeds: SeedDb,
 }
 
 impl Default for KeyBag {
@@ -39,7 +123,8 @@ impl KeyBag {
     #[must_use]
     pub fn new() -> Self {
         Self {
-            keys: HashMap::new(),
+            keys: BTreeMap::new(),
+            seeds: SeedDb::default(),
         }
     }
     /// Makes an instance of KeyBag from a string in format compatible with
@@ -71,37 +156,212 @@ impl KeyBag {
         }
         Ok(this)
     }
+    /// Makes an instance of KeyBag from keys ARM9 bootrom initializes keyslots with, accepts
+    /// both full dumps and dumps of only the protected half.
+    ///
+    /// Bootrom doesn't contain the generator or common keyYs, and keyslots set up later by
+    /// other firmware (like 0x18, 0x1B and 0x25 KeyX) aren't there either
+    pub fn from_boot9(data: &[u8]) -> CytrynaResult<Self> {
+        let offset = match data.len() {
+            BOOT9_SIZE => BOOT9_KEYS_OFFSET,
+            BOOT9_PROT_SIZE => BOOT9_KEYS_OFFSET - (BOOT9_SIZE - BOOT9_PROT_SIZE),
+            len => {
+                return Err(CytrynaError::InvalidLength {
+                    what: "boot9",
+                    actual: len,
+                    expected: BOOT9_SIZE,
+                })
+            }
+        };
+
+        let mut this = Self::new();
+        let keys = data[offset..].chunks_exact(0x10);
+        for ((ty, first, count), key) in BOOT9_KEYS.iter().zip(keys) {
+            for slot in *first..*first + *count {
+                this.set_key(KeyIndex::Slot(slot, ty.clone()), key.try_into().unwrap());
+            }
+        }
+        Ok(this)
+    }
     /// Adds a key to KeyBag, overwriting previous data if there was any
     pub fn set_key(&mut self, idx: KeyIndex, key: [u8; 0x10]) {
-        self.keys.insert(idx, key);
+        match idx {
+            KeyIndex::Seed(title_id) => self.seeds.seeds.insert(title_id, key),
+            _ => self.keys.insert(idx, key),
+        };
+    }
+    /// Adds the publicly known retail common keyYs, so that only slot 0x3D KeyX is needed to
+    /// derive common keys
+    pub fn with_retail_common_key_ys(&mut self) -> &mut Self {
+        for (i, key) in RETAIL_COMMON_KEY_YS.iter().enumerate() {
+            self.set_key(KeyIndex::Common(i as u8), *key);
+        }
+        self
+    }
+    /// Returns the normal-key of a given common key, deriving it from slot 0x3D KeyX and the
+    /// common keyY if it isn't in this KeyBag
+    pub fn common_key(&self, idx: u8) -> CytrynaResult<[u8; 0x10]> {
+        if let Ok(key) = self.get_key(KeyIndex::CommonN(idx)) {
+            return Ok(*key);
+        }
+        let y = self
+            .get_key(KeyIndex::Common(idx))
+            .map_err(|_| CytrynaError::MissingKey(KeyIndex::CommonN(idx)))?;
+        let x = self.get_key(KeyIndex::Slot(0x3d, KeyType::X))?;
+        let gen = self.get_key(KeyIndex::Generator)?;
+        Ok(scramble(*x, *y, *gen))
+    }
+    /// Adds title seeds used by NCCHs with seed crypto, seeds present in both are taken from
+    /// `seeds`
+    pub fn with_seed_db(&mut self, seeds: SeedDb) -> &mut Self {
+        self.seeds.seeds.extend(seeds.seeds);
+        self
+    }
+    /// Returns the seed of a given title, if this KeyBag contains one
+    #[must_use]
+    pub fn seed_for(&self, title_id: TitleId) -> Option<[u8; 0x10]> {
+        self.seeds.seed_for(title_id)
+    }
+    /// Adds all keys from another KeyBag, keys present in both are taken from `other`
+    pub fn merge(&mut self, other: KeyBag) {
+        self.keys.extend(other.keys);
+        self.with_seed_db(other.seeds);
+    }
+    /// Checks if a key is contained in this KeyBag
+    #[must_use]
+    pub fn contains(&self, idx: &KeyIndex) -> bool {
+        match idx {
+            KeyIndex::Seed(title_id) => self.seeds.seeds.contains_key(title_id),
+            _ => self.keys.contains_key(idx),
+        }
+    }
+    /// Returns an iterator over indices of keys contained in this KeyBag, including seeds
+    pub fn indices(&self) -> impl Iterator<Item = KeyIndex> + '_ {
+        let seeds = self.seeds.seeds.keys().map(|title_id| KeyIndex::Seed(*title_id));
+        self.keys.keys().cloned().chain(seeds)
     }
     /// Sets the KeyBag to be used for all crypto functions of this crate
+    ///
+    /// Only the first call has any effect
     pub fn finalize(self) {
+        #[cfg(feature = "std")]
         let _ = KEY_BAG.set(self);
+        #[cfg(not(feature = "std"))]
+        {
+            let bag = Box::into_raw(Box::new(self));
+            let set = KEY_BAG.compare_exchange(
+                ptr::null_mut(),
+                bag,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            );
+            if set.is_err() {
+                // SAFETY: bag was just made by Box::into_raw and never got shared
+                drop(unsafe { Box::from_raw(bag) });
+            }
+        }
     }
     /// Returns a key if it is contained in global KeyBag instance
     pub fn get_key(&self, idx: KeyIndex) -> CytrynaResult<&[u8; 0x10]> {
-        self.keys.get(&idx).ok_or(CytrynaError::MissingKey(idx))
+        let key = match idx {
+            // seeds are kept apart, so they can be merged from seeddb.bin
+            KeyIndex::Seed(title_id) => self.seeds.seeds.get(&title_id),
+            _ => self.keys.get(&idx),
+        };
+        key.ok_or(CytrynaError::MissingKey(idx))
     }
     /// Returns reference to the global KeyBag instance
     pub fn global() -> CytrynaResult<&'static Self> {
-        KEY_BAG.get().ok_or(CytrynaError::NoKeyBag)
+        #[cfg(feature = "std")]
+        let bag = KEY_BAG.get();
+        // SAFETY: the pointer is either null or was leaked by finalize() and is never freed
+        #[cfg(not(feature = "std"))]
+        let bag = unsafe { KEY_BAG.load(Ordering::Acquire).as_ref() };
+        bag.ok_or(CytrynaError::NoKeyBag)
     }
 }
 
 /// Generates a normal-key from X and Y keys and a keygen constant
 pub fn keygen(x: [u8; 0x10], y: [u8; 0x10]) -> CytrynaResult<[u8; 0x10]> {
+    let gen = *KeyBag::global()?.get_key(KeyIndex::Generator)?;
+    Ok(scramble(x, y, gen))
+}
+
+fn scramble(x: [u8; 0x10], y: [u8; 0x10], gen: [u8; 0x10]) -> [u8; 0x10] {
     let x = u128::from_be_bytes(x);
     let y = u128::from_be_bytes(y);
-    let gen = u128::from_be_bytes(*KeyBag::global()?.get_key(KeyIndex::Generator)?);
+    let gen = u128::from_be_bytes(gen);
 
-    Ok(((x.rotate_left(2) ^ y).wrapping_add(gen))
+    ((x.rotate_left(2) ^ y).wrapping_add(gen))
         .rotate_right(41)
-        .to_be_bytes())
+        .to_be_bytes()
+}
+
+/// Per-title seeds used for deriving NCCH keyY of titles using seed crypto, as stored in
+/// seeddb.bin
+#[derive(Clone, Debug, Default)]
+pub struct SeedDb {
+    seeds: BTreeMap<u64, [u8; 0x10]>,
+}
+
+impl SeedDb {
+    /// Parses seeddb.bin, a 0x10 byte header with entry count followed by 0x20 byte entries
+    pub fn from_bytes(bytes: &[u8]) -> CytrynaResult<Self> {
+        let count = bytes.get(..4).ok_or(CytrynaError::SliceTooSmall)?;
+        let count = u32::from_le_bytes(count.try_into().unwrap()) as usize;
+        let entries = count
+            .checked_mul(0x20)
+            .and_then(|size| bytes.get(0x10..)?.get(..size))
+            .ok_or(CytrynaError::SliceTooSmall)?;
+
+        let seeds = entries
+            .chunks_exact(0x20)
+            .map(|entry| {
+                let title_id = u64::from_le_bytes(entry[..0x8].try_into().unwrap());
+                (title_id, entry[0x8..0x18].try_into().unwrap())
+            })
+            .collect();
+        Ok(Self { seeds })
+    }
+    /// Returns the seed of a given title, if there is one
+    #[must_use]
+    pub fn seed_for(&self, title_id: TitleId) -> Option<[u8; 0x10]> {
+        self.seeds.get(&title_id.to_u64()).copied()
+    }
+}
+
+/// Derives keyY of NCCH using seed crypto from the one stored in its header and title seed
+/// <https://www.3dbrew.org/wiki/NCCH#Encryption>
+#[must_use]
+pub fn seeded_key_y(key_y: [u8; 0x10], seed: [u8; 0x10]) -> [u8; 0x10] {
+    let mut data = [0u8; 0x20];
+    data[..0x10].copy_from_slice(&key_y);
+    data[0x10..].copy_from_slice(&seed);
+    sha256(&data)[..0x10].try_into().unwrap()
+}
+
+/// Type of NCCH region, as used in its AES-CTR initialization vector
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum NcchRegionType {
+    Exheader = 1,
+    ExeFs = 2,
+    RomFs = 3,
+}
+
+/// Returns the AES-CTR initialization vector of a NCCH region, made of big-endian program ID
+/// followed by region type
+/// <https://www.3dbrew.org/wiki/NCCH#Encryption>
+#[must_use]
+pub fn ncch_iv(program_id: u64, region: NcchRegionType) -> [u8; 0x10] {
+    let mut iv = [0u8; 0x10];
+    iv[..0x8].copy_from_slice(&program_id.to_be_bytes());
+    iv[0x8] = region as u8;
+    iv
 }
 
 /// Is this self-documenting? I think it is
-#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+#[derive(Debug, Clone, Eq, Hash, PartialEq, PartialOrd, Ord)]
 pub enum KeyIndex {
     /// The generator key
     Generator,
@@ -111,6 +371,8 @@ pub enum KeyIndex {
     Common(u8),
     /// Index for common normal-key used in Title Key decryption
     CommonN(u8),
+    /// Seed of a title with a given ID, used by NCCHs with seed crypto
+    Seed(u64),
 }
 
 impl fmt::Display for KeyIndex {
@@ -120,21 +382,23 @@ impl fmt::Display for KeyIndex {
             Self::Slot(num, ty) => format!("slot0x{num:X}Key{ty}"),
             Self::Common(num) => format!("common{num}"),
             Self::CommonN(num) => format!("common{num}N"),
+            Self::Seed(title_id) => format!("seed{title_id:016X}"),
         };
         f.write_str(&string)
     }
 }
 
 /// An error type for KeyIndex parsing
-#[derive(Debug, Display, Error, From)]
+#[derive(Debug, Display, From)]
+#[cfg_attr(feature = "std", derive(Error))]
 pub enum KeyIndexParseError {
     #[display(fmt = "Failed to parse a hex number")]
     NumberParseError(num::ParseIntError),
-    #[error(ignore)]
+    #[cfg_attr(feature = "std", error(ignore))]
     #[from(ignore)]
     #[display(fmt = "Invalid key type \"{_0}\"")]
     InvalidKeyType(String),
-    #[error(ignore)]
+    #[cfg_attr(feature = "std", error(ignore))]
     #[from(ignore)]
     #[display(fmt = "Invalid X/Y/N key type \"{_0}\"")]
     InvalidKeyXYNType(String),
@@ -172,7 +436,7 @@ impl FromStr for KeyIndex {
 }
 
 /// Type of a 3DS key
-#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+#[derive(Debug, Clone, Eq, Hash, PartialEq, PartialOrd, Ord)]
 pub enum KeyType {
     /// KeyX
     X,
@@ -268,6 +532,8 @@ impl<T: ?Sized + FromBytes + fmt::Debug> SignedData<'_, T> {
 
             T::bytes_ok(&bytes[offset..])?;
 
+            // DST length is the length of trailing data, not the whole slice
+            let bytes = &bytes[..bytes.len() - offset];
             match bytes[3] {
                 0x03 => Ok(SignedData::Rsa4096Sha256(mem::transmute(bytes))),
                 0x04 => Ok(SignedData::Rsa2048Sha256(mem::transmute(bytes))),
@@ -285,6 +551,110 @@ impl<T: ?Sized + FromBytes + fmt::Debug> SignedData<'_, T> {
             Self::EcdsaSha256(inner) => T::cast(&inner.data),
         }
     }
+    /// Returns name of the certificate this data is signed with, like "Root-CA00000003-XS0000000c"
+    #[must_use]
+    pub fn issuer(&self) -> &SizedCString<0x40> {
+        match self {
+            Self::Rsa4096Sha256(inner) => &inner.sig_issuer,
+            Self::Rsa2048Sha256(inner) => &inner.sig_issuer,
+            Self::EcdsaSha256(inner) => &inner.sig_issuer,
+        }
+    }
+    /// Returns raw signature bytes, excluding padding
+    #[must_use]
+    pub fn signature_bytes(&self) -> &[u8] {
+        match self {
+            Self::Rsa4096Sha256(inner) => &inner.signature.sig,
+            Self::Rsa2048Sha256(inner) => &inner.signature.sig,
+            Self::EcdsaSha256(inner) => &inner.signature.sig,
+        }
+    }
+}
+
+impl<T: ?Sized + FromBytes + SignedBody + fmt::Debug> SignedData<'_, T> {
+    /// Verifies the signature using modulus of issuer's RSA public key, public exponent is
+    /// assumed to be 65537 like in all 3DS certificates.
+    ///
+    /// Signature covers the issuer field and signed part of data that follows it
+    pub fn verify(&self, issuer_pubkey: &[u8]) -> CytrynaResult<()> {
+        let (issuer, data) = match self {
+            Self::Rsa4096Sha256(inner) => (&inner.sig_issuer, &inner.data),
+            Self::Rsa2048Sha256(inner) => (&inner.sig_issuer, &inner.data),
+            Self::EcdsaSha256(_) => {
+                return Err(CytrynaError::Unsupported("ECDSA signature verification"))
+            }
+        };
+        let signed_size = T::signed_size(data);
+        if signed_size > data.len() {
+            return Err(CytrynaError::SliceTooSmall);
+        }
+        // issuer is directly followed by data
+        let body = unsafe {
+            slice::from_raw_parts(issuer.data().as_ptr(), issuer.data().len() + signed_size)
+        };
+
+        let key = RsaPublicKey::new(
+            BigUint::from_bytes_be(issuer_pubkey),
+            BigUint::from(0x10001u32),
+        )
+        .map_err(|_| CytrynaError::InvalidLength {
+            what: "RSA public key",
+            actual: issuer_pubkey.len(),
+            expected: self.signature_bytes().len(),
+        })?;
+        key.verify(
+            Pkcs1v15Sign::new::<sha2::Sha256>(),
+            &sha256(body),
+            self.signature_bytes(),
+        )
+        .map_err(|_| CytrynaError::SignatureCorrupted)
+    }
+}
+
+/// Lays out signature type, signature, issuer and data the way [SignedData] expects them
+///
+/// Signature shorter than what the signature type uses is padded with zeroes. Fails if the
+/// signature is too long for its type or the issuer doesn't fit in 0x40 bytes
+pub fn build_envelope(
+    sig_type: SignatureType,
+    signature: &[u8],
+    issuer: &str,
+    data: &[u8],
+) -> CytrynaResult<Vec<u8>> {
+    let (sig_size, pad_size) = match sig_type {
+        SignatureType::Rsa4096Sha256 => (0x200, 0x3c),
+        SignatureType::Rsa2048Sha256 => (0x100, 0x3c),
+        SignatureType::EcdsaSha256 => (0x3c, 0x40),
+    };
+    if signature.len() > sig_size {
+        return Err(CytrynaError::InvalidLength {
+            what: "signature",
+            actual: signature.len(),
+            expected: sig_size,
+        });
+    }
+    if issuer.len() > 0x40 {
+        return Err(CytrynaError::InvalidLength {
+            what: "issuer",
+            actual: issuer.len(),
+            expected: 0x40,
+        });
+    }
+
+    let mut buf = Vec::with_capacity(0x4 + sig_size + pad_size + 0x40 + data.len());
+    buf.extend_from_slice(&(sig_type as u32).to_le_bytes());
+    buf.extend_from_slice(signature);
+    buf.resize(0x4 + sig_size + pad_size, 0);
+    buf.extend_from_slice(issuer.as_bytes());
+    buf.resize(0x4 + sig_size + pad_size + 0x40, 0);
+    buf.extend_from_slice(data);
+    Ok(buf)
+}
+
+/// Data that can be signed, as not all of it is covered by the signature
+pub trait SignedBody {
+    /// Returns how many bytes of data following the issuer are covered by the signature
+    fn signed_size(bytes: &[u8]) -> usize;
 }
 
 /// Stores signature type of TMD and Ticket structs in a little-endian way
@@ -330,15 +700,160 @@ mod sealed_impl {
 }
 
 #[cfg(test)]
-mod tests {
-    use super::{KeyBag, KeyIndex};
+pub(crate) mod tests {
+    use super::{KeyBag, KeyIndex, KeyType, SeedDb};
+    use crate::titleid::TitleId;
+    use crate::CytrynaError;
+
+    // https://www.random.org/cgi-bin/randbyte?nbytes=16&format=h
+    const RANDOM_GENERATOR: [u8; 0x10] = [
+        0x12, 0x59, 0x9a, 0x14, 0xff, 0x66, 0xda, 0x9f, 0x65, 0xc1, 0x3e, 0xad, 0x30, 0x50, 0x15,
+        0xc7,
+    ];
+    pub(crate) const TEST_SEED: [u8; 0x10] = [0x5e; 0x10];
+    /// Title using seed crypto, its seed is in the test KeyBag
+    pub(crate) const TEST_SEED_TITLE: u64 = 0x0004000000055e00;
+    pub(crate) const TEST_COMMON_KEY: [u8; 0x10] = [
+        0x3d, 0x8e, 0x01, 0x9a, 0x57, 0xc4, 0xf2, 0x6b, 0x90, 0x1e, 0x44, 0xa8, 0x7c, 0x25, 0xd9,
+        0x63,
+    ];
+
+    /// Sets up the global KeyBag used by all tests, as it can only be set once
+    pub(crate) fn test_keybag() {
+        let mut bag = KeyBag::new();
+        bag.set_key(KeyIndex::Generator, RANDOM_GENERATOR);
+        bag.set_key(KeyIndex::CommonN(0), TEST_COMMON_KEY);
+        // common key 1 is only derivable from its keyY
+        bag.set_key(KeyIndex::Slot(0x3d, KeyType::X), [0x3d; 0x10]);
+        bag.set_key(KeyIndex::Common(1), [0xc1; 0x10]);
+        bag.set_key(KeyIndex::Slot(0x2c, KeyType::X), [0x2c; 0x10]);
+        bag.set_key(KeyIndex::Slot(0x25, KeyType::X), [0x25; 0x10]);
+        bag.with_seed_db(SeedDb::from_bytes(&seeddb(TEST_SEED_TITLE, TEST_SEED)).unwrap());
+        bag.finalize();
+    }
+
+    fn seeddb(title_id: u64, seed: [u8; 0x10]) -> Vec<u8> {
+        let mut db = vec![0u8; 0x30];
+        db[..0x4].copy_from_slice(&1u32.to_le_bytes());
+        db[0x10..0x18].copy_from_slice(&title_id.to_le_bytes());
+        db[0x18..0x28].copy_from_slice(&seed);
+        db
+    }
+
+    #[test]
+    fn seed_lookup() {
+        let db = seeddb(TEST_SEED_TITLE, TEST_SEED);
+        let seeds = SeedDb::from_bytes(&db).unwrap();
+        let title_id = TitleId::from_u64(TEST_SEED_TITLE).unwrap();
+        assert_eq!(seeds.seed_for(title_id), Some(TEST_SEED));
+        let other = TitleId::from_u64(0x0004000000055f00).unwrap();
+        assert_eq!(seeds.seed_for(other), None);
+
+        // entry count says there's more than there is
+        assert!(SeedDb::from_bytes(&db[..0x2f]).is_err());
+        assert!(SeedDb::from_bytes(&[]).is_err());
+    }
+
+    #[test]
+    fn boot9_keys() {
+        let mut boot9 = vec![0u8; 0x10000];
+        boot9[0xd860..0xd870].copy_from_slice(&[0x2c; 0x10]);
+        boot9[0xd8e0..0xd8f0].copy_from_slice(&[0x04; 0x10]);
+        boot9[0xda90..0xdaa0].copy_from_slice(&[0x3f; 0x10]);
+
+        for data in [&boot9[..], &boot9[0x8000..]] {
+            let bag = KeyBag::from_boot9(data).unwrap();
+            assert_eq!(bag.get_key(KeyIndex::Slot(0x2c, KeyType::X)).unwrap(), &[0x2c; 0x10]);
+            assert_eq!(bag.get_key(KeyIndex::Slot(0x2f, KeyType::X)).unwrap(), &[0x2c; 0x10]);
+            assert_eq!(bag.get_key(KeyIndex::Slot(0x04, KeyType::Y)).unwrap(), &[0x04; 0x10]);
+            assert_eq!(bag.get_key(KeyIndex::Slot(0x3f, KeyType::N)).unwrap(), &[0x3f; 0x10]);
+            assert!(bag.get_key(KeyIndex::Slot(0x25, KeyType::X)).is_err());
+        }
+        assert!(KeyBag::from_boot9(&boot9[..0x1000]).is_err());
+    }
+
+    #[test]
+    fn default_bag_is_empty() {
+        let bag: KeyBag = Default::default();
+        assert!(bag.get_key(KeyIndex::Generator).is_err());
+    }
+
+    #[test]
+    fn seeds_in_bag() {
+        let mut bag = KeyBag::new();
+        bag.set_key(KeyIndex::Generator, [0x11; 0x10]);
+        bag.set_key(KeyIndex::Seed(TEST_SEED_TITLE), TEST_SEED);
+        assert!(bag.contains(&KeyIndex::Seed(TEST_SEED_TITLE)));
+        assert!(!bag.contains(&KeyIndex::Seed(0x0004000000123400)));
+        assert_eq!(bag.get_key(KeyIndex::Seed(TEST_SEED_TITLE)).unwrap(), &TEST_SEED);
+        assert_eq!(
+            bag.indices().collect::<Vec<_>>(),
+            [KeyIndex::Generator, KeyIndex::Seed(TEST_SEED_TITLE)]
+        );
+    }
+
+    #[test]
+    fn derived_common_key() {
+        let mut bag = KeyBag::new();
+        bag.set_key(KeyIndex::Generator, RANDOM_GENERATOR);
+        bag.set_key(KeyIndex::Common(2), [0xc2; 0x10]);
+        assert!(matches!(
+            bag.common_key(2),
+            Err(CytrynaError::MissingKey(KeyIndex::Slot(0x3d, KeyType::X)))
+        ));
+        assert!(matches!(
+            bag.common_key(3),
+            Err(CytrynaError::MissingKey(KeyIndex::CommonN(3)))
+        ));
+
+        bag.set_key(KeyIndex::Slot(0x3d, KeyType::X), [0x3d; 0x10]);
+        let derived = bag.common_key(2).unwrap();
+        assert_ne!(derived, [0xc2; 0x10]);
+        assert!(!bag.contains(&KeyIndex::CommonN(2)));
+
+        bag.set_key(KeyIndex::CommonN(2), TEST_COMMON_KEY);
+        assert_eq!(bag.common_key(2).unwrap(), TEST_COMMON_KEY);
+    }
+
+    #[test]
+    fn merge_bags() {
+        let mut first = KeyBag::new();
+        first.set_key(KeyIndex::Generator, [0x11; 0x10]);
+        first.set_key(KeyIndex::Common(0), [0x22; 0x10]);
+        let mut second = KeyBag::new();
+        second.set_key(KeyIndex::Common(0), [0x33; 0x10]);
+        second.set_key(KeyIndex::CommonN(1), [0x44; 0x10]);
+
+        first.merge(second);
+        let mut indices: Vec<_> = first.indices().map(|idx| idx.to_string()).collect();
+        indices.sort();
+        assert_eq!(indices, ["common0", "common1N", "generator"]);
+        assert!(first.contains(&KeyIndex::CommonN(1)));
+        assert!(!first.contains(&KeyIndex::CommonN(0)));
+        assert_eq!(first.get_key(KeyIndex::Generator).unwrap(), &[0x11; 0x10]);
+        assert_eq!(first.get_key(KeyIndex::Common(0)).unwrap(), &[0x33; 0x10]);
+    }
+
+    #[test]
+    fn ncch_ivs() {
+        use super::{ncch_iv, NcchRegionType};
+
+        let program_id = 0x0004000000123400;
+        let prefix = [0x00, 0x04, 0x00, 0x00, 0x00, 0x12, 0x34, 0x00];
+        for (region, ty) in [
+            (NcchRegionType::Exheader, 1),
+            (NcchRegionType::ExeFs, 2),
+            (NcchRegionType::RomFs, 3),
+        ] {
+            let iv = ncch_iv(program_id, region);
+            assert_eq!(iv[..0x8], prefix);
+            assert_eq!(iv[0x8], ty);
+            assert_eq!(iv[0x9..], [0u8; 7]);
+        }
+    }
+
     #[test]
     fn test_keygen() {
-        // https://www.random.org/cgi-bin/randbyte?nbytes=16&format=h
-        const RANDOM_GENERATOR: [u8; 0x10] = [
-            0x12, 0x59, 0x9a, 0x14, 0xff, 0x66, 0xda, 0x9f, 0x65, 0xc1, 0x3e, 0xad, 0x30, 0x50,
-            0x15, 0xc7,
-        ];
         const RANDOM_X: [u8; 0x10] = [
             0xfa, 0xfe, 0x20, 0x7b, 0xb2, 0x3c, 0xa4, 0x30, 0x16, 0x2a, 0x65, 0xf6, 0xd3, 0xff,
             0x50, 0x40,
@@ -352,10 +867,57 @@ mod tests {
             0xb6, 0xe6,
         ];
 
-        let mut bag = KeyBag::new();
-        bag.set_key(KeyIndex::Generator, RANDOM_GENERATOR);
-        bag.finalize();
+        test_keybag();
 
         assert_eq!(super::keygen(RANDOM_X, RANDOM_Y).unwrap(), REFERENCE_KEY);
     }
+
+    #[cfg(feature = "cia")]
+    #[test]
+    fn envelope_read_back() {
+        use super::{build_envelope, SignatureType, SignedData};
+        use crate::ticket::TicketInner;
+
+        let mut data = [0u8; 0x124];
+        data[0x71] = 0x2;
+        for sig_type in [
+            SignatureType::Rsa4096Sha256,
+            SignatureType::Rsa2048Sha256,
+            SignatureType::EcdsaSha256,
+        ] {
+            let bytes =
+                build_envelope(sig_type, &[0xaa; 0x3c], "Root-CA00000003-XS0000000c", &data)
+                    .unwrap();
+            let signed = SignedData::<TicketInner>::from_bytes(&bytes).unwrap();
+            assert_eq!(signed.signature_bytes()[..0x3c], [0xaa; 0x3c]);
+            assert!(signed.signature_bytes()[0x3c..].iter().all(|&b| b == 0));
+            assert_eq!(signed.key_index(), 0x2);
+        }
+    }
+
+    #[test]
+    fn envelope_issuer_too_long() {
+        use super::{build_envelope, SignatureType};
+        use crate::CytrynaError;
+
+        let issuer = "Root-".repeat(0x10);
+        let ret = build_envelope(SignatureType::Rsa2048Sha256, &[], &issuer, &[]);
+        assert!(matches!(
+            ret,
+            Err(CytrynaError::InvalidLength { what: "issuer", actual: 0x50, expected: 0x40 })
+        ));
+        let ret = build_envelope(SignatureType::EcdsaSha256, &[0; 0x100], "Root", &[]);
+        assert!(matches!(ret, Err(CytrynaError::InvalidLength { what: "signature", .. })));
+    }
+
+    #[test]
+    fn retail_common_key_ys() {
+        let mut bag = KeyBag::new();
+        bag.with_retail_common_key_ys();
+        for i in 0..6 {
+            assert!(bag.get_key(KeyIndex::Common(i)).is_ok());
+        }
+        assert!(bag.get_key(KeyIndex::Common(6)).is_err());
+        assert_eq!(bag.get_key(KeyIndex::Common(1)).unwrap()[..2], [0x0c, 0x76]);
+    }
 }
@@ -1,12 +1,19 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 use core::mem;
+use core::slice;
 
 use crate::hash::sha256;
 use crate::string::SizedCString;
-use crate::FromBytes;
+use crate::{FromBytes, Verify};
 use crate::{align_up, CytrynaError, CytrynaResult};
 
-use derive_more::{Display, Error};
+use derive_more::Display;
+#[cfg(feature = "std")]
+use derive_more::Error;
 use hex_literal::hex;
+use memoffset::offset_of;
+use rsa::{BigUint, Pkcs1v15Sign, RsaPublicKey};
 use static_assertions::assert_eq_size;
 
 // source: https://gist.github.com/SciresM/cdd2266efb80175d37eabbe86f9d8c52
@@ -61,6 +68,20 @@ impl FirmHeader {
     pub fn sig(&self) -> &[u8; 0x100] {
         &self.rsa2048_sig
     }
+    /// Serializes the header field by field, as it is stored in FIRM file
+    fn to_bytes(&self) -> [u8; 0x200] {
+        let mut buf = [0u8; 0x200];
+        buf[0x0..0x4].copy_from_slice(self.magic.data());
+        buf[0x4..0x8].copy_from_slice(&self.boot_priority.to_le_bytes());
+        buf[0x8..0xc].copy_from_slice(&self.arm11_entrypoint.to_le_bytes());
+        buf[0xc..0x10].copy_from_slice(&self.arm9_entrypoint.to_le_bytes());
+        buf[0x10..0x40].copy_from_slice(&self._reserved);
+        for (i, section) in self.firmware_section_headers.iter().enumerate() {
+            buf[0x40 + i * 0x30..][..0x30].copy_from_slice(&section.to_bytes());
+        }
+        buf[0x100..].copy_from_slice(&self.rsa2048_sig);
+        buf
+    }
 }
 
 /// FIRM Section Header
@@ -107,6 +128,16 @@ impl SectionHeader {
     pub fn hash(&self) -> &[u8; 0x20] {
         &self.hash
     }
+    /// Serializes the header field by field, as it is stored in FIRM header
+    fn to_bytes(&self) -> [u8; 0x30] {
+        let mut buf = [0u8; 0x30];
+        buf[0x0..0x4].copy_from_slice(&self.offset.to_le_bytes());
+        buf[0x4..0x8].copy_from_slice(&self.phys_addr.to_le_bytes());
+        buf[0x8..0xc].copy_from_slice(&self.size.to_le_bytes());
+        buf[0xc..0x10].copy_from_slice(&(self.copy_method as u32).to_le_bytes());
+        buf[0x10..].copy_from_slice(&self.hash);
+        buf
+    }
 }
 
 /// Contains copy method of a section
@@ -122,7 +153,8 @@ pub enum CopyMethod {
 }
 
 /// An error type for FirmBuilder
-#[derive(Debug, Error, Display)]
+#[derive(Debug, Display)]
+#[cfg_attr(feature = "std", derive(Error))]
 pub enum FirmBuilderError {
     #[display(fmt = "Tried to add more than firware 4 sections")]
     TooManySections,
@@ -130,10 +162,22 @@ pub enum FirmBuilderError {
     NoArm9Entry,
     #[display(fmt = "Arm11 entry point is missing")]
     NoArm11Entry,
+    #[display(fmt = "Entry point is set to 0")]
+    ZeroEntrypoint,
     #[display(fmt = "Firmware sections are missing")]
     NoSections,
     #[display(fmt = "Signature type is missing")]
     NoSig,
+    #[cfg(feature = "std")]
+    #[display(fmt = "I/O error: {_0}")]
+    Io(std::io::Error),
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for FirmBuilderError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
 }
 
 /// Contains signature data or sighax signature type
@@ -198,20 +242,19 @@ impl FirmBuilder {
         self.fw_sections[which] = Some(section);
         self
     }
-    /// Builds the FIRM
-    pub fn build(&mut self) -> Result<Vec<u8>, FirmBuilderError> {
+    /// Checks if all required fields are set, and lays out section data, returning FIRM header
+    /// and sections padded to their size in FIRM file
+    fn layout(&mut self) -> Result<(FirmHeader, Vec<Vec<u8>>), FirmBuilderError> {
         let arm11_entrypoint = self
             .arm11_entrypoint
             .ok_or(FirmBuilderError::NoArm11Entry)?;
         let arm9_entrypoint = self.arm9_entrypoint.ok_or(FirmBuilderError::NoArm9Entry)?;
-        let file_size = self
-            .fw_sections
-            .iter()
-            .flatten()
-            .map(|s| s.data.len())
-            .reduce(|acc, size| acc + size)
-            .ok_or(FirmBuilderError::NoSections)?
-            + mem::size_of::<FirmHeader>();
+        if arm11_entrypoint == 0 || arm9_entrypoint == 0 {
+            return Err(FirmBuilderError::ZeroEntrypoint);
+        }
+        if self.fw_sections.iter().all(Option::is_none) {
+            return Err(FirmBuilderError::NoSections);
+        }
 
         let sig = match self.signature.take().ok_or(FirmBuilderError::NoSig)? {
             FirmSignature::RetailSighaxNand => RETAIL_NAND_FIRM,
@@ -235,9 +278,7 @@ impl FirmBuilder {
             rsa2048_sig: sig,
         };
 
-        let mut buf = Vec::with_capacity(file_size);
-        buf.resize(0x200, 0);
-
+        let mut sections = Vec::new();
         let mut offset = 0x200;
         for (i, mut s) in self.fw_sections.clone().into_iter().flatten().enumerate() {
             // https://github.com/derrekr/ctr_firm_builder aligns to 0x200
@@ -254,23 +295,45 @@ impl FirmBuilder {
             };
             offset += size;
             header.firmware_section_headers[i] = hdr;
-            buf.extend(s.data);
+            sections.push(s.data);
         }
 
-        unsafe {
-            let vec_ptr = buf.as_mut_ptr();
-            let header_ptr = &header as *const FirmHeader as *const u8;
-            vec_ptr.copy_from_nonoverlapping(header_ptr, mem::size_of::<FirmHeader>());
-        }
+        Ok((header, sections))
+    }
+    /// Builds the FIRM
+    pub fn build(&mut self) -> Result<Vec<u8>, FirmBuilderError> {
+        let (header, sections) = self.layout()?;
+        let file_size = mem::size_of::<FirmHeader>() + sections.iter().map(Vec::len).sum::<usize>();
 
+        let mut buf = Vec::with_capacity(file_size);
+        buf.extend_from_slice(&header.to_bytes());
+        for section in sections {
+            buf.extend(section);
+        }
         Ok(buf)
     }
+    /// Builds the FIRM into a writer, without holding the whole file in memory
+    #[cfg(feature = "std")]
+    pub fn build_to(&mut self, w: &mut impl std::io::Write) -> Result<(), FirmBuilderError> {
+        let (header, sections) = self.layout()?;
+        w.write_all(&header.to_bytes())?;
+        for section in &sections {
+            w.write_all(section)?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{FromBytes};
-    use super::{Firm, FirmwareSection, FirmSignature};
+    use crate::{CytrynaError, FromBytes};
+    use crate::tests::{verify_any, TEST_MODULUS};
+    use super::{CopyMethod, Firm, FirmBuilderError, FirmwareSection, FirmSignature};
+
+    use hex_literal::hex;
+
+    // signed with the private key of TEST_MODULUS, covers the header of `signed_firm()`
+    static TEST_SIGNATURE: [u8; 0x100] = hex!("45de8005582a3b118ecf164aada1c4184c85a1a36f13516bb0bc9962a7ff003fc273829ec4bc8d537e55c2e45f380c21128eeda469cf26ae3b75794b63ad27d46af994012854ac07e419260668f914ace790803bbbf5616bd06630c7db178c864f6570e35ff7e21a0b9d9b05ba4209518a86188b7b20b40aac33dd9332ce9ef4b01a9859f2cc9ac29da5d169f2d5fd024f07a9c6dce761ace5d99304f517ed1e17430202f54b1e420669c269604274c350542d40eaee6650e06f2d43d6292607de95bae14e0526d036cb7af395002d1f7906cf5effe1a910668beff29f7681bb9fa6a2b50544be85b4de7e49571a30ab5837a0cfb20f7bebe142858c96a58ae1");
     #[test]
     fn test_firm_building() {
         let input = include_bytes!("../testdata/fastboot3DS.firm");
@@ -298,6 +361,117 @@ mod tests {
         let firm = firm_builder.build().unwrap();
 
         assert!(Firm::from_bytes(&firm).is_ok());
+
+        // signature is taken by build(), so set it again
+        firm_builder.signature(FirmSignature::Custom(Box::new(*hdr.sig())));
+        let mut written = Vec::new();
+        firm_builder.build_to(&mut written).unwrap();
+        assert_eq!(written, firm);
+        // field-by-field serialization matches in-memory layout the header is cast from
+        assert_eq!(hdr.to_bytes(), input[..0x200]);
+    }
+
+    fn signed_firm() -> Vec<u8> {
+        let mut firm = Firm::builder();
+        firm.arm11_entrypoint(0x1ff80000)
+            .arm9_entrypoint(0x08006000)
+            .signature(FirmSignature::Custom(Box::new(TEST_SIGNATURE)))
+            .add_fw_section(FirmwareSection::new(vec![0x11; 0x200], 0x08006000, CopyMethod::Ndma))
+            .unwrap();
+        firm.build().unwrap()
+    }
+
+    #[test]
+    fn verify_trait() {
+        let mut firm = signed_firm();
+        assert!(verify_any(Firm::from_bytes(&firm).unwrap()).is_ok());
+
+        // from_bytes already rejects bad section hashes, so skip it
+        let offset = Firm::cast(&firm).header().section_iter().next().unwrap().offset;
+        firm[offset as usize] ^= 0x1;
+        assert!(matches!(verify_any(Firm::cast(&firm)), Err(CytrynaError::InvalidHash)));
+    }
+
+    #[test]
+    fn verify_custom_signature() {
+        let mut firm = signed_firm();
+        assert!(Firm::from_bytes(&firm).unwrap().verify_signature(&TEST_MODULUS).unwrap());
+        assert!(!Firm::from_bytes(&firm).unwrap().is_sighax());
+
+        firm[0x4] ^= 0x1;
+        assert!(!Firm::from_bytes(&firm).unwrap().verify_signature(&TEST_MODULUS).unwrap());
+    }
+
+    #[test]
+    fn zero_entrypoint() {
+        let mut firm = Firm::builder();
+        firm.arm9_entrypoint(0x08006000)
+            .signature(FirmSignature::RetailSighaxNand)
+            .add_fw_section(FirmwareSection::new(vec![0x11; 0x200], 0x08006000, CopyMethod::Ndma))
+            .unwrap();
+        assert!(matches!(firm.build(), Err(FirmBuilderError::NoArm11Entry)));
+
+        firm.arm11_entrypoint(0);
+        assert!(matches!(firm.build(), Err(FirmBuilderError::ZeroEntrypoint)));
+
+        firm.arm11_entrypoint(0x1ff80000);
+        assert!(firm.build().is_ok());
+    }
+
+    #[test]
+    fn too_small() {
+        assert!(matches!(Firm::from_bytes(b"FIR"), Err(CytrynaError::SliceTooSmall)));
+    }
+
+    #[test]
+    fn sighax_detection() {
+        // fastboot3DS ships with a signature other than the known retail ones
+        let input = include_bytes!("../testdata/fastboot3DS.firm");
+        assert!(!Firm::from_bytes(input).unwrap().is_sighax());
+
+        let mut firm = Firm::builder();
+        firm.arm11_entrypoint(0x1ff80000)
+            .arm9_entrypoint(0x08006000)
+            .signature(FirmSignature::RetailSighaxNand)
+            .add_fw_section(FirmwareSection::new(vec![0x11; 0x200], 0x08006000, CopyMethod::Ndma))
+            .unwrap();
+        let firm = firm.build().unwrap();
+        let firm = Firm::from_bytes(&firm).unwrap();
+        assert!(firm.is_sighax());
+        assert!(!firm.verify_signature(&TEST_MODULUS).unwrap());
+    }
+
+    #[test]
+    fn signature_kind() {
+        let kind = |signature| {
+            let mut firm = Firm::builder();
+            firm.arm11_entrypoint(0x1ff80000)
+                .arm9_entrypoint(0x08006000)
+                .signature(signature)
+                .add_fw_section(FirmwareSection::new(
+                    vec![0x11; 0x200],
+                    0x08006000,
+                    CopyMethod::Ndma,
+                ))
+                .unwrap();
+            let firm = firm.build().unwrap();
+            Firm::from_bytes(&firm).unwrap().detect_signature_kind()
+        };
+
+        assert!(matches!(
+            kind(FirmSignature::RetailSighaxNand),
+            Some(FirmSignature::RetailSighaxNand)
+        ));
+        assert!(matches!(
+            kind(FirmSignature::RetailSighaxNtr),
+            Some(FirmSignature::RetailSighaxNtr)
+        ));
+        // indistinguishable from ntrboot
+        assert!(matches!(
+            kind(FirmSignature::RetailSighaxSpi),
+            Some(FirmSignature::RetailSighaxNtr)
+        ));
+        assert!(kind(FirmSignature::Custom(Box::new([0x5a; 0x100]))).is_none());
     }
 }
 
@@ -352,6 +526,18 @@ impl FromBytes for Firm {
     }
 }
 
+impl Verify for Firm {
+    /// Checks hashes of all firmware sections, the signature needs a modulus supplied by the user
+    /// so it's not checked here, see [Firm::verify_signature]
+    fn verify(&self) -> CytrynaResult<()> {
+        if self.hash_ok() {
+            Ok(())
+        } else {
+            Err(CytrynaError::InvalidHash)
+        }
+    }
+}
+
 impl Firm {
     /// Returns the builder for creating FIRM files
     #[must_use]
@@ -375,4 +561,51 @@ impl Firm {
     pub fn header(&self) -> &FirmHeader {
         &self.header
     }
+    /// Verifies the header signature using modulus of the FIRM public key, public exponent is
+    /// assumed to be 65537.
+    ///
+    /// The modulus is not embedded, like keys in KeyBag it has to be supplied by the user.
+    /// Signature covers the header data preceding it, sighaxed signatures never pass
+    pub fn verify_signature(&self, modulus: &[u8]) -> CytrynaResult<bool> {
+        let signed = unsafe {
+            slice::from_raw_parts(
+                &self.header as *const FirmHeader as *const u8,
+                offset_of!(FirmHeader, rsa2048_sig),
+            )
+        };
+        let key = RsaPublicKey::new(BigUint::from_bytes_be(modulus), BigUint::from(0x10001u32))
+            .map_err(|_| CytrynaError::InvalidLength {
+                what: "RSA public key",
+                actual: modulus.len(),
+                expected: 0x100,
+            })?;
+        Ok(key
+            .verify(
+                Pkcs1v15Sign::new::<sha2::Sha256>(),
+                &sha256(signed),
+                &self.header.rsa2048_sig,
+            )
+            .is_ok())
+    }
+    /// Checks if the signature is one of known sighaxed retail signatures
+    #[must_use]
+    pub fn is_sighax(&self) -> bool {
+        self.detect_signature_kind().is_some()
+    }
+    /// Returns which known sighaxed retail signature this FIRM has, or None if it's not one of
+    /// them.
+    ///
+    /// ntrboot and SPI-boot use the same signature, so both are reported as
+    /// [FirmSignature::RetailSighaxNtr]
+    #[must_use]
+    pub fn detect_signature_kind(&self) -> Option<FirmSignature> {
+        let sig = self.header.sig();
+        if sig == &RETAIL_NAND_FIRM {
+            Some(FirmSignature::RetailSighaxNand)
+        } else if sig == &RETAIL_NTR_FIRM {
+            Some(FirmSignature::RetailSighaxNtr)
+        } else {
+            None
+        }
+    }
 }
@@ -1,10 +1,25 @@
+use alloc::vec;
+use alloc::vec::Vec;
 use core::mem;
 
 use crate::{CytrynaResult, CytrynaError, FromBytes};
+#[cfg(feature = "smdh")]
+use crate::smdh::{Language, Smdh};
 use crate::string::SizedCString;
 
+use bitflags::bitflags;
+use derive_more::Display;
+#[cfg(feature = "std")]
+use derive_more::Error;
 use static_assertions::assert_eq_size;
 
+/// The only 3DSX format version in use
+const FORMAT_VERSION: u32 = 0;
+/// Alignment of segments in memory after loading
+const PAGE_SIZE: u32 = 0x1000;
+
+/// 3DSX homebrew executable
+/// <https://www.3dbrew.org/wiki/3DSX_Format>
 #[repr(C)]
 pub struct Hb3dsx {
     header: Hb3dsxHeader,
@@ -17,17 +32,47 @@ impl FromBytes for Hb3dsx {
     }
     fn bytes_ok(bytes: &[u8]) -> CytrynaResult<()> {
         if [bytes[0], bytes[1], bytes[2], bytes[3]] != *b"3DSX" {
-            Err(CytrynaError::InvalidMagic)
-        } else {
-            Ok(())
+            return Err(CytrynaError::InvalidMagic);
+        }
+        let format_version = u32::from_le_bytes(bytes[0x8..0xc].try_into().unwrap());
+        if format_version != FORMAT_VERSION {
+            return Err(CytrynaError::UnsupportedHeaderVersion);
+        }
+        // either a bare header or one followed by the extended header
+        let header_size = u16::from_le_bytes([bytes[0x4], bytes[0x5]]) as usize;
+        if header_size != mem::size_of::<Hb3dsxHeader>()
+            && header_size != mem::size_of::<Hb3dsxHeader>() + mem::size_of::<Hb3dsxExheader>()
+        {
+            return Err(CytrynaError::InvalidHeaderSize);
+        }
+        // all three relocation headers follow the header
+        if bytes.len() < header_size + 3 * mem::size_of::<RelocationHeader>() {
+            return Err(CytrynaError::SliceTooSmall);
         }
+
+        Ok(())
     }
     fn cast(bytes: &[u8]) -> &Self {
-        unsafe { mem::transmute(bytes) }
+        // DST length is the length of trailing data, not the whole file
+        let data_len = bytes.len().saturating_sub(mem::size_of::<Hb3dsxHeader>());
+        unsafe { mem::transmute(&bytes[..data_len]) }
     }
 }
 
 impl Hb3dsx {
+    /// Returns the 3DSX builder
+    #[must_use]
+    pub fn builder() -> Hb3dsxBuilder {
+        Hb3dsxBuilder {
+            code: Vec::new(),
+            rodata: Vec::new(),
+            data: Vec::new(),
+            bss_size: 0,
+            relocations: Default::default(),
+            smdh: None,
+            romfs: None,
+        }
+    }
     pub fn header(&self) -> &Hb3dsxHeader {
         &self.header
     }
@@ -40,6 +85,49 @@ impl Hb3dsx {
             }
         }
     }
+    /// Returns the embedded SMDH, if there is one
+    #[cfg(feature = "smdh")]
+    pub fn smdh(&self) -> CytrynaResult<Option<&Smdh>> {
+        let Some(exheader) = self.exheader() else {
+            return Ok(None);
+        };
+        if exheader.smdh_size == 0 {
+            return Ok(None);
+        }
+
+        let offset = (exheader.smdh_offset as usize)
+            .checked_sub(mem::size_of::<Hb3dsxHeader>())
+            .ok_or(CytrynaError::InvalidRegionPosition)?;
+        let bytes = self
+            .data
+            .get(offset..)
+            .and_then(|d| d.get(..exheader.smdh_size as usize))
+            .ok_or(CytrynaError::SliceTooSmall)?;
+        if bytes.len() < mem::size_of::<Smdh>() {
+            return Err(CytrynaError::SliceTooSmall);
+        }
+        Smdh::from_bytes(bytes)
+            .map(Some)
+            .map_err(|e| e.with_context("smdh", exheader.smdh_offset as usize))
+    }
+    /// Returns the embedded RomFS image, if there is one
+    pub fn romfs(&self) -> Option<&[u8]> {
+        let exheader = self.exheader()?;
+        if exheader.romfs_offset == 0 {
+            return None;
+        }
+        let offset = (exheader.romfs_offset as usize)
+            .checked_sub(mem::size_of::<Hb3dsxHeader>())?;
+        self.data.get(offset..)
+    }
+    /// Returns an identifier for cataloging homebrew, made of publisher and short description
+    /// of the embedded SMDH, as 3DSX files have no title ID
+    #[cfg(feature = "smdh")]
+    pub fn catalog_id(&self) -> CytrynaResult<String> {
+        let smdh = self.smdh()?.ok_or(CytrynaError::MissingRegion)?;
+        let strings = smdh.title_strings(Language::English);
+        Ok(format!("{}/{}", strings.publisher, strings.short))
+    }
     unsafe fn reloc_header(&self, offset: usize) -> &RelocationHeader {
         &*self.data[offset..][..mem::size_of::<RelocationHeader>()].as_ptr().cast()
     }
@@ -52,15 +140,341 @@ impl Hb3dsx {
     pub fn data_reloc_header(&self) -> &RelocationHeader {
         unsafe { self.reloc_header(self.header.data_reloc_header_offset()) }
     }
-    pub fn code_reloc_iter(&self) -> impl Iterator<Item = (RelocationType, &Relocation)> {
+    fn reloc_iter<'a>(&'a self, hdr: &'a RelocationHeader, offset: usize) -> RelocationIter<'a> {
         RelocationIter {
-            hdr: self.code_reloc_header(),
+            hdr,
             offset_bytes: 0,
-            data: &self.data[self.header.code_reloc_table_offset()..][..self.code_reloc_header().table_size()],
+            data: &self.data[offset..][..hdr.table_size()],
+        }
+    }
+    fn rodata_reloc_table_offset(&self) -> usize {
+        self.header
+            .code_reloc_table_offset()
+            .saturating_add(self.code_reloc_header().table_size())
+    }
+    fn data_reloc_table_offset(&self) -> usize {
+        self.rodata_reloc_table_offset()
+            .saturating_add(self.rodata_reloc_header().table_size())
+    }
+    fn code_reloc_iter_inner(&self) -> RelocationIter<'_> {
+        self.reloc_iter(self.code_reloc_header(), self.header.code_reloc_table_offset())
+    }
+    fn rodata_reloc_iter_inner(&self) -> RelocationIter<'_> {
+        self.reloc_iter(self.rodata_reloc_header(), self.rodata_reloc_table_offset())
+    }
+    fn data_reloc_iter_inner(&self) -> RelocationIter<'_> {
+        self.reloc_iter(self.data_reloc_header(), self.data_reloc_table_offset())
+    }
+    pub fn code_reloc_iter(&self) -> impl Iterator<Item = (RelocationType, &Relocation)> {
+        self.code_reloc_iter_inner()
+    }
+    /// Returns an iterator over relocations of rodata segment
+    pub fn rodata_reloc_iter(&self) -> impl Iterator<Item = (RelocationType, &Relocation)> {
+        self.rodata_reloc_iter_inner()
+    }
+    /// Returns an iterator over relocations of data segment
+    pub fn data_reloc_iter(&self) -> impl Iterator<Item = (RelocationType, &Relocation)> {
+        self.data_reloc_iter_inner()
+    }
+    /// Returns an iterator over relocations of all segments, tagged with the segment they patch
+    pub fn all_relocations(&self) -> impl Iterator<Item = (Segment, RelocationType, &Relocation)> {
+        let code = self.code_reloc_iter_inner();
+        let rodata = self.rodata_reloc_iter_inner();
+        let data = self.data_reloc_iter_inner();
+        code.map(|(ty, reloc)| (Segment::Code, ty, reloc))
+            .chain(rodata.map(|(ty, reloc)| (Segment::Rodata, ty, reloc)))
+            .chain(data.map(|(ty, reloc)| (Segment::Data, ty, reloc)))
+    }
+    /// Loads the executable into a flat memory image starting at `base`, with all relocations
+    /// applied.
+    ///
+    /// Segments are placed like the homebrew loader places them: code at `base`, then rodata and
+    /// data, each starting at the next 0x1000-aligned address, with BSS zero-filled right after
+    /// data. Words in the file hold offsets into that same page-aligned layout, so they point at
+    /// the loaded image as they are
+    pub fn load_at(&self, base: u32) -> CytrynaResult<Vec<u8>> {
+        let code_size = self.header.code_segment_size;
+        let rodata_size = self.header.rodata_segment_size;
+        let data_size = self
+            .header
+            .data_bss_segment_size
+            .checked_sub(self.header.bss_segment_size)
+            .ok_or(CytrynaError::InvalidRegionPosition)?;
+        let segments_end = self.header.data_segment_offset() + data_size as usize;
+        if segments_end > self.data.len() {
+            return Err(CytrynaError::InvalidLength {
+                what: "3DSX segments",
+                actual: self.data.len(),
+                expected: segments_end,
+            });
+        }
+        let tables_end = self
+            .data_reloc_table_offset()
+            .saturating_add(self.data_reloc_header().table_size());
+        if tables_end > self.data.len() {
+            return Err(CytrynaError::InvalidLength {
+                what: "3DSX relocation tables",
+                actual: self.data.len(),
+                expected: tables_end,
+            });
+        }
+
+        let rodata_addr = code_size
+            .checked_next_multiple_of(PAGE_SIZE)
+            .ok_or(CytrynaError::InvalidRegionPosition)?;
+        let data_addr = rodata_size
+            .checked_next_multiple_of(PAGE_SIZE)
+            .and_then(|size| size.checked_add(rodata_addr))
+            .ok_or(CytrynaError::InvalidRegionPosition)?;
+        let image_size = data_addr
+            .checked_add(self.header.data_bss_segment_size)
+            .ok_or(CytrynaError::InvalidRegionPosition)?;
+
+        let mut image = vec![0u8; image_size as usize];
+        let segments = [
+            (0, self.code_segment(), self.code_reloc_iter_inner()),
+            (rodata_addr, self.rodata_segment(), self.rodata_reloc_iter_inner()),
+            (data_addr, self.data_segment(), self.data_reloc_iter_inner()),
+        ];
+        for (addr, data, _) in &segments {
+            image[*addr as usize..][..data.len()].copy_from_slice(data);
+        }
+
+        for (addr, data, relocations) in segments {
+            // each relocation type has its own table, starting from the start of segment
+            let mut pos = [0usize; 2];
+            for (ty, reloc) in relocations {
+                let pos = &mut pos[ty as usize];
+                *pos += reloc.skip as usize;
+                if (*pos + reloc.patch as usize) * 4 > data.len() {
+                    return Err(CytrynaError::InvalidRegionPosition);
+                }
+                for _ in 0..reloc.patch {
+                    let offset = addr as usize + *pos * 4;
+                    let word = &mut image[offset..][..4];
+                    let value = u32::from_le_bytes(word.try_into().unwrap());
+                    let reloc_base = match ty {
+                        RelocationType::Absolute => base,
+                        RelocationType::Relative => offset as u32,
+                    };
+                    word.copy_from_slice(&reloc.apply(value, reloc_base, ty).to_le_bytes());
+                    *pos += 1;
+                }
+            }
+        }
+
+        Ok(image)
+    }
+    /// Returns code segment data
+    #[must_use]
+    pub fn code_segment(&self) -> &[u8] {
+        &self.data[self.header.code_segment_offset()..][..self.header.code_segment_size as usize]
+    }
+    /// Returns rodata segment data
+    #[must_use]
+    pub fn rodata_segment(&self) -> &[u8] {
+        &self.data[self.header.rodata_segment_offset()..][..self.header.rodata_segment_size as usize]
+    }
+    /// Returns data segment data, excluding BSS
+    #[must_use]
+    pub fn data_segment(&self) -> &[u8] {
+        let size = self.header.data_bss_segment_size - self.header.bss_segment_size;
+        &self.data[self.header.data_segment_offset()..][..size as usize]
+    }
+}
+
+/// Segment of a 3DSX executable
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Segment {
+    Code = 0,
+    Rodata = 1,
+    Data = 2,
+}
+
+/// An error type for Hb3dsxBuilder
+#[derive(Debug, Display)]
+#[cfg_attr(feature = "std", derive(Error))]
+pub enum Hb3dsxBuilderError {
+    #[display(fmt = "Segment size is not a multiple of 4")]
+    UnalignedSegment,
+    #[display(fmt = "Relocation points outside of its segment")]
+    RelocationOutOfBounds,
+}
+
+/// 3DSX builder
+#[derive(Debug, Clone)]
+pub struct Hb3dsxBuilder {
+    code: Vec<u8>,
+    rodata: Vec<u8>,
+    data: Vec<u8>,
+    bss_size: u32,
+    // word indices to patch, indexed by segment and then by relocation type
+    relocations: [[Vec<u32>; 2]; 3],
+    smdh: Option<Vec<u8>>,
+    romfs: Option<Vec<u8>>,
+}
+
+impl Hb3dsxBuilder {
+    /// Sets the code segment data
+    pub fn with_code(&mut self, code: Vec<u8>) -> &mut Self {
+        self.code = code;
+        self
+    }
+    /// Sets the rodata segment data
+    pub fn with_rodata(&mut self, rodata: Vec<u8>) -> &mut Self {
+        self.rodata = rodata;
+        self
+    }
+    /// Sets the data segment data, excluding BSS
+    pub fn with_data(&mut self, data: Vec<u8>) -> &mut Self {
+        self.data = data;
+        self
+    }
+    /// Sets the BSS size, default is 0
+    pub fn with_bss_size(&mut self, size: u32) -> &mut Self {
+        self.bss_size = size;
+        self
+    }
+    /// Adds a relocation of a word, `word` is an index of a 32-bit word from the start of
+    /// a segment
+    pub fn add_relocation(&mut self, segment: Segment, ty: RelocationType, word: u32) -> &mut Self {
+        self.relocations[segment as usize][ty as usize].push(word);
+        self
+    }
+    /// Adds relocations from skip/patch relocation entries, like the ones returned by
+    /// [Hb3dsx::code_reloc_iter]
+    pub fn add_relocations<'a>(
+        &mut self,
+        segment: Segment,
+        relocations: impl IntoIterator<Item = (RelocationType, &'a Relocation)>,
+    ) -> &mut Self {
+        // each relocation type has its own table, starting from the start of segment
+        let mut pos = [0u32; 2];
+        for (ty, reloc) in relocations {
+            let pos = &mut pos[ty as usize];
+            *pos += reloc.skip as u32;
+            for word in *pos..*pos + reloc.patch as u32 {
+                self.add_relocation(segment, ty, word);
+            }
+            *pos += reloc.patch as u32;
         }
+        self
+    }
+    /// Sets the SMDH to embed in the extended header
+    #[cfg(feature = "smdh")]
+    pub fn with_smdh(&mut self, smdh: &Smdh) -> &mut Self {
+        self.smdh = Some(smdh.as_bytes().to_vec());
+        self
+    }
+    /// Sets the RomFS image to embed in the extended header
+    pub fn with_romfs(&mut self, romfs: Vec<u8>) -> &mut Self {
+        self.romfs = Some(romfs);
+        self
+    }
+    /// Builds the 3DSX
+    pub fn build(&mut self) -> Result<Vec<u8>, Hb3dsxBuilderError> {
+        let segments = [&self.code, &self.rodata, &self.data];
+        if segments.iter().any(|s| s.len() % 4 != 0) {
+            return Err(Hb3dsxBuilderError::UnalignedSegment);
+        }
+
+        let mut tables: [[Vec<Relocation>; 2]; 3] = Default::default();
+        for (i, segment) in segments.iter().enumerate() {
+            for (ty, words) in self.relocations[i].iter().enumerate() {
+                if words.iter().any(|w| *w as usize >= segment.len() / 4) {
+                    return Err(Hb3dsxBuilderError::RelocationOutOfBounds);
+                }
+                tables[i][ty] = encode_relocations(words.clone());
+            }
+        }
+
+        let has_exheader = self.smdh.is_some() || self.romfs.is_some();
+        let header_size = if has_exheader {
+            mem::size_of::<Hb3dsxHeader>() + mem::size_of::<Hb3dsxExheader>()
+        } else {
+            mem::size_of::<Hb3dsxHeader>()
+        };
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(b"3DSX");
+        buf.extend_from_slice(&(header_size as u16).to_le_bytes());
+        buf.extend_from_slice(&(mem::size_of::<RelocationHeader>() as u16).to_le_bytes());
+        buf.extend_from_slice(&0u32.to_le_bytes()); // format version
+        buf.extend_from_slice(&0u32.to_le_bytes()); // flags
+        buf.extend_from_slice(&(self.code.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&(self.rodata.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&(self.data.len() as u32 + self.bss_size).to_le_bytes());
+        buf.extend_from_slice(&self.bss_size.to_le_bytes());
+
+        // filled in after everything else is laid out
+        let exheader_offset = buf.len();
+        buf.resize(header_size, 0);
+
+        for [abs, rel] in tables.iter() {
+            buf.extend_from_slice(&(abs.len() as u32).to_le_bytes());
+            buf.extend_from_slice(&(rel.len() as u32).to_le_bytes());
+        }
+        for segment in segments {
+            buf.extend_from_slice(segment);
+        }
+        for reloc in tables.iter().flatten().flatten() {
+            buf.extend_from_slice(&reloc.skip.to_le_bytes());
+            buf.extend_from_slice(&reloc.patch.to_le_bytes());
+        }
+
+        if has_exheader {
+            let mut exheader = [0u32; 3];
+            if let Some(smdh) = &self.smdh {
+                exheader[0] = buf.len() as u32;
+                exheader[1] = smdh.len() as u32;
+                buf.extend_from_slice(smdh);
+            }
+            if let Some(romfs) = &self.romfs {
+                exheader[2] = buf.len() as u32;
+                buf.extend_from_slice(romfs);
+            }
+            for (i, val) in exheader.iter().enumerate() {
+                buf[exheader_offset + i * 4..][..4].copy_from_slice(&val.to_le_bytes());
+            }
+        }
+
+        Ok(buf)
     }
 }
 
+/// Encodes word indices into skip/patch relocation entries
+fn encode_relocations(mut words: Vec<u32>) -> Vec<Relocation> {
+    words.sort_unstable();
+    words.dedup();
+
+    let mut ret = Vec::new();
+    let mut pos = 0;
+    let mut iter = words.into_iter().peekable();
+    while let Some(start) = iter.next() {
+        let mut end = start + 1;
+        while iter.next_if_eq(&end).is_some() {
+            end += 1;
+        }
+
+        let mut skip = start - pos;
+        let mut patch = end - start;
+        while skip > u16::MAX as u32 {
+            ret.push(Relocation { skip: u16::MAX, patch: 0 });
+            skip -= u16::MAX as u32;
+        }
+        while patch > u16::MAX as u32 {
+            ret.push(Relocation { skip: skip as u16, patch: u16::MAX });
+            skip = 0;
+            patch -= u16::MAX as u32;
+        }
+        ret.push(Relocation { skip: skip as u16, patch: patch as u16 });
+        pos = end;
+    }
+    ret
+}
+
+/// 3DSX header data
+/// <https://www.3dbrew.org/wiki/3DSX_Format#Main_header>
 #[derive(Clone, Debug)]
 #[repr(C)]
 pub struct Hb3dsxHeader {
@@ -76,7 +490,58 @@ pub struct Hb3dsxHeader {
 }
 assert_eq_size!([u8; 0x20], Hb3dsxHeader);
 
+bitflags! {
+    /// 3DSX header flags
+    ///
+    /// No bits are defined by the format and 3dsxtool always writes zero, all bits are
+    /// retained so loaders can check for unknown ones
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Hb3dsxFlags: u32 {
+        const _ = !0;
+    }
+}
+
 impl Hb3dsxHeader {
+    /// Returns the header size, 0x2c if there's an extended header, 0x20 otherwise
+    #[must_use]
+    pub fn header_size(&self) -> u16 {
+        self.header_size
+    }
+    /// Returns the format version, only version 0 is supported
+    #[must_use]
+    pub fn format_version(&self) -> u32 {
+        self.format_version
+    }
+    /// Returns the flags
+    #[must_use]
+    pub fn flags(&self) -> Hb3dsxFlags {
+        Hb3dsxFlags::from_bits_retain(self.flags)
+    }
+    /// Returns size of a single relocation header
+    #[must_use]
+    pub fn relocation_header_size(&self) -> u16 {
+        self.relocation_header_size
+    }
+    /// Returns size of the code segment
+    #[must_use]
+    pub fn code_segment_size(&self) -> u32 {
+        self.code_segment_size
+    }
+    /// Returns size of the rodata segment
+    #[must_use]
+    pub fn rodata_segment_size(&self) -> u32 {
+        self.rodata_segment_size
+    }
+    /// Returns size of the data segment, including BSS
+    #[must_use]
+    pub fn data_bss_segment_size(&self) -> u32 {
+        self.data_bss_segment_size
+    }
+    /// Returns size of BSS
+    #[must_use]
+    pub fn bss_segment_size(&self) -> u32 {
+        self.bss_segment_size
+    }
     fn exheader_offset(&self) -> usize {
         0
     }
@@ -101,14 +566,10 @@ impl Hb3dsxHeader {
     pub fn code_reloc_table_offset(&self) -> usize {
         self.data_segment_offset() + (self.data_bss_segment_size - self.bss_segment_size) as usize
     }
-    /*fn rodata_reloc_table_offset(&self) -> usize {
-        self.data_segment_offset + self.data_segment_offset
-    }
-    fn data_reloc_table_offset(&self) -> usize {
-        self.data_segment_offset + self.data_segment_offset
-    }*/
 }
 
+/// 3DSX extended header data
+/// <https://www.3dbrew.org/wiki/3DSX_Format#Extended_Header>
 #[derive(Clone, Debug)]
 #[repr(C)]
 pub struct Hb3dsxExheader {
@@ -118,6 +579,26 @@ pub struct Hb3dsxExheader {
 }
 assert_eq_size!([u8; 0xc], Hb3dsxExheader);
 
+impl Hb3dsxExheader {
+    /// Returns offset of SMDH data in the file
+    #[must_use]
+    pub fn smdh_offset(&self) -> u32 {
+        self.smdh_offset
+    }
+    /// Returns size of SMDH data
+    #[must_use]
+    pub fn smdh_size(&self) -> u32 {
+        self.smdh_size
+    }
+    /// Returns offset of RomFS data in the file
+    #[must_use]
+    pub fn romfs_offset(&self) -> u32 {
+        self.romfs_offset
+    }
+}
+
+/// 3DSX relocation header data
+/// <https://www.3dbrew.org/wiki/3DSX_Format#Relocation_Header>
 #[derive(Clone, Debug)]
 #[repr(C)]
 pub struct RelocationHeader {
@@ -127,11 +608,25 @@ pub struct RelocationHeader {
 assert_eq_size!([u8; 0x8], RelocationHeader);
 
 impl RelocationHeader {
+    /// Returns amount of absolute relocations
+    #[must_use]
+    pub fn abs_count(&self) -> u32 {
+        self.abs_count
+    }
+    /// Returns amount of relative relocations
+    #[must_use]
+    pub fn rel_count(&self) -> u32 {
+        self.rel_count
+    }
+    /// Size of relocation table in bytes, saturated so that bogus counts fail bounds checks
     fn table_size(&self) -> usize {
-        (self.abs_count + self.rel_count) as usize * mem::size_of::<Relocation>()
+        (self.abs_count as usize)
+            .saturating_add(self.rel_count as usize)
+            .saturating_mul(mem::size_of::<Relocation>())
     }
 }
 
+/// 3DSX relocation entry, describes a run of words to skip followed by a run of words to patch
 #[derive(Clone, Debug)]
 #[repr(C)]
 pub struct Relocation {
@@ -140,6 +635,33 @@ pub struct Relocation {
 }
 assert_eq_size!([u8; 0x4], Relocation);
 
+impl Relocation {
+    /// Returns amount of words to skip before patching
+    #[must_use]
+    pub fn skip(&self) -> u16 {
+        self.skip
+    }
+    /// Returns amount of words to patch after skipping
+    #[must_use]
+    pub fn patch(&self) -> u16 {
+        self.patch
+    }
+    /// Patches a single word covered by this relocation.
+    ///
+    /// Words in a 3DSX file hold addresses as if the image was loaded at address 0.
+    /// For [RelocationType::Absolute] `base` is the address the image is loaded at, and it gets
+    /// added to the word. For [RelocationType::Relative] `base` is the offset of the patched word
+    /// from the start of the image, and it gets subtracted from the word, as the load address
+    /// cancels out
+    #[must_use]
+    pub fn apply(&self, word: u32, base: u32, ty: RelocationType) -> u32 {
+        match ty {
+            RelocationType::Absolute => word.wrapping_add(base),
+            RelocationType::Relative => word.wrapping_sub(base),
+        }
+    }
+}
+
 pub struct RelocationIter<'a> {
     hdr: &'a RelocationHeader,
     offset_bytes: usize,
@@ -168,8 +690,303 @@ impl<'a> Iterator for RelocationIter<'a> {
     }
 }
 
-#[derive(Clone, Debug)]
+/// Type of a 3DSX relocation
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum RelocationType {
-    Absolute,
-    Relative,
+    /// Word is an address, the load address gets added to it
+    Absolute = 0,
+    /// Word is an address, gets replaced with its distance from the patched word
+    Relative = 1,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Hb3dsx, Hb3dsxFlags, Relocation, RelocationHeader, RelocationType, Segment};
+    use crate::{CytrynaError, FromBytes};
+
+    #[test]
+    fn build_and_read_back() {
+        let code: Vec<u8> = (0..0x20).collect();
+        let rodata = vec![0xaa; 0x8];
+        let data = vec![0x55; 0xc];
+
+        let mut builder = Hb3dsx::builder();
+        builder
+            .with_code(code.clone())
+            .with_rodata(rodata.clone())
+            .with_data(data.clone())
+            .with_bss_size(0x100)
+            .add_relocation(Segment::Code, RelocationType::Absolute, 1)
+            .add_relocation(Segment::Code, RelocationType::Absolute, 2)
+            .add_relocation(Segment::Code, RelocationType::Absolute, 5)
+            .add_relocation(Segment::Code, RelocationType::Relative, 7)
+            .add_relocation(Segment::Data, RelocationType::Absolute, 2)
+            .with_romfs(vec![0x12; 0x10]);
+        let out = builder.build().unwrap();
+        let hb3dsx = Hb3dsx::from_bytes(&out).unwrap();
+
+        let hdr = hb3dsx.header();
+        assert_eq!(hdr.header_size(), 0x2c);
+        assert!(hdr.flags().is_empty());
+        assert_eq!(hdr.relocation_header_size(), 0x8);
+        assert_eq!(hdr.code_segment_size(), 0x20);
+        assert_eq!(hdr.rodata_segment_size(), 0x8);
+        assert_eq!(hdr.data_bss_segment_size(), 0x10c);
+        assert_eq!(hdr.bss_segment_size(), 0x100);
+
+        assert_eq!(hb3dsx.code_segment(), code);
+        assert_eq!(hb3dsx.rodata_segment(), rodata);
+        assert_eq!(hb3dsx.data_segment(), data);
+
+        assert_eq!(hb3dsx.code_reloc_header().abs_count(), 2);
+        assert_eq!(hb3dsx.code_reloc_header().rel_count(), 1);
+        assert_eq!(hb3dsx.rodata_reloc_header().abs_count(), 0);
+        assert_eq!(hb3dsx.data_reloc_header().abs_count(), 1);
+
+        let code_relocs: Vec<_> = hb3dsx
+            .code_reloc_iter()
+            .map(|(ty, r)| (ty, r.skip(), r.patch()))
+            .collect();
+        assert_eq!(
+            code_relocs,
+            [
+                (RelocationType::Absolute, 1, 2),
+                (RelocationType::Absolute, 2, 1),
+                (RelocationType::Relative, 7, 1),
+            ]
+        );
+        assert_eq!(hb3dsx.rodata_reloc_iter().count(), 0);
+        let data_relocs: Vec<_> = hb3dsx
+            .data_reloc_iter()
+            .map(|(ty, r)| (ty, r.skip(), r.patch()))
+            .collect();
+        assert_eq!(data_relocs, [(RelocationType::Absolute, 2, 1)]);
+
+        let exheader = hb3dsx.exheader().unwrap();
+        assert_eq!(exheader.smdh_size(), 0);
+        assert_eq!(&out[exheader.romfs_offset() as usize..], [0x12; 0x10]);
+    }
+
+    #[test]
+    fn relocation_apply() {
+        let reloc = Relocation { skip: 0, patch: 1 };
+        assert_eq!(reloc.apply(0x1234, 0x0010_0000, RelocationType::Absolute), 0x0010_1234);
+        // word at offset 0x1000 pointing to 0x2000
+        assert_eq!(reloc.apply(0x2000, 0x1000, RelocationType::Relative), 0x1000);
+        // word at offset 0x2000 pointing backwards to 0x1000
+        assert_eq!(reloc.apply(0x1000, 0x2000, RelocationType::Relative), (-0x1000i32) as u32);
+    }
+
+    #[test]
+    fn load_with_relocations() {
+        let mut code = vec![0u8; 0x10];
+        // addresses are laid out like mk3dsx writes them, relative to the start of the
+        // page-aligned image: absolute pointer to second word of rodata
+        code[0x0..0x4].copy_from_slice(&0x1004u32.to_le_bytes());
+        // relative pointer to start of data
+        code[0xc..0x10].copy_from_slice(&0x2000u32.to_le_bytes());
+
+        let out = Hb3dsx::builder()
+            .with_code(code)
+            .with_rodata(vec![0xaa; 0x8])
+            .with_data(vec![0x55; 0x8])
+            .with_bss_size(0x10)
+            .add_relocation(Segment::Code, RelocationType::Absolute, 0)
+            .add_relocation(Segment::Code, RelocationType::Relative, 3)
+            .build()
+            .unwrap();
+        let image = Hb3dsx::from_bytes(&out).unwrap().load_at(0x0010_0000).unwrap();
+
+        let word = |offset: usize| u32::from_le_bytes(image[offset..][..4].try_into().unwrap());
+        assert_eq!(image.len(), 0x2018);
+        assert_eq!(word(0x0), 0x0010_1004);
+        assert_eq!(word(0x4), 0);
+        assert_eq!(word(0xc), 0x2000 - 0xc);
+        assert_eq!(image[0x1000..0x1008], [0xaa; 0x8]);
+        assert_eq!(image[0x2000..0x2008], [0x55; 0x8]);
+        assert_eq!(image[0x2008..], [0; 0x10]);
+    }
+
+    #[test]
+    fn load_bogus_sizes() {
+        let out = Hb3dsx::builder()
+            .with_code(vec![0x11; 0x10])
+            .with_rodata(vec![0xaa; 0x8])
+            .with_data(vec![0x55; 0x8])
+            .build()
+            .unwrap();
+        let load = |offset: usize, value: u32| {
+            let mut out = out.clone();
+            out[offset..][..4].copy_from_slice(&value.to_le_bytes());
+            Hb3dsx::from_bytes(&out).unwrap().load_at(0x0010_0000)
+        };
+
+        // BSS larger than data and BSS together
+        assert!(matches!(load(0x1c, 0x10), Err(CytrynaError::InvalidRegionPosition)));
+        // rodata past the end of file
+        assert!(matches!(
+            load(0x14, 0x1000_0000),
+            Err(CytrynaError::InvalidLength { what: "3DSX segments", .. })
+        ));
+        // relocation counts past the end of file, large enough to overflow when added
+        assert!(matches!(
+            load(0x20, u32::MAX),
+            Err(CytrynaError::InvalidLength { what: "3DSX relocation tables", .. })
+        ));
+        let mut huge = out.clone();
+        huge[0x20..0x28].copy_from_slice(&[0xff; 0x8]);
+        assert!(matches!(
+            Hb3dsx::from_bytes(&huge).unwrap().load_at(0x0010_0000),
+            Err(CytrynaError::InvalidLength { what: "3DSX relocation tables", .. })
+        ));
+        // BSS so large that the image size overflows
+        let mut out = out.clone();
+        out[0x18..0x20].copy_from_slice(&[0xf8, 0xff, 0xff, 0xff, 0xf0, 0xff, 0xff, 0xff]);
+        assert!(matches!(
+            Hb3dsx::from_bytes(&out).unwrap().load_at(0x0010_0000),
+            Err(CytrynaError::InvalidRegionPosition)
+        ));
+    }
+
+    #[test]
+    fn all_relocations() {
+        let out = Hb3dsx::builder()
+            .with_code(vec![0; 0x10])
+            .with_rodata(vec![0; 0x10])
+            .with_data(vec![0; 0x10])
+            .add_relocation(Segment::Code, RelocationType::Absolute, 0)
+            .add_relocation(Segment::Code, RelocationType::Relative, 2)
+            .add_relocation(Segment::Rodata, RelocationType::Absolute, 1)
+            .add_relocation(Segment::Rodata, RelocationType::Absolute, 3)
+            .add_relocation(Segment::Data, RelocationType::Relative, 0)
+            .build()
+            .unwrap();
+        let hb3dsx = Hb3dsx::from_bytes(&out).unwrap();
+
+        let count = |hdr: &RelocationHeader| (hdr.abs_count() + hdr.rel_count()) as usize;
+        let expected = count(hb3dsx.code_reloc_header())
+            + count(hb3dsx.rodata_reloc_header())
+            + count(hb3dsx.data_reloc_header());
+        let all: Vec<_> = hb3dsx.all_relocations().collect();
+        assert_eq!(all.len(), expected);
+        assert_eq!(all[0].0, Segment::Code);
+        assert_eq!(all[all.len() - 1].0, Segment::Data);
+        assert_eq!(all[all.len() - 1].1, RelocationType::Relative);
+        assert_eq!(all.iter().filter(|r| r.0 == Segment::Rodata).count(), 2);
+    }
+
+    #[test]
+    fn unknown_format_version() {
+        let mut out = Hb3dsx::builder().with_code(vec![0; 0x10]).build().unwrap();
+        assert_eq!(Hb3dsx::from_bytes(&out).unwrap().header().format_version(), 0);
+
+        out[0x8] = 1;
+        assert!(matches!(
+            Hb3dsx::from_bytes(&out),
+            Err(CytrynaError::UnsupportedHeaderVersion)
+        ));
+    }
+
+    #[test]
+    fn header_sizes() {
+        let out = Hb3dsx::builder().with_code(vec![0; 0x10]).build().unwrap();
+        assert_eq!(Hb3dsx::from_bytes(&out).unwrap().header().header_size(), 0x20);
+
+        let mut out = Hb3dsx::builder()
+            .with_code(vec![0; 0x10])
+            .with_romfs(vec![0x12; 0x10])
+            .build()
+            .unwrap();
+        assert_eq!(Hb3dsx::from_bytes(&out).unwrap().header().header_size(), 0x2c);
+
+        out[0x4] = 0x24;
+        assert!(matches!(Hb3dsx::from_bytes(&out), Err(CytrynaError::InvalidHeaderSize)));
+        out[0x4] = 0x2c;
+        assert!(matches!(
+            Hb3dsx::from_bytes(&out[..0x2c + 0x10]),
+            Err(CytrynaError::SliceTooSmall)
+        ));
+    }
+
+    #[test]
+    fn unknown_flags_retained() {
+        let mut out = Hb3dsx::builder().with_code(vec![0; 0x10]).build().unwrap();
+        out[0xc] = 0x5;
+        let flags = Hb3dsx::from_bytes(&out).unwrap().header().flags();
+        assert_eq!(flags, Hb3dsxFlags::from_bits_retain(0x5));
+        assert_eq!(flags.bits(), 0x5);
+    }
+
+    #[cfg(feature = "smdh")]
+    #[test]
+    fn catalog_id_from_smdh() {
+        use crate::smdh::tests::test_smdh;
+
+        let out = Hb3dsx::builder()
+            .with_code(vec![0; 0x10])
+            .with_smdh(&test_smdh())
+            .build()
+            .unwrap();
+        let hb3dsx = Hb3dsx::from_bytes(&out).unwrap();
+        assert!(hb3dsx.smdh().unwrap().is_some());
+        assert_eq!(hb3dsx.catalog_id().unwrap(), "Someone/Short");
+
+        let out = Hb3dsx::builder().with_code(vec![0; 0x10]).build().unwrap();
+        let hb3dsx = Hb3dsx::from_bytes(&out).unwrap();
+        assert!(hb3dsx.smdh().unwrap().is_none());
+        assert!(matches!(hb3dsx.catalog_id(), Err(CytrynaError::MissingRegion)));
+    }
+
+    #[cfg(feature = "smdh")]
+    #[test]
+    fn embedded_smdh_and_romfs() {
+        use crate::smdh::{tests::test_smdh, Language};
+
+        let out = Hb3dsx::builder()
+            .with_code(vec![0; 0x10])
+            .with_smdh(&test_smdh())
+            .with_romfs(vec![0x34; 0x20])
+            .build()
+            .unwrap();
+        let hb3dsx = Hb3dsx::from_bytes(&out).unwrap();
+        assert_eq!(hb3dsx.header().header_size(), 0x2c);
+        let smdh = hb3dsx.smdh().unwrap().unwrap();
+        assert_eq!(smdh.title_strings(Language::English).short, "Short");
+        assert_eq!(hb3dsx.romfs(), Some(&[0x34; 0x20][..]));
+
+        let out = Hb3dsx::builder().with_code(vec![0; 0x10]).build().unwrap();
+        let hb3dsx = Hb3dsx::from_bytes(&out).unwrap();
+        assert_eq!(hb3dsx.header().header_size(), 0x20);
+        assert!(hb3dsx.romfs().is_none());
+    }
+
+    #[test]
+    fn rebuild_from_parsed() {
+        let mut builder = Hb3dsx::builder();
+        builder
+            .with_code((0..0x40).collect())
+            .with_rodata(vec![0xaa; 0x8])
+            .with_data(vec![0x55; 0xc])
+            .with_bss_size(0x20)
+            .add_relocation(Segment::Code, RelocationType::Absolute, 0)
+            .add_relocation(Segment::Code, RelocationType::Absolute, 3)
+            .add_relocation(Segment::Code, RelocationType::Absolute, 4)
+            .add_relocation(Segment::Code, RelocationType::Relative, 9)
+            .add_relocation(Segment::Rodata, RelocationType::Relative, 1)
+            .add_relocation(Segment::Data, RelocationType::Absolute, 2);
+        let out = builder.build().unwrap();
+        let parsed = Hb3dsx::from_bytes(&out).unwrap();
+
+        let rebuilt = Hb3dsx::builder()
+            .with_code(parsed.code_segment().to_vec())
+            .with_rodata(parsed.rodata_segment().to_vec())
+            .with_data(parsed.data_segment().to_vec())
+            .with_bss_size(parsed.header().bss_segment_size())
+            .add_relocations(Segment::Code, parsed.code_reloc_iter())
+            .add_relocations(Segment::Rodata, parsed.rodata_reloc_iter())
+            .add_relocations(Segment::Data, parsed.data_reloc_iter())
+            .build()
+            .unwrap();
+        assert_eq!(rebuilt, out);
+    }
 }
@@ -1,3 +1,4 @@
+#![cfg_attr(not(feature = "std"), no_std)]
 #![allow(clippy::transmute_ptr_to_ref)]
 #![allow(clippy::identity_op)]
 
@@ -6,6 +7,8 @@ extern crate alloc;
 #[cfg(feature = "3dsx")]
 pub mod hb3dsx;
 #[cfg(feature = "cia")]
+pub mod cert;
+#[cfg(feature = "cia")]
 pub mod cia;
 #[cfg(feature = "crypto")]
 pub mod crypto;
@@ -15,6 +18,9 @@ pub mod firm;
 pub mod hash;
 #[cfg(feature = "ncch")]
 pub mod ncch;
+#[cfg(feature = "ncch")]
+pub mod ncsd;
+pub mod scan;
 #[cfg(feature = "smdh")]
 pub mod smdh;
 pub mod string;
@@ -24,13 +30,24 @@ pub mod titleid;
 #[cfg(feature = "cia")]
 pub mod tmd;
 
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 use core::ops::Deref;
 
-use derive_more::{Display, Error, From};
+pub use scan::{detect, FileKind};
+#[cfg(any(feature = "cia", feature = "ncch", feature = "firm", feature = "3dsx", feature = "smdh"))]
+pub use scan::{open, Container};
+#[cfg(feature = "std")]
+pub use scan::scan_dir;
+
+use derive_more::{Display, From};
+#[cfg(feature = "std")]
+use derive_more::Error;
 
 /// Low-effort catch-all error type for cytryna library
 #[non_exhaustive]
-#[derive(Display, Debug, Error, From)]
+#[derive(Display, Debug, From)]
+#[cfg_attr(feature = "std", derive(Error))]
 pub enum CytrynaError {
     #[display(fmt = "Invalid magic bytes")]
     InvalidMagic,
@@ -47,13 +64,13 @@ pub enum CytrynaError {
     #[display(fmt = "Unsupported version of header")]
     UnsupportedHeaderVersion,
     #[cfg(feature = "crypto")]
-    #[error(ignore)]
+    #[cfg_attr(feature = "std", error(ignore))]
     #[display(fmt = "Missing {_0} key")]
     MissingKey(crypto::KeyIndex),
     #[cfg(feature = "crypto")]
     #[display(fmt = "Uninitialized keybag")]
     NoKeyBag,
-    #[error(ignore)]
+    #[cfg_attr(feature = "std", error(ignore))]
     #[from(ignore)]
     #[display(fmt = "Value out of range for {_0} enum")]
     EnumValueOutOfRange(&'static str),
@@ -72,10 +89,38 @@ pub enum CytrynaError {
     #[cfg(feature = "crypto")]
     #[display(fmt = "Failed to stream-encrypt/decrypt data")]
     StreamCrypt(ctr::cipher::StreamCipherError),
+    #[display(fmt = "Decrypted data is garbage, wrong key?")]
+    DecryptionFailed,
     #[display(fmt = "Failed to decode hex string")]
     HexError(hex::FromHexError),
     #[display(fmt = "Incorrect alignment")]
     BadAlign,
+    #[cfg_attr(feature = "std", error(ignore))]
+    #[from(ignore)]
+    #[display(fmt = "Unsupported: {_0}")]
+    Unsupported(&'static str),
+    #[cfg(feature = "std")]
+    #[display(fmt = "I/O error: {_0}")]
+    Io(std::io::Error),
+    #[from(ignore)]
+    #[display(fmt = "Failed to parse {context} at offset {offset:#x}: {source}")]
+    ParseError {
+        context: &'static str,
+        offset: usize,
+        source: Box<CytrynaError>,
+    },
+}
+
+impl CytrynaError {
+    /// Wraps the error with the region it happened in and its offset in the file
+    #[must_use]
+    pub fn with_context(self, context: &'static str, offset: usize) -> Self {
+        Self::ParseError {
+            context,
+            offset,
+            source: Box::new(self),
+        }
+    }
 }
 
 pub type CytrynaResult<T> = core::result::Result<T, CytrynaError>;
@@ -100,6 +145,9 @@ pub trait FromBytes {
 
     /// A function that brings it all together
     fn from_bytes(bytes: &[u8]) -> CytrynaResult<&Self> {
+        if bytes.len() < Self::min_size() {
+            return Err(CytrynaError::SliceTooSmall);
+        }
         Self::bytes_ok(bytes)?;
         let ret = Self::cast(bytes);
         if ret.hash_ok() {
@@ -110,8 +158,15 @@ pub trait FromBytes {
     }
 }
 
+/// Trait for containers that can check integrity of their own data
+pub trait Verify {
+    /// Runs every integrity check that doesn't need data from outside of the container,
+    /// returning an error on the first one that fails
+    fn verify(&self) -> CytrynaResult<()>;
+}
+
 pub mod prelude {
-    pub use crate::FromBytes;
+    pub use crate::{FromBytes, Verify};
     #[cfg(feature = "3dsx")]
     pub use crate::hb3dsx::Hb3dsx;
     #[cfg(feature = "cia")]
@@ -148,6 +203,45 @@ pub(crate) const fn align_up(val: u32, alignment: u32) -> u32 {
     }
 }
 
+/// Offset or size stored in media units, which are 0x200 bytes unless a container says otherwise
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct MediaUnits(pub u32);
+
+impl MediaUnits {
+    /// Default size of a media unit in bytes
+    pub const SIZE: usize = 0x200;
+
+    /// Converts into bytes, using the default media unit size
+    #[must_use]
+    pub const fn bytes(self) -> usize {
+        self.bytes_with_unit_size(Self::SIZE)
+    }
+    /// Converts into bytes, using a given media unit size. Saturates instead of overflowing, as
+    /// such value can't describe anything that fits in memory anyway
+    #[must_use]
+    pub const fn bytes_with_unit_size(self, unit_size: usize) -> usize {
+        (self.0 as usize).saturating_mul(unit_size)
+    }
+    /// Checks if this is zero, which usually means that a region is missing
+    #[must_use]
+    pub const fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl From<u32> for MediaUnits {
+    fn from(units: u32) -> Self {
+        Self(units)
+    }
+}
+
+impl From<MediaUnits> for u32 {
+    fn from(units: MediaUnits) -> Self {
+        units.0
+    }
+}
+
 /// Contains either a box pointer to a type, or a reference to it, used as a return type for
 /// functions that may or may not decompress/decrypt data
 #[derive(Debug, Clone)]
@@ -192,7 +286,29 @@ impl<T> Deref for VecOrSlice<'_, T> {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
+    use super::{CytrynaResult, Verify};
+    use hex_literal::hex;
+
+    /// Modulus of an RSA-2048 key generated with `openssl genrsa 2048`, used in signature tests
+    pub(crate) static TEST_MODULUS: [u8; 0x100] = hex!("B578FC0960442F870B636A69F6007BC69A0F952B96831CD9DCBCE8E70834EB762692DD2E87212DC59CF5A2A84630BA53980F170D753CCE4522FD630FED355EBCE2AD66463BDC1DB02776C4118A63AF32A2879E33A65626D40FA68E41369140DA0165467E3F202FE6E57663D8718F97116180D3DB1504CF07D32CDAD127A157AEFE137AAC364F7CCB7B97B27E129F4708C0D696AEAEE989A8058467F3454E9E4E912488A1E3A497EEC18F6F01E68B27ABE73C0C79865D799513F3AB97BB3C2576D0E2D18F5AB6F701642B6C33595C12CE307ACD9CAABF5C20229FFE7338C786367CFE3F044597BE1CCCB220C4E67EBF0959C722036C88B9C56B23CD78962A2E5B");
+
+    /// Verifies through a generic bound, like code that doesn't know what it has been given
+    pub(crate) fn verify_any<T: Verify + ?Sized>(container: &T) -> CytrynaResult<()> {
+        container.verify()
+    }
+
+    /// Returns a path in the temporary directory that isn't used by other tests, even ones run
+    /// by other processes at the same time
+    #[cfg(feature = "std")]
+    pub(crate) fn temp_path(name: &str) -> std::path::PathBuf {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let unique = COUNTER.fetch_add(1, Ordering::Relaxed);
+        std::env::temp_dir().join(format!("cytryna-{}-{unique}-{name}", std::process::id()))
+    }
+
     #[test]
     fn check_align_up() {
         use super::align_up;
@@ -204,4 +320,20 @@ mod tests {
         assert_eq!(align_up(val_unaligned, alignment), 0x40);
         assert_eq!(align_up(val_aligned, alignment), 0x40);
     }
+
+    #[test]
+    fn media_units_to_bytes() {
+        use super::MediaUnits;
+
+        assert_eq!(MediaUnits(0).bytes(), 0);
+        assert_eq!(MediaUnits(3).bytes(), 0x600);
+        assert_eq!(MediaUnits::from(3u32), MediaUnits(3));
+        assert_eq!(u32::from(MediaUnits(3)), 3);
+        assert_eq!(MediaUnits(3).bytes_with_unit_size(0x400), 0xc00);
+
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(MediaUnits(u32::MAX).bytes(), 0x1ff_ffff_fe00);
+        assert_eq!(MediaUnits(2).bytes_with_unit_size(usize::MAX), usize::MAX);
+        assert_eq!(MediaUnits(u32::MAX).bytes_with_unit_size(usize::MAX / 2), usize::MAX);
+    }
 }
@@ -1,3 +1,4 @@
+use crate::hash::sha256;
 use crate::string::SizedCString;
 use crate::VecOrSlice;
 
@@ -27,6 +28,16 @@ impl ExeFs<'_> {
             Some(VecOrSlice::S(file))
         }
     }
+    /// Returns a reference to ExeFS header
+    #[must_use]
+    pub fn header(&self) -> &ExeFsHeader {
+        self.inner.header()
+    }
+    /// Checks if a file matches its hash stored in ExeFS header. Always fails on encrypted data
+    #[must_use]
+    pub fn verify_file_hash(&self, hdr: &FileHeader) -> bool {
+        self.inner.verify_file_hash(hdr)
+    }
 }
 
 /// Raw ExeFS data
@@ -41,11 +52,23 @@ pub struct ExeFsInner {
 }
 
 impl ExeFsInner {
+    /// Returns a reference to ExeFS header
+    #[must_use]
+    pub fn header(&self) -> &ExeFsHeader {
+        &self.header
+    }
     /// Returns a file that is referenced by a given header
     #[must_use]
     pub fn file_by_header<'a>(&'a self, hdr: &'a FileHeader) -> &'a [u8] {
         &self.data[hdr.offset as usize..][..hdr.size as usize]
     }
+    /// Checks if a file matches its hash stored in ExeFS header
+    #[must_use]
+    pub fn verify_file_hash(&self, hdr: &FileHeader) -> bool {
+        self.header
+            .file_hash(hdr)
+            .is_some_and(|hash| sha256(self.file_by_header(hdr)) == *hash)
+    }
 }
 
 /// Raw ExeFS Header
@@ -66,6 +89,14 @@ impl ExeFsHeader {
     pub fn file_headers_used(&self) -> impl Iterator<Item = &FileHeader> {
         self.file_headers.iter().filter(|hdr| !hdr.is_unused())
     }
+    /// Returns an iterator over used file headers paired with their stored hashes
+    pub fn files_with_hashes(&self) -> impl Iterator<Item = (&FileHeader, &[u8; 32])> {
+        // hashes are stored in reverse order
+        self.file_headers
+            .iter()
+            .zip(self.file_hashes.iter().rev())
+            .filter(|(hdr, _)| !hdr.is_unused())
+    }
     /// Returns a file header with a given filename
     #[must_use]
     pub fn file_header_by_name<'a>(&'a self, name: &[u8]) -> Option<&'a FileHeader> {
@@ -77,6 +108,16 @@ impl ExeFsHeader {
         self.file_headers_used()
             .find(|&hdr| name == hdr.name.data())
     }
+    /// Returns the stored hash of a file with a given header
+    #[must_use]
+    pub fn file_hash(&self, hdr: &FileHeader) -> Option<&[u8; 32]> {
+        let idx = self
+            .file_headers
+            .iter()
+            .position(|v| v.name.data() == hdr.name.data())?;
+        // hashes are stored in reverse order
+        Some(&self.file_hashes[self.file_hashes.len() - 1 - idx])
+    }
 }
 
 /// ExeFS File header data
@@ -90,9 +131,83 @@ pub struct FileHeader {
 assert_eq_size!([u8; 16], FileHeader);
 
 impl FileHeader {
-    /// Checks if a given file header is used
+    /// Returns the file name
+    #[must_use]
+    pub fn name(&self) -> &SizedCString<0x8> {
+        &self.name
+    }
+    /// Returns offset of the file, relative to the end of ExeFS header
+    #[must_use]
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+    /// Returns size of the file
+    #[must_use]
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+    /// Checks if a given file header is unused
     #[must_use]
     fn is_unused(&self) -> bool {
-        !self.name.is_zero() && self.offset == 0 && self.size == 0
+        self.name.is_zero() && self.offset == 0 && self.size == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExeFsHeader, FileHeader};
+
+    #[test]
+    fn unused_file_header() {
+        let unused = FileHeader {
+            name: [0; 0x8].into(),
+            offset: 0,
+            size: 0,
+        };
+        assert!(unused.is_unused());
+
+        let empty_file = FileHeader {
+            name: (*b"logo\0\0\0\0").into(),
+            offset: 0,
+            size: 0,
+        };
+        assert!(!empty_file.is_unused());
+    }
+
+    #[test]
+    fn files_with_hashes() {
+        let mut hdr = ExeFsHeader {
+            file_headers: [0; 8].map(|_| FileHeader {
+                name: [0; 0x8].into(),
+                offset: 0,
+                size: 0,
+            }),
+            _reserved: [0; 0x80],
+            file_hashes: [[0; 32]; 8],
+        };
+        for (i, name) in [b".code\0\0\0", b"banner\0\0", b"icon\0\0\0\0"]
+            .iter()
+            .enumerate()
+        {
+            hdr.file_headers[i].name = (**name).into();
+            hdr.file_headers[i].size = 0x10;
+            hdr.file_hashes[7 - i] = [i as u8 + 1; 32];
+        }
+
+        let pairs: Vec<_> = hdr
+            .files_with_hashes()
+            .map(|(file, hash)| (file.name().data(), hash[0]))
+            .collect();
+        assert_eq!(
+            pairs,
+            [
+                (&b".code\0\0\0"[..], 1),
+                (&b"banner\0\0"[..], 2),
+                (&b"icon\0\0\0\0"[..], 3)
+            ]
+        );
+        for (file, hash) in hdr.files_with_hashes() {
+            assert_eq!(hdr.file_hash(file), Some(hash));
+        }
     }
 }
@@ -1,13 +1,22 @@
 pub mod exefs;
 pub mod romfs;
 
+use alloc::alloc::{alloc as alloc_raw, handle_alloc_error, Layout};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
 use core::fmt;
 use core::mem;
+use core::ptr;
+use core::slice;
 
-use crate::crypto::{self, aes128_ctr::*, KeyBag, KeyIndex, KeyType};
+use crate::crypto::{self, aes128_ctr::*, KeyBag, KeyIndex, KeyType, NcchRegionType};
+use crate::hash::sha256;
 use crate::string::SizedCString;
-use crate::titleid::MaybeTitleId;
-use crate::{CytrynaError, CytrynaResult, OwnedOrBorrowed};
+use crate::titleid::{MaybeTitleId, TitleId};
+use crate::{CytrynaError, CytrynaResult, MediaUnits, OwnedOrBorrowed, VecOrSlice, Verify};
+#[cfg(feature = "smdh")]
+use crate::{smdh::Smdh, FromBytes};
 
 use bitflags::bitflags;
 use bitfield_struct::bitfield;
@@ -23,7 +32,7 @@ pub struct NcchHeader {
     sig: [u8; 0x100],
     magic: [u8; 4],
     #[derivative(Debug = "ignore")]
-    content_size: u32,
+    content_size: MediaUnits,
     partition_id: u64,
     maker_code: SizedCString<2>,
     version: u16,
@@ -38,18 +47,18 @@ pub struct NcchHeader {
     #[derivative(Debug = "ignore")]
     _reserved1: u32,
     flags: NcchFlags,
-    plain_offset: u32,
-    plain_size: u32,
-    logo_offset: u32,
-    logo_size: u32,
-    exefs_offset: u32,
-    exefs_size: u32,
-    exefs_hash_size: u32,
+    plain_offset: MediaUnits,
+    plain_size: MediaUnits,
+    logo_offset: MediaUnits,
+    logo_size: MediaUnits,
+    exefs_offset: MediaUnits,
+    exefs_size: MediaUnits,
+    exefs_hash_size: MediaUnits,
     #[derivative(Debug = "ignore")]
     _reserved2: u32,
-    romfs_offset: u32,
-    romfs_size: u32,
-    romfs_hash_size: u32,
+    romfs_offset: MediaUnits,
+    romfs_size: MediaUnits,
+    romfs_hash_size: MediaUnits,
     #[derivative(Debug = "ignore")]
     _reserved3: u32,
     exefs_super_hash: [u8; 0x20],
@@ -97,7 +106,67 @@ bitflags! {
     }
 }
 
+impl NcchHeader {
+    /// Returns the program ID
+    pub fn program_id(&self) -> CytrynaResult<TitleId> {
+        TitleId::from_u64(self.program_id)
+    }
+    /// Returns the partition ID
+    #[must_use]
+    pub fn partition_id(&self) -> u64 {
+        self.partition_id
+    }
+    /// Returns the maker code
+    #[must_use]
+    pub fn maker_code(&self) -> &SizedCString<2> {
+        &self.maker_code
+    }
+    /// Returns the NCCH version
+    #[must_use]
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+    /// Returns the product code, like "CTR-P-ABCE"
+    #[must_use]
+    pub fn product_code(&self) -> &SizedCString<0x10> {
+        &self.product_code
+    }
+    /// Returns size of a media unit in bytes, offsets and sizes of regions are stored in these
+    #[must_use]
+    pub fn media_unit_size(&self) -> usize {
+        // units too big to fit in usize can't describe any real NCCH, so just saturate
+        1usize
+            .checked_shl(9 + u32::from(self.flags.content_unit_size))
+            .unwrap_or(usize::MAX)
+    }
+    /// Returns size of the whole NCCH in bytes
+    #[must_use]
+    pub fn content_size_bytes(&self) -> u64 {
+        (self.content_size.0 as u64).saturating_mul(self.media_unit_size() as u64)
+    }
+    /// Converts a value in media units into bytes
+    fn units_to_bytes(&self, units: MediaUnits) -> usize {
+        units.bytes_with_unit_size(self.media_unit_size())
+    }
+}
+
+/// Region of NCCH that is located by NCCH header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NcchRegion {
+    Plain,
+    Logo,
+    ExeFs,
+    RomFs,
+}
+
+/// How much data [Ncch::decrypt_region_into] decrypts at once
+#[cfg(feature = "std")]
+const DECRYPT_CHUNK_SIZE: usize = 0x10000;
+
 /// NCCH File
+///
+/// Parsing it in place requires the data to be aligned like NcchHeader,
+/// [Ncch::from_slice_owned] can be used for data of any alignment
 #[repr(C)]
 pub struct Ncch {
     header: NcchHeader,
@@ -110,16 +179,50 @@ impl Ncch {
     pub fn header(&self) -> &NcchHeader {
         &self.header
     }
+    /// Parses an NCCH in place, fails with BadAlign if the data isn't aligned enough
     pub fn from_slice(what: &[u8]) -> CytrynaResult<&Self> {
+        if what.len() < mem::size_of::<NcchHeader>() {
+            return Err(CytrynaError::SliceTooSmall);
+        }
         let alignment = mem::align_of::<NcchHeader>();
-        assert_eq!(0, what.as_ptr().align_offset(alignment));
+        if what.as_ptr().align_offset(alignment) != 0 {
+            return Err(CytrynaError::BadAlign);
+        }
 
-        let me: &Ncch = unsafe { mem::transmute(what) };
+        // length metadata of a DST reference is the length of its trailing slice, not of the
+        // whole struct
+        let data_len = what.len() - mem::size_of::<NcchHeader>();
+        let me: &Ncch = unsafe { mem::transmute(&what[..data_len]) };
         if &me.header.magic != b"NCCH" {
             Err(CytrynaError::InvalidMagic)?;
         }
         Ok(me)
     }
+    /// Parses an NCCH from a byte slice of any alignment, copying it into a new allocation
+    pub fn from_slice_owned(what: &[u8]) -> CytrynaResult<Box<Self>> {
+        if what.len() < mem::size_of::<NcchHeader>() {
+            return Err(CytrynaError::SliceTooSmall);
+        }
+        if what[0x100..0x104] != *b"NCCH" {
+            return Err(CytrynaError::InvalidMagic);
+        }
+
+        // has to match the layout Box<Ncch> gets deallocated with
+        let layout = Layout::from_size_align(what.len(), mem::align_of::<NcchHeader>())
+            .map_err(|_| CytrynaError::SliceTooSmall)?
+            .pad_to_align();
+        unsafe {
+            let buf = alloc_raw(layout);
+            if buf.is_null() {
+                handle_alloc_error(layout);
+            }
+            buf.copy_from_nonoverlapping(what.as_ptr(), what.len());
+            let data_len = what.len() - mem::size_of::<NcchHeader>();
+            Ok(Box::from_raw(
+                ptr::slice_from_raw_parts_mut(buf, data_len) as *mut Ncch
+            ))
+        }
+    }
     /// Check if data is encrypted
     #[must_use]
     pub fn is_encrypted(&self) -> bool {
@@ -129,15 +232,112 @@ impl Ncch {
             .options
             .contains(NcchFlagsOptions::NO_CRYPTO)
     }
+    /// Checks if RomFS (and ExeFS .code) is encrypted with a different key than the rest of
+    /// this NCCH, which is the case for titles using 7.x or newer crypto
+    #[must_use]
+    pub fn romfs_uses_secondary_key(&self) -> bool {
+        self.is_encrypted() && self.header.flags.two_keyslots != 0
+    }
+    /// Returns keys needed for decrypting this NCCH
+    pub fn required_keys(&self) -> CytrynaResult<Vec<KeyIndex>> {
+        if !self.is_encrypted() {
+            return Ok(Vec::new());
+        }
+
+        let mut keys = vec![KeyIndex::Generator, KeyIndex::Slot(0x2c, KeyType::X)];
+        let secondary = self.secondary_keyslot()?;
+        if secondary != 0x2c {
+            keys.push(KeyIndex::Slot(secondary, KeyType::X));
+        }
+        if self.uses_seed_crypto() {
+            keys.push(KeyIndex::Seed(self.header.program_id()?.to_u64()));
+        }
+        Ok(keys)
+    }
+    /// Returns keys needed for decrypting this NCCH that aren't in a given KeyBag
+    pub fn missing_keys(&self, bag: &KeyBag) -> CytrynaResult<Vec<KeyIndex>> {
+        let mut keys = self.required_keys()?;
+        keys.retain(|idx| bag.get_key(idx.clone()).is_err());
+        Ok(keys)
+    }
+    /// Checks if RomFS (and ExeFS .code) keyY is derived from a per-title seed
+    #[must_use]
+    pub fn uses_seed_crypto(&self) -> bool {
+        self.is_encrypted()
+            && self
+                .header
+                .flags
+                .options
+                .contains(NcchFlagsOptions::NEW_KEY_Y_GENERATOR)
+    }
+    /// Checks if RomFS and ExeFS .code are encrypted with a different key than everything else
+    fn has_secondary_key(&self) -> bool {
+        self.romfs_uses_secondary_key() || self.uses_seed_crypto()
+    }
+    /// Returns the normal-key for a given keyslot, derived from this NCCH's keyY
+    fn slot_key(&self, slot: u8) -> CytrynaResult<[u8; 0x10]> {
+        let x = KeyBag::global()?.get_key(KeyIndex::Slot(slot, KeyType::X))?;
+        let y = &self.header.sig[..0x10];
+
+        crypto::keygen(*x, y.try_into().unwrap())
+    }
+    /// Returns the normal-key used for RomFS and ExeFS .code, with keyY derived from title seed
+    /// if this NCCH uses seed crypto
+    fn secondary_key(&self) -> CytrynaResult<[u8; 0x10]> {
+        let bag = KeyBag::global()?;
+        let x = bag.get_key(KeyIndex::Slot(self.secondary_keyslot()?, KeyType::X))?;
+        let mut y: [u8; 0x10] = self.header.sig[..0x10].try_into().unwrap();
+
+        if self.uses_seed_crypto() {
+            let seed = bag.get_key(KeyIndex::Seed(self.header.program_id()?.to_u64()))?;
+            y = crypto::seeded_key_y(y, *seed);
+        }
+        crypto::keygen(*x, y)
+    }
+    /// Returns the keyslot used for decrypting RomFS and ExeFS .code
+    /// <https://www.3dbrew.org/wiki/NCCH#NCCH_Flags>
+    fn secondary_keyslot(&self) -> CytrynaResult<u8> {
+        match self.header.flags.two_keyslots {
+            0x00 => Ok(0x2c),
+            0x01 => Ok(0x25),
+            0x0a => Ok(0x18),
+            0x0b => Ok(0x1b),
+            _ => Err(CytrynaError::EnumValueOutOfRange("ncch::NcchFlags crypto method")),
+        }
+    }
+    /// Returns the AES-CTR initialization vector of a region
+    fn region_iv(&self, region: NcchRegionType) -> [u8; 0x10] {
+        crypto::ncch_iv(self.header.program_id, region)
+    }
     /// Returns a region as a byte slice
-    fn region(&self, offset: u32, size: u32) -> CytrynaResult<&[u8]> {
-        if offset == 0 || size == 0 {
+    fn region(&self, offset: MediaUnits, size: MediaUnits) -> CytrynaResult<&[u8]> {
+        if offset.is_zero() || size.is_zero() {
             return Err(CytrynaError::MissingRegion);
         }
 
-        let offset = offset as usize * 0x200 - mem::size_of::<NcchHeader>();
-        let size = size as usize * 0x200;
-        Ok(&self.data[offset..][..size])
+        let offset = self.header.units_to_bytes(offset) - mem::size_of::<NcchHeader>();
+        let size = self.header.units_to_bytes(size);
+        self.data
+            .get(offset..)
+            .and_then(|data| data.get(..size))
+            .ok_or(CytrynaError::SliceTooSmall)
+    }
+    /// Returns offset and size in media units of a region, as stored in NCCH header
+    fn region_units(&self, which: NcchRegion) -> (MediaUnits, MediaUnits) {
+        let hdr = &self.header;
+        match which {
+            NcchRegion::Plain => (hdr.plain_offset, hdr.plain_size),
+            NcchRegion::Logo => (hdr.logo_offset, hdr.logo_size),
+            NcchRegion::ExeFs => (hdr.exefs_offset, hdr.exefs_size),
+            NcchRegion::RomFs => (hdr.romfs_offset, hdr.romfs_size),
+        }
+    }
+    /// Returns offset and size in bytes of a region, relative to the start of NCCH. Fails the
+    /// same way as accessing that region would
+    pub fn region_bounds(&self, which: NcchRegion) -> CytrynaResult<(usize, usize)> {
+        let (offset, size) = self.region_units(which);
+        self.region(offset, size)?;
+        Ok((self.header.units_to_bytes(offset), self.header.units_to_bytes(size)))
     }
     /// Returns a reference to "plain region"
     pub fn plain_region(&self) -> CytrynaResult<&[u8]> {
@@ -155,7 +355,9 @@ impl Ncch {
     pub fn exefs(&self) -> CytrynaResult<exefs::ExeFs> {
         let data = self.exefs_region()?;
         let alignment = mem::align_of::<exefs::ExeFsHeader>();
-        assert_eq!(0, data.as_ptr().align_offset(alignment));
+        if data.as_ptr().align_offset(alignment) != 0 {
+            return Err(CytrynaError::BadAlign);
+        }
 
         let inner = unsafe { mem::transmute(data) };
 
@@ -169,6 +371,36 @@ impl Ncch {
             inner,
         })
     }
+    /// Returns the title icon stored as "icon" file in ExeFS, decrypting it if needed
+    #[cfg(feature = "smdh")]
+    pub fn icon(&self) -> CytrynaResult<OwnedOrBorrowed<'_, Smdh>> {
+        let exefs = self.decrypted_exefs_region()?;
+        let hdr_size = mem::size_of::<exefs::ExeFsHeader>();
+        if exefs.len() < hdr_size {
+            return Err(CytrynaError::SliceTooSmall);
+        }
+        let hdr: exefs::ExeFsHeader = unsafe { ptr::read_unaligned(exefs.as_ptr().cast()) };
+        let file = hdr
+            .file_header_by_name(b"icon")
+            .ok_or(CytrynaError::MissingRegion)?;
+        let start = hdr_size + file.offset() as usize;
+        let end = start + file.size() as usize;
+
+        let data = exefs.get(start..end).ok_or(CytrynaError::SliceTooSmall)?;
+        if data.len() < Smdh::min_size() {
+            return Err(CytrynaError::SliceTooSmall);
+        }
+        Smdh::bytes_ok(data)?;
+
+        if let VecOrSlice::S(borrowed) = &exefs {
+            let borrowed: &[u8] = borrowed;
+            if data.as_ptr().align_offset(mem::align_of::<Smdh>()) == 0 {
+                return Ok(OwnedOrBorrowed::Borrowed(Smdh::cast(&borrowed[start..end])));
+            }
+        }
+        let smdh = unsafe { ptr::read_unaligned(data.as_ptr().cast()) };
+        Ok(OwnedOrBorrowed::Owned(Box::new(smdh)))
+    }
     /// Returns a decrypted Exheader stored in OwnedOrBorrowed
     pub fn exheader(&self) -> CytrynaResult<OwnedOrBorrowed<Exheader>> {
         if self.header.exheader_size == 0 {
@@ -177,24 +409,18 @@ impl Ncch {
 
         // self.header.exheader_size is a fucking lie
         let exheader_size = mem::size_of::<Exheader>();
+        let exheader_data = self
+            .data
+            .get(..exheader_size)
+            .ok_or(CytrynaError::SliceTooSmall)?;
 
         if self.is_encrypted() {
-            let x = KeyBag::global()?.get_key(KeyIndex::Slot(0x2c, KeyType::X))?;
-            let y = &self.header.sig[..0x10];
-
-            let key = crypto::keygen(*x, y.try_into().unwrap())?;
-            let iv: [u8; 0x10] = unsafe {
-                mem::transmute(Aes128Iv {
-                    title_id: self.header.program_id.swap_bytes(),
-                    ty: 1,
-                    pad: [0u8; 7],
-                })
-            };
+            let key = self.slot_key(0x2c)?;
+            let iv = self.region_iv(NcchRegionType::Exheader);
 
-            let inp = &self.data[..exheader_size];
-            let mut out = vec![0u8; inp.len()].into_boxed_slice();
+            let mut out = vec![0u8; exheader_size].into_boxed_slice();
             Aes128CtrDec::new(&key.into(), &iv.into())
-                .apply_keystream_b2b(inp, &mut out)?;
+                .apply_keystream_b2b(exheader_data, &mut out)?;
 
             unsafe {
                 let raw = Box::into_raw(out) as *mut u8 as *mut Exheader;
@@ -203,15 +429,171 @@ impl Ncch {
         } else {
             unsafe {
                 Ok(OwnedOrBorrowed::Borrowed(mem::transmute(
-                    self.data[..exheader_size].as_ptr(),
+                    exheader_data.as_ptr(),
                 )))
             }
         }
     }
+    /// Checks if the decrypted Exheader matches the hash stored in NCCH header.
+    /// Only the first `exheader_size` bytes are covered by the hash, which excludes the second
+    /// AccessControlInfo
+    pub fn verify_exheader_hash(&self) -> CytrynaResult<bool> {
+        let exheader = self.exheader()?;
+        let bytes: &[u8] = unsafe {
+            slice::from_raw_parts(
+                (&*exheader as *const Exheader).cast(),
+                mem::size_of::<Exheader>(),
+            )
+        };
+        let covered = bytes
+            .get(..self.header.exheader_size as usize)
+            .ok_or(CytrynaError::InvalidHeaderSize)?;
+
+        Ok(sha256(covered) == self.header.exheader_hash)
+    }
+    /// Returns how many media units from the start of ExeFS are covered by the superblock hash
+    #[must_use]
+    pub fn exefs_hash_size(&self) -> MediaUnits {
+        self.header.exefs_hash_size
+    }
+    /// Returns how many bytes from the start of ExeFS are covered by the superblock hash
+    #[must_use]
+    pub fn exefs_hash_size_bytes(&self) -> usize {
+        self.header.units_to_bytes(self.header.exefs_hash_size)
+    }
+    /// Checks if the decrypted start of ExeFS matches the superblock hash stored in NCCH header.
+    /// Fails if the hashed region doesn't fit within the ExeFS
+    pub fn verify_exefs_superblock(&self) -> CytrynaResult<bool> {
+        if self.header.exefs_hash_size > self.header.exefs_size {
+            return Err(CytrynaError::InvalidLength {
+                what: "exefs hash region",
+                actual: self.exefs_hash_size_bytes(),
+                expected: self.header.units_to_bytes(self.header.exefs_size),
+            });
+        }
+        let exefs = self.decrypted_exefs_region()?;
+        let covered = &exefs[..self.exefs_hash_size_bytes()];
+
+        Ok(sha256(covered) == self.header.exefs_super_hash)
+    }
     /// Returns the RomFS region data as a byte slice
     pub fn romfs_region(&self) -> CytrynaResult<&[u8]> {
         self.region(self.header.romfs_offset, self.header.romfs_size)
     }
+    /// Returns decrypted ExeFS region data, borrowing it if NCCH isn't encrypted
+    pub fn decrypted_exefs_region(&self) -> CytrynaResult<VecOrSlice<'_, u8>> {
+        let data = self.exefs_region()?;
+        if !self.is_encrypted() {
+            return Ok(VecOrSlice::S(data));
+        }
+
+        let iv = self.region_iv(NcchRegionType::ExeFs);
+        let mut out = data.to_vec();
+        Aes128CtrDec::new(&self.slot_key(0x2c)?.into(), &iv.into()).apply_keystream(&mut out);
+
+        // .code is encrypted with the secondary key, everything else with the primary one
+        if self.has_secondary_key() {
+            let hdr: exefs::ExeFsHeader = unsafe { ptr::read_unaligned(out.as_ptr().cast()) };
+            if let Some(code) = hdr.file_header_by_name(b".code") {
+                let start = mem::size_of::<exefs::ExeFsHeader>() + code.offset() as usize;
+                let end = start + code.size() as usize;
+                let file = out.get_mut(start..end).ok_or(CytrynaError::SliceTooSmall)?;
+                file.copy_from_slice(&data[start..end]);
+
+                let mut cipher = Aes128CtrDec::new(&self.secondary_key()?.into(), &iv.into());
+                cipher.seek(start);
+                cipher.apply_keystream(file);
+            }
+        }
+
+        Ok(VecOrSlice::V(out))
+    }
+    /// Returns decrypted RomFS region data, borrowing it if NCCH isn't encrypted
+    pub fn decrypted_romfs_region(&self) -> CytrynaResult<VecOrSlice<'_, u8>> {
+        let data = self.romfs_region()?;
+        if !self.is_encrypted() {
+            return Ok(VecOrSlice::S(data));
+        }
+
+        let key = self.secondary_key()?;
+        let mut out = data.to_vec();
+        Aes128CtrDec::new(&key.into(), &self.region_iv(NcchRegionType::RomFs).into())
+            .apply_keystream(&mut out);
+
+        Ok(VecOrSlice::V(out))
+    }
+    /// Decrypts ExeFS or RomFS region into a writer, a chunk at a time instead of holding the
+    /// whole decrypted region in memory. `offset` and `size` are in media units and have to
+    /// match one of these regions in NCCH header
+    #[cfg(feature = "std")]
+    pub fn decrypt_region_into(
+        &self,
+        offset: MediaUnits,
+        size: MediaUnits,
+        out: &mut impl std::io::Write,
+    ) -> CytrynaResult<()> {
+        let hdr = &self.header;
+        let region = if (offset, size) == (hdr.exefs_offset, hdr.exefs_size) {
+            NcchRegionType::ExeFs
+        } else if (offset, size) == (hdr.romfs_offset, hdr.romfs_size) {
+            NcchRegionType::RomFs
+        } else {
+            return Err(CytrynaError::InvalidRegionPosition);
+        };
+        let data = self.region(offset, size)?;
+        if !self.is_encrypted() {
+            out.write_all(data)?;
+            return Ok(());
+        }
+
+        let iv = self.region_iv(region);
+        let (key, code) = match region {
+            NcchRegionType::RomFs => (self.secondary_key()?, None),
+            _ => {
+                let key = self.slot_key(0x2c)?;
+                // .code is encrypted with the secondary key, everything else with the primary one
+                let mut code = None;
+                if self.has_secondary_key() {
+                    const HDR_SIZE: usize = mem::size_of::<exefs::ExeFsHeader>();
+                    let mut raw = [0u8; HDR_SIZE];
+                    raw.copy_from_slice(data.get(..HDR_SIZE).ok_or(CytrynaError::SliceTooSmall)?);
+                    Aes128CtrDec::new(&key.into(), &iv.into()).apply_keystream(&mut raw);
+                    let exefs: exefs::ExeFsHeader =
+                        unsafe { ptr::read_unaligned(raw.as_ptr().cast()) };
+                    if let Some(file) = exefs.file_header_by_name(b".code") {
+                        let start = HDR_SIZE + file.offset() as usize;
+                        let end = start + file.size() as usize;
+                        if end > data.len() {
+                            return Err(CytrynaError::SliceTooSmall);
+                        }
+                        code = Some((start..end, self.secondary_key()?));
+                    }
+                }
+                (key, code)
+            }
+        };
+
+        let mut cipher = Aes128CtrDec::new(&key.into(), &iv.into());
+        let mut buf = vec![0u8; DECRYPT_CHUNK_SIZE];
+        for (i, chunk) in data.chunks(DECRYPT_CHUNK_SIZE).enumerate() {
+            let pos = i * DECRYPT_CHUNK_SIZE;
+            let buf = &mut buf[..chunk.len()];
+            cipher.apply_keystream_b2b(chunk, buf)?;
+
+            if let Some((range, key)) = &code {
+                let start = range.start.max(pos);
+                let end = range.end.min(pos + chunk.len());
+                if start < end {
+                    let mut cipher = Aes128CtrDec::new(&(*key).into(), &iv.into());
+                    cipher.seek(start);
+                    let part = &mut buf[start - pos..end - pos];
+                    cipher.apply_keystream_b2b(&data[start..end], part)?;
+                }
+            }
+            out.write_all(buf)?;
+        }
+        Ok(())
+    }
     /// Returns a reference to NCCH Flags
     #[must_use]
     pub fn flags(&self) -> &NcchFlags {
@@ -219,14 +601,19 @@ impl Ncch {
     }
 }
 
-/// AES-128 Initialization Vector used in NCCH Exheader Decryption
-#[repr(C)]
-struct Aes128Iv {
-    title_id: u64,
-    ty: u8,
-    pad: [u8; 7],
+impl Verify for Ncch {
+    /// Checks the Exheader hash and ExeFS superblock hash, skipping regions that aren't present
+    fn verify(&self) -> CytrynaResult<()> {
+        if self.header.exheader_size != 0 && !self.verify_exheader_hash()? {
+            return Err(CytrynaError::InvalidHash.with_context("exheader", 0x200));
+        }
+        if !self.header.exefs_size.is_zero() && !self.verify_exefs_superblock()? {
+            let offset = self.header.units_to_bytes(self.header.exefs_offset);
+            return Err(CytrynaError::InvalidHash.with_context("exefs", offset));
+        }
+        Ok(())
+    }
 }
-assert_eq_size!([u8; 0x10], Aes128Iv);
 
 /// NCCH Extended Header
 /// <https://www.3dbrew.org/wiki/NCCH/Extended_Header>
@@ -241,6 +628,19 @@ pub struct Exheader {
 }
 assert_eq_size!([u8; 0x800], Exheader);
 
+impl Exheader {
+    /// Returns the SystemControlInfo
+    #[must_use]
+    pub fn sci(&self) -> &SystemControlInfo {
+        &self.sci
+    }
+    /// Returns the AccessControlInfo
+    #[must_use]
+    pub fn aci(&self) -> &AccessControlInfo {
+        &self.aci
+    }
+}
+
 /// Exheader SystemControlInfo
 /// <https://www.3dbrew.org/wiki/NCCH/Extended_Header#System_Control_Info>
 #[derive(Derivative, Clone)]
@@ -267,6 +667,19 @@ pub struct SystemControlInfo {
 }
 assert_eq_size!([u8; 0x200], SystemControlInfo);
 
+impl SystemControlInfo {
+    /// Returns the application title
+    #[must_use]
+    pub fn app_title(&self) -> &SizedCString<0x8> {
+        &self.app_title
+    }
+    /// Returns the remaster version of this title
+    #[must_use]
+    pub fn remaster_version(&self) -> u16 {
+        self.remaster_version
+    }
+}
+
 bitflags! {
     /// SystemControlInfo flags
     #[derive(Debug, Clone, Copy)]
@@ -298,6 +711,14 @@ pub struct AccessControlInfo {
 }
 assert_eq_size!([u8; 0x200], AccessControlInfo);
 
+impl AccessControlInfo {
+    /// Returns the ARM11 local system capabilities
+    #[must_use]
+    pub fn arm11_syscaps(&self) -> &Arm11LocalSystemCaps {
+        &self.arm11_syscaps
+    }
+}
+
 /// ARM11 Local system capabilities
 /// <https://www.3dbrew.org/wiki/NCCH/Extended_Header#ARM11_Local_System_Capabilities>
 #[derive(Derivative, Clone)]
@@ -306,7 +727,7 @@ assert_eq_size!([u8; 0x200], AccessControlInfo);
 pub struct Arm11LocalSystemCaps {
     program_id: MaybeTitleId,
     core_version: u32,
-    n3ds_sysmode: New3dsSystemMode,
+    flag2: Flag2,
     flag1: Flag1,
     flag0: Flag0,
     priority: u8,
@@ -319,6 +740,41 @@ pub struct Arm11LocalSystemCaps {
 }
 assert_eq_size!([u8; 0x170], Arm11LocalSystemCaps);
 
+impl Arm11LocalSystemCaps {
+    /// Returns the title ID low of kernel this title was built for
+    #[must_use]
+    pub fn core_version(&self) -> u32 {
+        self.core_version
+    }
+    /// Returns the Flag0 data
+    #[must_use]
+    pub fn flag0(&self) -> Flag0 {
+        self.flag0
+    }
+    /// Returns the Flag1 data, only used by New3DS
+    #[must_use]
+    pub fn flag1(&self) -> Flag1 {
+        self.flag1
+    }
+    /// Returns the Old3DS system mode, values that aren't defined on any firmware are returned as
+    /// [Old3dsSystemMode::Undefined]
+    ///
+    /// The value is decoded with the current firmware's meaning, interpreting it differently
+    /// depending on [Self::core_version] or the remaster version isn't implemented yet
+    #[must_use]
+    pub fn old3ds_system_mode(&self) -> Old3dsSystemMode {
+        self.flag0.old3ds_system_mode()
+    }
+    /// Returns the New3DS system mode
+    ///
+    /// Like [Self::old3ds_system_mode] it's decoded the same way for titles built for any
+    /// firmware
+    #[must_use]
+    pub fn new3ds_system_mode(&self) -> New3dsSystemMode {
+        self.flag2.new3ds_system_mode()
+    }
+}
+
 /// ARM11 Local system capabilities Flag0 data
 /// <https://www.3dbrew.org/wiki/NCCH/Extended_Header#Flag0>
 #[bitfield(u8)]
@@ -333,7 +789,7 @@ pub struct Flag0 {
 
 /// Stores the Old3DS system mode data
 /// <https://www.3dbrew.org/wiki/NCCH/Extended_Header#Old3DS_System_Mode>
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum Old3dsSystemMode {
     /// Prod (64MB of usable application memory) 
@@ -362,7 +818,8 @@ impl Old3dsSystemMode {
             3 => Self::Dev2_80Mb,
             4 => Self::Dev3_72Mb,
             5 => Self::Dev4_32Mb,
-            _ => panic!("value out of range for Old3dsSystemMode"),
+            // values above 5 don't have a meaning on any firmware
+            _ => Self::Undefined,
         }
     }
 }
@@ -377,9 +834,19 @@ pub struct Flag1 {
     __: u8,
 }
 
+/// ARM11 Local system capabilities Flag2 data
+/// <https://www.3dbrew.org/wiki/NCCH/Extended_Header#Flag2>
+#[bitfield(u8)]
+pub struct Flag2 {
+    #[bits(4)]
+    new3ds_system_mode: New3dsSystemMode,
+    #[bits(4)]
+    __: u8,
+}
+
 /// ARM11 Local system capabilities New3DS system mode data
 /// <https://www.3dbrew.org/wiki/NCCH/Extended_Header#New3DS_System_Mode>
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum New3dsSystemMode {
     /// Legacy(use Old3DS system mode)
@@ -390,6 +857,23 @@ pub enum New3dsSystemMode {
     Dev1_178Mb,
     /// Dev2 (124MB of usable application memory) 
     Dev2_124Mb,
+    /// Value not defined on any firmware
+    Unknown,
+}
+
+impl New3dsSystemMode {
+    const fn into_bits(self) -> u8 {
+        self as _
+    }
+    const fn from_bits(value: u8) -> Self {
+        match value {
+            0 => Self::Legacy,
+            1 => Self::Prod124Mb,
+            2 => Self::Dev1_178Mb,
+            3 => Self::Dev2_124Mb,
+            _ => Self::Unknown,
+        }
+    }
 }
 
 /// ARM11 Local system capabilities storage info
@@ -482,7 +966,7 @@ impl Arm11KernelCaps {
                     expect_nine = !expect_nine;
 
                     let bit20 = (val & 1 << 20) != 0;
-                    let addr = (val & !0xfff00000) << 16;
+                    let addr = (val & !0xfff00000) << 12;
 
                     if expect_nine {
                         KernelCap::MapMemoryRangeStart {
@@ -496,7 +980,8 @@ impl Arm11KernelCaps {
                         }
                     }
                 }
-                11 => todo!("MapIoMemoryPage"),
+                // unlike memory ranges, these map a single page and aren't paired
+                11 => KernelCap::MapIoMemoryPage((val & 0xfffff) << 12),
                 _ => continue,
             };
 
@@ -529,8 +1014,7 @@ pub enum KernelCap {
     KernelFlags(Arm11Flags),
     MapMemoryRangeStart { read_only: bool, start: u32 },
     MapMemoryRangeEnd { cacheable: bool, end: u32 },
-    MapIoMemoryPageStart,
-    MapIoMemoryPageEnd,
+    MapIoMemoryPage(u32),
 }
 
 /// ARM11 enabled syscall mask
@@ -558,31 +1042,37 @@ impl SyscallMask {
             self.mask() & (1 << (rem & 31)) != 0
         }
     }
-    //pub fn iter(&self) -> impl Iterator<Item = (u8, bool)> {
-    //    SyscallIter { mask: self.mask(), idx: self.idx(), mask_shift: 0 }
-    //}
+    /// Returns an iterator over syscall numbers enabled in this syscall mask
+    pub fn iter(&self) -> impl Iterator<Item = u8> {
+        SyscallIter {
+            mask: self.mask(),
+            idx: self.idx(),
+            mask_shift: 0,
+        }
+    }
 }
 
-/*
-pub struct SyscallIter {
+/// An iterator over syscall numbers enabled in a SyscallMask
+struct SyscallIter {
     mask: u32,
     idx: u8,
     mask_shift: u8,
 }
 
 impl Iterator for SyscallIter {
-    type Item = (u8, bool);
+    type Item = u8;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.mask_shift > 24 {
-            return None;
+        while self.mask_shift < 24 {
+            let shift = self.mask_shift;
+            self.mask_shift += 1;
+            if self.mask & (1 << shift) != 0 {
+                return Some(self.idx * 24 + shift);
+            }
         }
-
-        let num = self.idx * 24 + self.mask_shift;
-        let is_enabled = self.mask & (1 << self.mask_shift) != 0;
-        Some((num, is_enabled))
+        None
     }
-}*/
+}
 
 impl fmt::Debug for SyscallMask {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -619,12 +1109,14 @@ pub struct Arm11Flags {
 }
 
 /// ARM11 Memory Type
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum Arm11MemoryType {
     Application = 1,
     System,
     Base,
+    /// Value not defined on any firmware
+    Unknown,
 }
 impl Arm11MemoryType {
     const fn into_bits(self) -> u32 {
@@ -635,7 +1127,7 @@ impl Arm11MemoryType {
             1 => Self::Application,
             2 => Self::System,
             3 => Self::Base,
-            _ => panic!("Invalid value for Arm11MemoryType"),
+            _ => Self::Unknown,
         }
     }
 }
@@ -670,3 +1162,437 @@ bitflags! {
         const MOUNT_SDMC_WRITE = 0x200;
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{
+        Arm11Flags, Arm11KernelCaps, Arm11MemoryType, Flag0, Flag2, KernelCap, KernelCapRaw,
+        New3dsSystemMode, Ncch, NcchHeader, NcchRegion, Old3dsSystemMode, SyscallMask,
+    };
+    use crate::crypto::{
+        self, aes128_ctr::*, tests::test_keybag, KeyBag, KeyIndex, KeyType, NcchRegionType,
+    };
+    use crate::hash::sha256;
+    use crate::tests::verify_any;
+    use crate::{CytrynaError, MediaUnits, VecOrSlice};
+    use core::mem;
+
+    /// Byte buffer with alignment good enough for casting into NCCH structs
+    #[repr(C, align(16))]
+    pub(crate) struct Aligned<const SIZE: usize>(pub(crate) [u8; SIZE]);
+
+    /// Makes an unencrypted NCCH header with no regions
+    pub(crate) fn ncch_header() -> [u8; 0x200] {
+        let mut hdr = [0u8; 0x200];
+        hdr[0x100..0x104].copy_from_slice(b"NCCH");
+        // flags.options
+        hdr[0x18f] = 0x4;
+        hdr
+    }
+
+    /// Makes an unencrypted NCCH with an Exheader and an ExeFS containing a single ".code" file
+    pub(crate) fn ncch_with_exefs() -> Aligned<0xe00> {
+        let mut buf = Aligned([0u8; 0xe00]);
+        buf.0[..0x200].copy_from_slice(&ncch_header());
+        buf.0[0x180..0x184].copy_from_slice(&0x400u32.to_le_bytes());
+        // exefs at media unit 5, 2 media units long
+        buf.0[0x1a0..0x1a4].copy_from_slice(&5u32.to_le_bytes());
+        buf.0[0x1a4..0x1a8].copy_from_slice(&2u32.to_le_bytes());
+        buf.0[0x1a8..0x1ac].copy_from_slice(&1u32.to_le_bytes());
+
+        // exheader
+        buf.0[0x200..0x208].copy_from_slice(b"test\0\0\0\0");
+        let hash = sha256(&buf.0[0x200..0x600]);
+        buf.0[0x160..0x180].copy_from_slice(&hash);
+
+        // exefs header with ".code" file at offset 0, and its data
+        buf.0[0xa00..0xa08].copy_from_slice(b".code\0\0\0");
+        buf.0[0xa0c..0xa10].copy_from_slice(&0x10u32.to_le_bytes());
+        buf.0[0xc00..0xc10].fill(0xc0);
+        let hash = sha256(&buf.0[0xc00..0xc10]);
+        buf.0[0xbe0..0xc00].copy_from_slice(&hash);
+
+        buf
+    }
+
+    #[cfg(feature = "smdh")]
+    #[test]
+    fn icon_from_exefs() {
+        use crate::smdh::{tests::test_smdh, Language};
+        use crate::OwnedOrBorrowed;
+
+        let ncch = ncch_with_exefs();
+        assert!(matches!(
+            Ncch::from_slice(&ncch.0).unwrap().icon(),
+            Err(CytrynaError::MissingRegion)
+        ));
+
+        let smdh = test_smdh();
+        let mut buf = Box::new(Aligned([0u8; 0x4400]));
+        buf.0[..0xa00].copy_from_slice(&ncch.0[..0xa00]);
+        buf.0[0x1a4..0x1a8].copy_from_slice(&0x1du32.to_le_bytes());
+        // exefs header with "icon" file at offset 0, and its data
+        buf.0[0xa00..0xa08].copy_from_slice(b"icon\0\0\0\0");
+        buf.0[0xa0c..0xa10].copy_from_slice(&0x36c0u32.to_le_bytes());
+        buf.0[0xc00..0x42c0].copy_from_slice(smdh.as_bytes());
+
+        let ncch = Ncch::from_slice(&buf.0).unwrap();
+        let icon = ncch.icon().unwrap();
+        assert!(matches!(icon, OwnedOrBorrowed::Borrowed(_)));
+        assert_eq!(icon.title_strings(Language::English).short, "Short");
+    }
+
+    #[test]
+    fn verify_hashes() {
+        let mut buf = ncch_with_exefs();
+        let ncch = Ncch::from_slice(&buf.0).unwrap();
+        assert!(ncch.verify_exheader_hash().unwrap());
+
+        let exefs = ncch.exefs().unwrap();
+        let code = exefs.header().file_header_by_name(b".code").unwrap();
+        assert!(exefs.verify_file_hash(code));
+
+        // second AccessControlInfo isn't covered by the hash
+        buf.0[0x900] ^= 0xff;
+        let ncch = Ncch::from_slice(&buf.0).unwrap();
+        assert!(ncch.verify_exheader_hash().unwrap());
+
+        buf.0[0x210] ^= 0xff;
+        buf.0[0xc00] ^= 0xff;
+        let ncch = Ncch::from_slice(&buf.0).unwrap();
+        assert!(!ncch.verify_exheader_hash().unwrap());
+        let exefs = ncch.exefs().unwrap();
+        let code = exefs.header().file_header_by_name(b".code").unwrap();
+        assert!(!exefs.verify_file_hash(code));
+    }
+
+    #[test]
+    fn header_fields() {
+        let mut buf = Aligned(ncch_header());
+        buf.0[0x108..0x110].copy_from_slice(&0x0004000000123400u64.to_le_bytes());
+        buf.0[0x110..0x112].copy_from_slice(b"01");
+        buf.0[0x112..0x114].copy_from_slice(&2u16.to_le_bytes());
+        buf.0[0x118..0x120].copy_from_slice(&0x0004000000123400u64.to_le_bytes());
+        buf.0[0x150..0x15a].copy_from_slice(b"CTR-P-ABCE");
+
+        let ncch = Ncch::from_slice(&buf.0).unwrap();
+        let header = ncch.header();
+        assert_eq!(header.partition_id(), 0x0004000000123400);
+        assert_eq!(header.program_id().unwrap().to_u64(), 0x0004000000123400);
+        assert_eq!(header.maker_code().data(), b"01");
+        assert_eq!(header.version(), 2);
+        assert_eq!(header.product_code().data(), b"CTR-P-ABCE\0\0\0\0\0\0");
+    }
+
+    #[test]
+    fn region_bounds() {
+        let buf = ncch_with_exefs();
+        let ncch = Ncch::from_slice(&buf.0).unwrap();
+        let (offset, size) = ncch.region_bounds(NcchRegion::ExeFs).unwrap();
+        assert_eq!((offset, size), (5 * 0x200, 2 * 0x200));
+        assert_eq!(&buf.0[offset..][..size], ncch.exefs_region().unwrap());
+        assert!(matches!(
+            ncch.region_bounds(NcchRegion::RomFs),
+            Err(CytrynaError::MissingRegion)
+        ));
+    }
+
+    #[test]
+    fn bigger_media_units() {
+        let mut buf = Aligned([0u8; 0xc00]);
+        buf.0[..0x200].copy_from_slice(&ncch_header());
+        buf.0[0x104..0x108].copy_from_slice(&3u32.to_le_bytes());
+        // content unit size, media units are 0x400 bytes long
+        buf.0[0x18e] = 1;
+        buf.0[0x1a0..0x1a4].copy_from_slice(&2u32.to_le_bytes());
+        buf.0[0x1a4..0x1a8].copy_from_slice(&1u32.to_le_bytes());
+        buf.0[0x800..0xc00].fill(0xee);
+
+        let ncch = Ncch::from_slice(&buf.0).unwrap();
+        assert_eq!(ncch.header().media_unit_size(), 0x400);
+        assert_eq!(ncch.header().content_size_bytes(), 0xc00);
+        assert_eq!(ncch.region_bounds(NcchRegion::ExeFs).unwrap(), (0x800, 0x400));
+        assert_eq!(ncch.exefs_region().unwrap(), &[0xee; 0x400]);
+
+        let buf = ncch_with_exefs();
+        assert_eq!(Ncch::from_slice(&buf.0).unwrap().header().media_unit_size(), 0x200);
+    }
+
+    #[test]
+    fn exefs_superblock() {
+        let mut buf = ncch_with_exefs();
+        let hash = sha256(&buf.0[0xa00..0xc00]);
+        buf.0[0x1c0..0x1e0].copy_from_slice(&hash);
+        let ncch = Ncch::from_slice(&buf.0).unwrap();
+        assert_eq!(ncch.exefs_hash_size(), MediaUnits(1));
+        assert_eq!(ncch.exefs_hash_size_bytes(), 0x200);
+        assert!(ncch.verify_exefs_superblock().unwrap());
+
+        // file data isn't covered by the superblock hash
+        buf.0[0xc00] ^= 0xff;
+        let ncch = Ncch::from_slice(&buf.0).unwrap();
+        assert!(ncch.verify_exefs_superblock().unwrap());
+
+        buf.0[0xa00] ^= 0xff;
+        let ncch = Ncch::from_slice(&buf.0).unwrap();
+        assert!(!ncch.verify_exefs_superblock().unwrap());
+
+        // hashed region bigger than ExeFS
+        buf.0[0x1a8..0x1ac].copy_from_slice(&3u32.to_le_bytes());
+        let ncch = Ncch::from_slice(&buf.0).unwrap();
+        assert!(matches!(
+            ncch.verify_exefs_superblock(),
+            Err(CytrynaError::InvalidLength { .. })
+        ));
+    }
+
+    #[test]
+    fn verify_trait() {
+        let mut buf = ncch_with_exefs();
+        let hash = sha256(&buf.0[0xa00..0xc00]);
+        buf.0[0x1c0..0x1e0].copy_from_slice(&hash);
+        assert!(verify_any(Ncch::from_slice(&buf.0).unwrap()).is_ok());
+
+        buf.0[0xa00] ^= 0xff;
+        assert!(matches!(
+            verify_any(Ncch::from_slice(&buf.0).unwrap()),
+            Err(CytrynaError::ParseError { context: "exefs", offset: 0xa00, .. })
+        ));
+    }
+
+    #[test]
+    fn streamed_decryption() {
+        test_keybag();
+        const ROMFS_UNITS: u32 = 0x90;
+        let mut buf = Box::new(Aligned([0u8; 0xe00 + ROMFS_UNITS as usize * 0x200]));
+        buf.0[..0xe00].copy_from_slice(&ncch_with_exefs().0);
+        // encrypted with 7.x crypto
+        buf.0[0x18f] = 0;
+        buf.0[0x18b] = 0x1;
+        buf.0[0x1b0..0x1b4].copy_from_slice(&7u32.to_le_bytes());
+        buf.0[0x1b4..0x1b8].copy_from_slice(&ROMFS_UNITS.to_le_bytes());
+        for (i, byte) in buf.0[0xe00..].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        // "encrypt" ExeFS header, so that .code can be found after decrypting it
+        let ncch = Ncch::from_slice(&buf.0).unwrap();
+        let exefs_header = ncch.decrypted_exefs_region().unwrap().as_slice()[..0x200].to_vec();
+        buf.0[0xa00..0xc00].copy_from_slice(&exefs_header);
+
+        let ncch = Ncch::from_slice(&buf.0).unwrap();
+        let hdr = ncch.header();
+        let mut romfs = Vec::new();
+        ncch.decrypt_region_into(hdr.romfs_offset, hdr.romfs_size, &mut romfs)
+            .unwrap();
+        assert_eq!(romfs.len(), ROMFS_UNITS as usize * 0x200);
+        assert_eq!(romfs, ncch.decrypted_romfs_region().unwrap().as_slice());
+
+        let mut exefs = Vec::new();
+        ncch.decrypt_region_into(hdr.exefs_offset, hdr.exefs_size, &mut exefs)
+            .unwrap();
+        let expected = ncch.decrypted_exefs_region().unwrap();
+        assert_eq!(&exefs[..0x8], b".code\0\0\0");
+        assert_eq!(exefs, expected.as_slice());
+
+        assert!(matches!(
+            ncch.decrypt_region_into(hdr.romfs_offset, MediaUnits(1), &mut Vec::new()),
+            Err(CytrynaError::InvalidRegionPosition)
+        ));
+    }
+
+    #[test]
+    fn seeded_romfs() {
+        use crate::crypto::tests::{TEST_SEED, TEST_SEED_TITLE};
+
+        test_keybag();
+        let mut buf = Aligned([0u8; 0x400]);
+        buf.0[..0x200].copy_from_slice(&ncch_header());
+        buf.0[0x118..0x120].copy_from_slice(&TEST_SEED_TITLE.to_le_bytes());
+        buf.0[0x18f] = 0;
+        buf.0[0x1b0..0x1b4].copy_from_slice(&1u32.to_le_bytes());
+        buf.0[0x1b4..0x1b8].copy_from_slice(&1u32.to_le_bytes());
+        let unseeded = Ncch::from_slice(&buf.0).unwrap();
+        assert!(!unseeded.uses_seed_crypto());
+        let unseeded = unseeded.decrypted_romfs_region().unwrap().as_slice().to_vec();
+
+        buf.0[0x18f] = 0x20;
+        let ncch = Ncch::from_slice(&buf.0).unwrap();
+        assert!(ncch.uses_seed_crypto());
+        let romfs = ncch.decrypted_romfs_region().unwrap();
+        assert_ne!(romfs.as_slice(), unseeded);
+
+        let key_y = crypto::seeded_key_y([0; 0x10], TEST_SEED);
+        let key = crypto::keygen([0x2c; 0x10], key_y).unwrap();
+        let iv = crypto::ncch_iv(TEST_SEED_TITLE, NcchRegionType::RomFs);
+        let mut expected = vec![0u8; 0x200];
+        Aes128CtrDec::new(&key.into(), &iv.into()).apply_keystream(&mut expected);
+        assert_eq!(romfs.as_slice(), expected);
+
+        // no known seed for this title
+        buf.0[0x118..0x120].copy_from_slice(&0x0004000000123400u64.to_le_bytes());
+        let ncch = Ncch::from_slice(&buf.0).unwrap();
+        assert!(matches!(
+            ncch.decrypted_romfs_region(),
+            Err(CytrynaError::MissingKey(KeyIndex::Seed(0x0004000000123400)))
+        ));
+        assert_eq!(
+            ncch.missing_keys(KeyBag::global().unwrap()).unwrap(),
+            [KeyIndex::Seed(0x0004000000123400)]
+        );
+    }
+
+    #[test]
+    fn romfs_secondary_key() {
+        let mut buf = Aligned(ncch_header());
+        // 7.x crypto, but not encrypted at all
+        buf.0[0x18b] = 0x1;
+        assert!(!Ncch::from_slice(&buf.0).unwrap().romfs_uses_secondary_key());
+
+        buf.0[0x18f] = 0;
+        assert!(Ncch::from_slice(&buf.0).unwrap().romfs_uses_secondary_key());
+
+        // original crypto uses slot 0x2C for everything
+        buf.0[0x18b] = 0;
+        assert!(!Ncch::from_slice(&buf.0).unwrap().romfs_uses_secondary_key());
+    }
+
+    #[test]
+    fn required_keys() {
+        let mut buf = Aligned(ncch_header());
+        let ncch = Ncch::from_slice(&buf.0).unwrap();
+        assert!(ncch.required_keys().unwrap().is_empty());
+
+        // encrypted, using 7.x crypto
+        buf.0[0x18f] = 0;
+        buf.0[0x18b] = 0x1;
+        let ncch = Ncch::from_slice(&buf.0).unwrap();
+        assert_eq!(
+            ncch.required_keys().unwrap(),
+            [
+                KeyIndex::Generator,
+                KeyIndex::Slot(0x2c, KeyType::X),
+                KeyIndex::Slot(0x25, KeyType::X)
+            ]
+        );
+
+        let mut bag = KeyBag::new();
+        bag.set_key(KeyIndex::Generator, [0; 0x10]);
+        bag.set_key(KeyIndex::Slot(0x2c, KeyType::X), [0; 0x10]);
+        assert_eq!(
+            ncch.missing_keys(&bag).unwrap(),
+            [KeyIndex::Slot(0x25, KeyType::X)]
+        );
+    }
+
+    #[test]
+    fn header_only_ncch() {
+        let mut buf = Aligned(ncch_header());
+        // exefs at media unit 5, 1 media unit long
+        buf.0[0x1a0..0x1a4].copy_from_slice(&5u32.to_le_bytes());
+        buf.0[0x1a4..0x1a8].copy_from_slice(&1u32.to_le_bytes());
+        buf.0[0x180..0x184].copy_from_slice(&0x400u32.to_le_bytes());
+
+        let ncch = Ncch::from_slice(&buf.0).unwrap();
+        assert!(matches!(ncch.plain_region(), Err(CytrynaError::MissingRegion)));
+        assert!(matches!(ncch.exefs_region(), Err(CytrynaError::SliceTooSmall)));
+        assert!(matches!(ncch.exheader(), Err(CytrynaError::SliceTooSmall)));
+
+        let short = &buf.0[..mem::size_of::<NcchHeader>() - 1];
+        assert!(matches!(Ncch::from_slice(short), Err(CytrynaError::SliceTooSmall)));
+    }
+
+    #[test]
+    fn unaligned_ncch() {
+        let mut buf = Aligned([0u8; 0x201]);
+        buf.0[1..].copy_from_slice(&ncch_header());
+        assert!(matches!(Ncch::from_slice(&buf.0[1..]), Err(CytrynaError::BadAlign)));
+        assert!(Ncch::from_slice_owned(&buf.0[1..]).is_ok());
+    }
+
+    #[test]
+    fn decrypted_regions_borrow_without_crypto() {
+        let mut buf = Aligned([0u8; 0x600]);
+        buf.0[..0x200].copy_from_slice(&ncch_header());
+        // exefs at media unit 1, romfs at media unit 2
+        buf.0[0x1a0..0x1a4].copy_from_slice(&1u32.to_le_bytes());
+        buf.0[0x1a4..0x1a8].copy_from_slice(&1u32.to_le_bytes());
+        buf.0[0x1b0..0x1b4].copy_from_slice(&2u32.to_le_bytes());
+        buf.0[0x1b4..0x1b8].copy_from_slice(&1u32.to_le_bytes());
+        buf.0[0x200..0x400].fill(0xaa);
+        buf.0[0x400..0x600].fill(0x55);
+
+        let ncch = Ncch::from_slice(&buf.0).unwrap();
+        let exefs = ncch.decrypted_exefs_region().unwrap();
+        let romfs = ncch.decrypted_romfs_region().unwrap();
+
+        assert!(matches!(exefs, VecOrSlice::S(_)));
+        assert!(matches!(romfs, VecOrSlice::S(_)));
+        assert_eq!(exefs.as_ptr(), ncch.exefs_region().unwrap().as_ptr());
+        assert_eq!(&*romfs, &buf.0[0x400..0x600]);
+    }
+
+    #[test]
+    fn decode_kernel_caps() {
+        let mut caps = Arm11KernelCaps {
+            descriptors: core::array::from_fn(|_| KernelCapRaw(0xffffffff)),
+            _reserved0: [0; 0x10],
+        };
+        caps.descriptors[0] = KernelCapRaw(0xff91f000);
+        caps.descriptors[1] = KernelCapRaw(0xff81f001);
+        caps.descriptors[2] = KernelCapRaw(0xffe1ec40);
+        caps.descriptors[3] = KernelCapRaw(0xffe1ec41);
+
+        let decoded = caps.decode_descriptors();
+        assert_eq!(decoded.len(), 4);
+        assert!(matches!(
+            decoded[0],
+            KernelCap::MapMemoryRangeStart { read_only: true, start: 0x1f000000 }
+        ));
+        assert!(matches!(
+            decoded[1],
+            KernelCap::MapMemoryRangeEnd { cacheable: false, end: 0x1f000fff }
+        ));
+        assert!(matches!(decoded[2], KernelCap::MapIoMemoryPage(0x1ec40000)));
+        assert!(matches!(decoded[3], KernelCap::MapIoMemoryPage(0x1ec41000)));
+    }
+
+    #[test]
+    fn remaster_version() {
+        let mut buf = ncch_with_exefs();
+        buf.0[0x20e..0x210].copy_from_slice(&0x0102u16.to_le_bytes());
+        let ncch = Ncch::from_slice(&buf.0).unwrap();
+        assert_eq!(ncch.exheader().unwrap().sci().remaster_version(), 0x0102);
+    }
+
+    #[test]
+    fn old3ds_system_mode_out_of_range() {
+        let flag0 = Flag0::from(0x21u8);
+        assert_eq!(flag0.ideal_proccessor(), 1);
+        assert_eq!(flag0.old3ds_system_mode(), Old3dsSystemMode::Dev1_96Mb);
+
+        assert_eq!(Flag0::from(0xf0u8).old3ds_system_mode(), Old3dsSystemMode::Undefined);
+    }
+
+    #[test]
+    fn invalid_mode_and_memory_type() {
+        let flag2 = Flag2::from(0x0au8);
+        assert_eq!(flag2.new3ds_system_mode(), New3dsSystemMode::Unknown);
+        assert_eq!(Flag2::from(0x2u8).new3ds_system_mode(), New3dsSystemMode::Dev1_178Mb);
+
+        let flags = Arm11Flags::from(0xf00);
+        assert_eq!(flags.memory_type(), Arm11MemoryType::Unknown);
+        assert_eq!(Arm11Flags::from(0x0).memory_type(), Arm11MemoryType::Unknown);
+        assert_eq!(Arm11Flags::from(0x100).memory_type(), Arm11MemoryType::Application);
+        // decoding doesn't panic on any of these
+        let _ = format!("{flag2:?} {flags:?}");
+    }
+
+    #[test]
+    fn syscall_mask_iter() {
+        let mask = SyscallMask::new()
+            .with_idx(2)
+            .with_mask(1 << 0 | 1 << 5 | 1 << 23);
+        assert_eq!(mask.iter().collect::<Vec<_>>(), [48, 53, 71]);
+        assert_eq!(SyscallMask::new().iter().count(), 0);
+    }
+}
@@ -1,7 +1,17 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::{mem, ptr};
+
 use crate::string::SizedCString;
+use crate::{align_up, CytrynaError, CytrynaResult};
 
 use static_assertions::assert_eq_size;
 
+/// Marks an unused offset in RomFS metadata
+const NONE: u32 = 0xffff_ffff;
+
+/// RomFS IVFC header
+/// <https://www.3dbrew.org/wiki/RomFS#IVFC_Header>
 #[repr(C, packed)]
 pub struct RomfsHeader {
     magic: SizedCString<4>,
@@ -23,3 +33,376 @@ pub struct RomfsHeader {
 }
 
 assert_eq_size!([u8; 0x5c], RomfsHeader);
+
+/// RomFS Level 3 header
+/// <https://www.3dbrew.org/wiki/RomFS#Level_3_Format>
+#[repr(C)]
+struct Level3Header {
+    header_size: u32,
+    dir_hash_offset: u32,
+    dir_hash_size: u32,
+    dir_meta_offset: u32,
+    dir_meta_size: u32,
+    file_hash_offset: u32,
+    file_hash_size: u32,
+    file_meta_offset: u32,
+    file_meta_size: u32,
+    file_data_offset: u32,
+}
+assert_eq_size!([u8; 0x28], Level3Header);
+
+/// Decrypted RomFS data, parsed up to its Level 3 partition
+/// <https://www.3dbrew.org/wiki/RomFS>
+#[derive(Debug, Clone, Copy)]
+pub struct Romfs<'a> {
+    dir_meta: &'a [u8],
+    file_meta: &'a [u8],
+    file_data: &'a [u8],
+}
+
+impl<'a> Romfs<'a> {
+    /// Parses decrypted RomFS data
+    pub fn from_bytes(bytes: &'a [u8]) -> CytrynaResult<Self> {
+        if bytes.len() < mem::size_of::<RomfsHeader>() {
+            return Err(CytrynaError::SliceTooSmall);
+        }
+        if bytes[..4] != *b"IVFC" {
+            return Err(CytrynaError::InvalidMagic);
+        }
+
+        let hdr: RomfsHeader = unsafe { ptr::read_unaligned(bytes.as_ptr().cast()) };
+        if hdr.magic_number != 0x10000 {
+            return Err(CytrynaError::UnsupportedHeaderVersion);
+        }
+        if hdr.master_hash_size as usize > bytes.len() {
+            return Err(CytrynaError::SliceTooSmall);
+        }
+        let block_size = 1u32
+            .checked_shl(hdr.lv3_block_size)
+            .ok_or(CytrynaError::InvalidHeaderSize)?;
+
+        // level 3 follows the IVFC header and master hash, aligned to its block size
+        let lv3_offset = align_up(0x60 + hdr.master_hash_size, block_size) as usize;
+        let lv3 = bytes.get(lv3_offset..).ok_or(CytrynaError::SliceTooSmall)?;
+        if lv3.len() < mem::size_of::<Level3Header>() {
+            return Err(CytrynaError::SliceTooSmall);
+        }
+
+        let lv3_hdr: Level3Header = unsafe { ptr::read_unaligned(lv3.as_ptr().cast()) };
+        if lv3_hdr.header_size as usize != mem::size_of::<Level3Header>() {
+            return Err(CytrynaError::InvalidHeaderSize);
+        }
+        let region = |offset: u32, size: u32| {
+            lv3.get(offset as usize..)
+                .and_then(|data| data.get(..size as usize))
+                .ok_or(CytrynaError::SliceTooSmall)
+        };
+
+        Ok(Self {
+            dir_meta: region(lv3_hdr.dir_meta_offset, lv3_hdr.dir_meta_size)?,
+            file_meta: region(lv3_hdr.file_meta_offset, lv3_hdr.file_meta_size)?,
+            file_data: lv3
+                .get(lv3_hdr.file_data_offset as usize..)
+                .ok_or(CytrynaError::SliceTooSmall)?,
+        })
+    }
+    /// Returns the root directory
+    pub fn root_dir(&self) -> CytrynaResult<DirMeta> {
+        self.dir_at(0).ok_or(CytrynaError::MissingRegion)
+    }
+    /// Returns an iterator over subdirectories of a directory.
+    /// Iteration stops at the first entry that points outside of metadata table
+    #[must_use]
+    pub fn subdirs(&self, dir: &DirMeta) -> DirIter<'a> {
+        DirIter {
+            romfs: *self,
+            next: dir.child,
+            remaining: self.dir_meta.len() / DIR_META_SIZE,
+        }
+    }
+    /// Returns an iterator over files in a directory.
+    /// Iteration stops at the first entry that points outside of metadata or data region
+    #[must_use]
+    pub fn files(&self, dir: &DirMeta) -> FileIter<'a> {
+        FileIter {
+            romfs: *self,
+            next: dir.file,
+            remaining: self.file_meta.len() / FILE_META_SIZE,
+        }
+    }
+    /// Returns data of a file
+    #[must_use]
+    pub fn file_data(&self, file: &FileMeta) -> &'a [u8] {
+        &self.file_data[file.data_offset as usize..][..file.data_size as usize]
+    }
+    fn dir_at(&self, offset: u32) -> Option<DirMeta> {
+        let entry = self.dir_meta.get(offset as usize..)?;
+        let name_len = read_u32(entry, 0x14)? as usize;
+        let name = entry.get(DIR_META_SIZE..)?.get(..name_len)?;
+
+        Some(DirMeta {
+            offset,
+            parent: read_u32(entry, 0x0)?,
+            sibling: read_u32(entry, 0x4)?,
+            child: read_u32(entry, 0x8)?,
+            file: read_u32(entry, 0xc)?,
+            name: utf16_name(name),
+        })
+    }
+    fn file_at(&self, offset: u32) -> Option<FileMeta> {
+        let entry = self.file_meta.get(offset as usize..)?;
+        let name_len = read_u32(entry, 0x1c)? as usize;
+        let name = entry.get(FILE_META_SIZE..)?.get(..name_len)?;
+        let data_offset = read_u64(entry, 0x8)?;
+        let data_size = read_u64(entry, 0x10)?;
+
+        if data_offset.checked_add(data_size)? > self.file_data.len() as u64 {
+            return None;
+        }
+
+        Some(FileMeta {
+            offset,
+            parent: read_u32(entry, 0x0)?,
+            sibling: read_u32(entry, 0x4)?,
+            data_offset,
+            data_size,
+            name: utf16_name(name),
+        })
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().unwrap()))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(bytes.get(offset..offset + 8)?.try_into().unwrap()))
+}
+
+fn utf16_name(bytes: &[u8]) -> String {
+    let data: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    String::from_utf16_lossy(&data)
+}
+
+/// Size of directory metadata, excluding the name
+const DIR_META_SIZE: usize = 0x18;
+/// Size of file metadata, excluding the name
+const FILE_META_SIZE: usize = 0x20;
+
+/// RomFS Directory Metadata
+/// <https://www.3dbrew.org/wiki/RomFS#Directory_Metadata_Structure>
+#[derive(Debug, Clone)]
+pub struct DirMeta {
+    offset: u32,
+    parent: u32,
+    sibling: u32,
+    child: u32,
+    file: u32,
+    name: String,
+}
+
+impl DirMeta {
+    /// Returns offset of this entry in directory metadata table
+    #[must_use]
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+    /// Returns offset of the parent directory
+    #[must_use]
+    pub fn parent(&self) -> u32 {
+        self.parent
+    }
+    /// Returns the directory name, empty for root directory
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// RomFS File Metadata
+/// <https://www.3dbrew.org/wiki/RomFS#File_Metadata_Structure>
+#[derive(Debug, Clone)]
+pub struct FileMeta {
+    offset: u32,
+    parent: u32,
+    sibling: u32,
+    data_offset: u64,
+    data_size: u64,
+    name: String,
+}
+
+impl FileMeta {
+    /// Returns offset of this entry in file metadata table
+    #[must_use]
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+    /// Returns offset of the parent directory
+    #[must_use]
+    pub fn parent(&self) -> u32 {
+        self.parent
+    }
+    /// Returns the file size
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.data_size
+    }
+    /// Returns the file name
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// An iterator over directories, following the sibling chain
+#[derive(Debug, Clone)]
+pub struct DirIter<'a> {
+    romfs: Romfs<'a>,
+    next: u32,
+    // guards against sibling chains that loop
+    remaining: usize,
+}
+
+impl Iterator for DirIter<'_> {
+    type Item = DirMeta;
+
+    fn next(&mut self) -> Option<DirMeta> {
+        if self.next == NONE || self.remaining == 0 {
+            return None;
+        }
+        let dir = self.romfs.dir_at(self.next)?;
+        self.next = dir.sibling;
+        self.remaining -= 1;
+        Some(dir)
+    }
+}
+
+/// An iterator over files, following the sibling chain
+#[derive(Debug, Clone)]
+pub struct FileIter<'a> {
+    romfs: Romfs<'a>,
+    next: u32,
+    // guards against sibling chains that loop
+    remaining: usize,
+}
+
+impl Iterator for FileIter<'_> {
+    type Item = FileMeta;
+
+    fn next(&mut self) -> Option<FileMeta> {
+        if self.next == NONE || self.remaining == 0 {
+            return None;
+        }
+        let file = self.romfs.file_at(self.next)?;
+        self.next = file.sibling;
+        self.remaining -= 1;
+        Some(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Romfs, NONE};
+
+    fn name_bytes(name: &str) -> Vec<u8> {
+        let mut ret: Vec<u8> = name.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        ret.resize((ret.len() + 3) & !3, 0);
+        ret
+    }
+
+    fn dir_entry(parent: u32, sibling: u32, child: u32, file: u32, name: &str) -> Vec<u8> {
+        let mut ret = Vec::new();
+        for val in [parent, sibling, child, file, NONE, name.len() as u32 * 2] {
+            ret.extend_from_slice(&val.to_le_bytes());
+        }
+        ret.extend(name_bytes(name));
+        ret
+    }
+
+    fn file_entry(parent: u32, sibling: u32, offset: u64, size: u64, name: &str) -> Vec<u8> {
+        let mut ret = Vec::new();
+        ret.extend_from_slice(&parent.to_le_bytes());
+        ret.extend_from_slice(&sibling.to_le_bytes());
+        ret.extend_from_slice(&offset.to_le_bytes());
+        ret.extend_from_slice(&size.to_le_bytes());
+        ret.extend_from_slice(&NONE.to_le_bytes());
+        ret.extend_from_slice(&(name.len() as u32 * 2).to_le_bytes());
+        ret.extend(name_bytes(name));
+        ret
+    }
+
+    /// Makes a RomFS with "/a.txt" and "/sub/b" files
+    pub(crate) fn test_romfs() -> Vec<u8> {
+        let sub_offset = 0x18;
+        let mut dir_meta = dir_entry(0, NONE, sub_offset, 0, "");
+        dir_meta.extend(dir_entry(0, NONE, NONE, 0x2c, "sub"));
+
+        let mut file_meta = file_entry(0, NONE, 0, 5, "a.txt");
+        file_meta.extend(file_entry(sub_offset, NONE, 8, 3, "b"));
+
+        let dir_hash = [0u8; 4];
+        let file_hash = [0u8; 4];
+        let dir_hash_offset = 0x28;
+        let dir_meta_offset = dir_hash_offset + dir_hash.len();
+        let file_hash_offset = dir_meta_offset + dir_meta.len();
+        let file_meta_offset = file_hash_offset + file_hash.len();
+        let file_data_offset = (file_meta_offset + file_meta.len() + 0xf) & !0xf;
+
+        let mut lv3 = Vec::new();
+        for val in [
+            0x28,
+            dir_hash_offset,
+            dir_hash.len(),
+            dir_meta_offset,
+            dir_meta.len(),
+            file_hash_offset,
+            file_hash.len(),
+            file_meta_offset,
+            file_meta.len(),
+            file_data_offset,
+        ] {
+            lv3.extend_from_slice(&(val as u32).to_le_bytes());
+        }
+        lv3.extend_from_slice(&dir_hash);
+        lv3.extend(dir_meta);
+        lv3.extend_from_slice(&file_hash);
+        lv3.extend(file_meta);
+        lv3.resize(file_data_offset, 0);
+        lv3.extend_from_slice(b"hello\0\0\0bye");
+
+        let mut romfs = vec![0u8; 0x60];
+        romfs[..4].copy_from_slice(b"IVFC");
+        romfs[0x4..0x8].copy_from_slice(&0x10000u32.to_le_bytes());
+        // level 3 block size of 0x10 bytes
+        romfs[0x4c..0x50].copy_from_slice(&4u32.to_le_bytes());
+        romfs.extend(lv3);
+        romfs
+    }
+
+    #[test]
+    fn list_files() {
+        let data = test_romfs();
+        let romfs = Romfs::from_bytes(&data).unwrap();
+        let root = romfs.root_dir().unwrap();
+        assert_eq!(root.name(), "");
+
+        let files: Vec<_> = romfs.files(&root).collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name(), "a.txt");
+        assert_eq!(romfs.file_data(&files[0]), b"hello");
+
+        let dirs: Vec<_> = romfs.subdirs(&root).collect();
+        assert_eq!(dirs.len(), 1);
+        assert_eq!(dirs[0].name(), "sub");
+        assert_eq!(romfs.subdirs(&dirs[0]).count(), 0);
+
+        let files: Vec<_> = romfs.files(&dirs[0]).collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name(), "b");
+        assert_eq!(files[0].parent(), dirs[0].offset());
+        assert_eq!(romfs.file_data(&files[0]), b"bye");
+    }
+}
@@ -0,0 +1,346 @@
+use core::mem;
+
+use crate::ncch::Ncch;
+use crate::string::SizedCString;
+use crate::{CytrynaError, CytrynaResult, FromBytes, MediaUnits};
+
+use derivative::Derivative;
+use static_assertions::assert_eq_size;
+
+/// NCSD Header data
+/// <https://www.3dbrew.org/wiki/NCSD#NCSD_header>
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+#[repr(C)]
+pub struct NcsdHeader {
+    #[derivative(Debug = "ignore")]
+    sig: [u8; 0x100],
+    magic: SizedCString<4>,
+    image_size: MediaUnits,
+    media_id: [u8; 0x8],
+    fs_types: [u8; 0x8],
+    crypt_types: [u8; 0x8],
+    partitions: [PartitionEntry; 0x8],
+    #[derivative(Debug = "ignore")]
+    _unused0: [u8; 0x28],
+    partition_flags: [u8; 0x8],
+    partition_ids: [[u8; 0x8]; 0x8],
+    #[derivative(Debug = "ignore")]
+    _unused1: [u8; 0x30],
+}
+assert_eq_size!([u8; 0x200], NcsdHeader);
+
+impl NcsdHeader {
+    /// Returns size of the whole NCSD image in media units
+    #[must_use]
+    pub fn image_size(&self) -> MediaUnits {
+        self.image_size
+    }
+    /// Returns the partition table
+    #[must_use]
+    pub fn partitions(&self) -> &[PartitionEntry; 0x8] {
+        &self.partitions
+    }
+    /// Returns an iterator over filesystem and crypto types of all partitions, in the same order
+    /// as the partition table
+    pub fn partition_flags(&self) -> impl Iterator<Item = NcsdPartitionFlags> + '_ {
+        self.fs_types
+            .iter()
+            .zip(self.crypt_types.iter())
+            .map(|(&fs_type, &crypt_type)| NcsdPartitionFlags {
+                fs_type,
+                crypt_type,
+            })
+    }
+}
+
+/// Filesystem and crypto type of a single NCSD partition
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NcsdPartitionFlags {
+    fs_type: u8,
+    crypt_type: u8,
+}
+
+impl NcsdPartitionFlags {
+    /// Returns the filesystem type of partition
+    #[must_use]
+    pub fn fs_type(&self) -> PartitionFsType {
+        PartitionFsType::from_bits(self.fs_type)
+    }
+    /// Returns the crypto type of partition
+    #[must_use]
+    pub fn crypt_type(&self) -> PartitionCryptType {
+        PartitionCryptType::from_bits(self.crypt_type)
+    }
+}
+
+/// NCSD partition filesystem type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PartitionFsType {
+    None = 0,
+    Normal,
+    Firm = 3,
+    AgbFirmSave,
+    /// Value not defined on any firmware
+    Unknown,
+}
+
+impl PartitionFsType {
+    const fn from_bits(value: u8) -> Self {
+        match value {
+            0 => Self::None,
+            1 => Self::Normal,
+            3 => Self::Firm,
+            4 => Self::AgbFirmSave,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// NCSD partition crypto type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PartitionCryptType {
+    Initial = 0,
+    FixedKey,
+    NoCrypto,
+    /// Value not defined on any firmware
+    Unknown,
+}
+
+impl PartitionCryptType {
+    const fn from_bits(value: u8) -> Self {
+        match value {
+            0 => Self::Initial,
+            1 => Self::FixedKey,
+            2 => Self::NoCrypto,
+            _ => Self::Unknown,
+        }
+    }
+    /// Checks if partition data is encrypted
+    #[must_use]
+    pub fn is_encrypted(self) -> bool {
+        self != Self::NoCrypto
+    }
+}
+
+/// NCSD partition table entry
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct PartitionEntry {
+    offset: MediaUnits,
+    size: MediaUnits,
+}
+assert_eq_size!([u8; 0x8], PartitionEntry);
+
+impl PartitionEntry {
+    /// Returns offset of partition in media units
+    #[must_use]
+    pub fn offset(&self) -> MediaUnits {
+        self.offset
+    }
+    /// Returns size of partition in media units
+    #[must_use]
+    pub fn size(&self) -> MediaUnits {
+        self.size
+    }
+}
+
+/// NCSD image, used by cartridge dumps and NAND
+/// <https://www.3dbrew.org/wiki/NCSD>
+#[repr(C)]
+pub struct Ncsd {
+    header: NcsdHeader,
+    data: [u8],
+}
+
+impl FromBytes for Ncsd {
+    fn min_size() -> usize {
+        mem::size_of::<NcsdHeader>()
+    }
+    fn bytes_ok(bytes: &[u8]) -> CytrynaResult<()> {
+        if bytes.len() < Self::min_size() {
+            return Err(CytrynaError::SliceTooSmall);
+        }
+        if bytes.as_ptr().align_offset(mem::align_of::<NcsdHeader>()) != 0 {
+            return Err(CytrynaError::BadAlign);
+        }
+        if bytes[0x100..0x104] != *b"NCSD" {
+            return Err(CytrynaError::InvalidMagic);
+        }
+        Ok(())
+    }
+    fn cast(bytes: &[u8]) -> &Self {
+        // DST length is the length of trailing data, not the whole image
+        let data_len = bytes.len() - mem::size_of::<NcsdHeader>();
+        unsafe { mem::transmute(&bytes[..data_len]) }
+    }
+}
+
+impl Ncsd {
+    /// Returns a reference to NCSD Header
+    #[must_use]
+    pub fn header(&self) -> &NcsdHeader {
+        &self.header
+    }
+    /// Returns partition data, checking that it lies within the NCSD image
+    pub fn partition_data(&self, idx: usize) -> CytrynaResult<&[u8]> {
+        let entry = self
+            .header
+            .partitions
+            .get(idx)
+            .ok_or(CytrynaError::MissingRegion)?;
+        if entry.size.is_zero() {
+            return Err(CytrynaError::MissingRegion);
+        }
+
+        let offset = entry
+            .offset
+            .bytes()
+            .checked_sub(mem::size_of::<NcsdHeader>())
+            .ok_or(CytrynaError::InvalidRegionPosition)?;
+        let size = entry.size.bytes();
+        self.data
+            .get(offset..)
+            .and_then(|data| data.get(..size))
+            .ok_or(CytrynaError::InvalidRegionPosition)
+    }
+    /// Returns the NCCH stored in a partition
+    pub fn partition(&self, idx: usize) -> CytrynaResult<&Ncch> {
+        let data = self.partition_data(idx)?;
+        let offset = self.header.partitions[idx].offset.bytes();
+        Ncch::from_slice(data).map_err(|e| e.with_context("ncch partition", offset))
+    }
+    /// Returns an iterator over NCCHs of partitions present in the partition table, along with
+    /// their indices
+    #[must_use]
+    pub fn partitions(&self) -> PartitionIter<'_> {
+        PartitionIter { ncsd: self, idx: 0 }
+    }
+}
+
+/// Iterator over present NCSD partitions
+pub struct PartitionIter<'a> {
+    ncsd: &'a Ncsd,
+    idx: usize,
+}
+
+impl<'a> Iterator for PartitionIter<'a> {
+    type Item = CytrynaResult<(usize, &'a Ncch)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let partitions = &self.ncsd.header.partitions;
+        while let Some(entry) = partitions.get(self.idx) {
+            let idx = self.idx;
+            self.idx += 1;
+            if !entry.size.is_zero() {
+                return Some(self.ncsd.partition(idx).map(|ncch| (idx, ncch)));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ncsd, PartitionCryptType, PartitionFsType};
+    use crate::ncch::tests::{ncch_header, ncch_with_exefs, Aligned};
+    use crate::{CytrynaError, FromBytes, MediaUnits};
+    use core::mem;
+
+    /// Makes an NCSD with the test NCCH in partition 0
+    fn test_ncsd() -> Aligned<0x1000> {
+        let mut buf = Aligned([0u8; 0x1000]);
+        buf.0[0x100..0x104].copy_from_slice(b"NCSD");
+        buf.0[0x104..0x108].copy_from_slice(&8u32.to_le_bytes());
+        // partition 0 at media unit 1, 7 media units long
+        buf.0[0x120..0x124].copy_from_slice(&1u32.to_le_bytes());
+        buf.0[0x124..0x128].copy_from_slice(&7u32.to_le_bytes());
+        buf.0[0x200..].copy_from_slice(&ncch_with_exefs().0);
+        buf
+    }
+
+    #[test]
+    fn partition() {
+        let mut buf = test_ncsd();
+        let ncsd = Ncsd::from_bytes(&buf.0).unwrap();
+        let ncch = ncsd.partition(0).unwrap();
+        let exheader = ncch.exheader().unwrap();
+        assert_eq!(exheader.sci().app_title().data(), b"test\0\0\0\0");
+        assert!(matches!(
+            ncsd.partition(1),
+            Err(CytrynaError::MissingRegion)
+        ));
+        assert!(matches!(
+            ncsd.partition(8),
+            Err(CytrynaError::MissingRegion)
+        ));
+
+        // partition going past the end of image
+        buf.0[0x124..0x128].copy_from_slice(&8u32.to_le_bytes());
+        let ncsd = Ncsd::from_bytes(&buf.0).unwrap();
+        assert!(matches!(
+            ncsd.partition(0),
+            Err(CytrynaError::InvalidRegionPosition)
+        ));
+    }
+
+    #[test]
+    fn two_partitions() {
+        let mut buf = Aligned([0u8; 0x1800]);
+        buf.0[..0x1000].copy_from_slice(&test_ncsd().0);
+        buf.0[0x104..0x108].copy_from_slice(&12u32.to_le_bytes());
+        // partition 2 at media unit 8, 4 media units long
+        buf.0[0x130..0x134].copy_from_slice(&8u32.to_le_bytes());
+        buf.0[0x134..0x138].copy_from_slice(&4u32.to_le_bytes());
+        buf.0[0x1000..0x1200].copy_from_slice(&ncch_header());
+
+        let ncsd = Ncsd::from_bytes(&buf.0).unwrap();
+        let entries = ncsd.header().partitions();
+        assert_eq!(
+            (entries[0].offset(), entries[0].size()),
+            (MediaUnits(1), MediaUnits(7))
+        );
+        assert_eq!(
+            (entries[2].offset(), entries[2].size()),
+            (MediaUnits(8), MediaUnits(4))
+        );
+
+        let found: Vec<_> = ncsd.partitions().map(|p| p.unwrap()).collect();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].0, 0);
+        assert_eq!(found[1].0, 2);
+        let base = buf.0.as_ptr() as usize;
+        assert_eq!(found[0].1 as *const _ as *const u8 as usize - base, 0x200);
+        assert_eq!(found[1].1 as *const _ as *const u8 as usize - base, 0x1000);
+        assert_eq!(mem::size_of_val(found[1].1), 0x800);
+    }
+
+    #[test]
+    fn partition_flags() {
+        let mut buf = test_ncsd();
+        buf.0[0x110..0x112].copy_from_slice(&[1, 3]);
+        buf.0[0x118..0x11c].copy_from_slice(&[0, 1, 2, 9]);
+
+        let ncsd = Ncsd::from_bytes(&buf.0).unwrap();
+        let flags: Vec<_> = ncsd.header().partition_flags().collect();
+        assert_eq!(flags.len(), 8);
+        assert_eq!(flags[0].fs_type(), PartitionFsType::Normal);
+        assert_eq!(flags[1].fs_type(), PartitionFsType::Firm);
+        assert_eq!(flags[2].fs_type(), PartitionFsType::None);
+
+        let crypt: Vec<_> = flags.iter().take(4).map(|f| f.crypt_type()).collect();
+        assert_eq!(
+            crypt,
+            [
+                PartitionCryptType::Initial,
+                PartitionCryptType::FixedKey,
+                PartitionCryptType::NoCrypto,
+                PartitionCryptType::Unknown
+            ]
+        );
+        assert!(flags[0].crypt_type().is_encrypted());
+        assert!(!flags[2].crypt_type().is_encrypted());
+    }
+}
@@ -0,0 +1,253 @@
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::fs::{self, File};
+#[cfg(feature = "std")]
+use std::io::Read;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "cia")]
+use crate::cia::Cia;
+#[cfg(feature = "firm")]
+use crate::firm::Firm;
+#[cfg(feature = "3dsx")]
+use crate::hb3dsx::Hb3dsx;
+#[cfg(feature = "ncch")]
+use crate::ncch::Ncch;
+#[cfg(feature = "smdh")]
+use crate::smdh::Smdh;
+#[cfg(any(feature = "cia", feature = "firm", feature = "3dsx", feature = "smdh"))]
+use crate::FromBytes;
+use crate::{CytrynaError, CytrynaResult};
+
+/// Number of bytes needed by [detect] to tell apart all supported file types
+pub const DETECT_SIZE: usize = 0x104;
+
+/// Kind of a file recognized by [detect]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FileKind {
+    Cia,
+    Ncch,
+    Firm,
+    Hb3dsx,
+    Smdh,
+}
+
+/// Detects the kind of a file from its first bytes
+pub fn detect(bytes: &[u8]) -> CytrynaResult<FileKind> {
+    if bytes.len() < 4 {
+        return Err(CytrynaError::SliceTooSmall);
+    }
+    match &bytes[..4] {
+        b"FIRM" => return Ok(FileKind::Firm),
+        b"3DSX" => return Ok(FileKind::Hb3dsx),
+        b"SMDH" => return Ok(FileKind::Smdh),
+        _ => (),
+    }
+    if bytes.len() >= DETECT_SIZE && &bytes[0x100..0x104] == b"NCCH" {
+        return Ok(FileKind::Ncch);
+    }
+    // CIAs have no magic, but their header size and type are fixed
+    if bytes.len() >= 6 && bytes[..6] == [0x20, 0x20, 0x00, 0x00, 0x00, 0x00] {
+        return Ok(FileKind::Cia);
+    }
+    Err(CytrynaError::InvalidMagic)
+}
+
+/// A parsed file of any kind supported by [open]
+#[cfg(any(
+    feature = "cia",
+    feature = "ncch",
+    feature = "firm",
+    feature = "3dsx",
+    feature = "smdh"
+))]
+#[derive(Clone, Copy)]
+pub enum Container<'a> {
+    #[cfg(feature = "cia")]
+    Cia(&'a Cia),
+    #[cfg(feature = "ncch")]
+    Ncch(&'a Ncch),
+    #[cfg(feature = "firm")]
+    Firm(&'a Firm),
+    #[cfg(feature = "3dsx")]
+    Hb3dsx(&'a Hb3dsx),
+    #[cfg(feature = "smdh")]
+    Smdh(&'a Smdh),
+}
+
+#[cfg(any(
+    feature = "cia",
+    feature = "ncch",
+    feature = "firm",
+    feature = "3dsx",
+    feature = "smdh"
+))]
+impl Container<'_> {
+    /// Returns the kind of this container
+    #[must_use]
+    pub fn kind(&self) -> FileKind {
+        match self {
+            #[cfg(feature = "cia")]
+            Self::Cia(_) => FileKind::Cia,
+            #[cfg(feature = "ncch")]
+            Self::Ncch(_) => FileKind::Ncch,
+            #[cfg(feature = "firm")]
+            Self::Firm(_) => FileKind::Firm,
+            #[cfg(feature = "3dsx")]
+            Self::Hb3dsx(_) => FileKind::Hb3dsx,
+            #[cfg(feature = "smdh")]
+            Self::Smdh(_) => FileKind::Smdh,
+        }
+    }
+}
+
+/// Detects the kind of a file with [detect] and parses it
+///
+/// Fails with Unsupported if support for that kind of file is disabled by crate features
+#[cfg(any(
+    feature = "cia",
+    feature = "ncch",
+    feature = "firm",
+    feature = "3dsx",
+    feature = "smdh"
+))]
+pub fn open(bytes: &[u8]) -> CytrynaResult<Container<'_>> {
+    match detect(bytes)? {
+        #[cfg(feature = "cia")]
+        FileKind::Cia => Ok(Container::Cia(Cia::from_bytes(bytes)?)),
+        #[cfg(feature = "ncch")]
+        FileKind::Ncch => Ok(Container::Ncch(Ncch::from_slice(bytes)?)),
+        #[cfg(feature = "firm")]
+        FileKind::Firm => Ok(Container::Firm(Firm::from_bytes(bytes)?)),
+        #[cfg(feature = "3dsx")]
+        FileKind::Hb3dsx => Ok(Container::Hb3dsx(Hb3dsx::from_bytes(bytes)?)),
+        #[cfg(feature = "smdh")]
+        FileKind::Smdh => Ok(Container::Smdh(Smdh::from_bytes(bytes)?)),
+        #[allow(unreachable_patterns)]
+        _ => Err(CytrynaError::Unsupported(
+            "file kind disabled by crate features",
+        )),
+    }
+}
+
+/// Runs [detect] over every file in a directory, without descending into subdirectories
+///
+/// Results are sorted by path, errors are reported per entry. Errors that can't be tied to any
+/// entry, like failing to read the directory, are reported with the path of `dir`
+#[cfg(feature = "std")]
+pub fn scan_dir(dir: &Path) -> Vec<(PathBuf, CytrynaResult<FileKind>)> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => return vec![(dir.to_path_buf(), Err(e.into()))],
+    };
+
+    let mut ret = Vec::new();
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                ret.push((dir.to_path_buf(), Err(e.into())));
+                continue;
+            }
+        };
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(ty) if ty.is_dir() => continue,
+            Ok(_) => {
+                let kind = detect_file(&path);
+                ret.push((path, kind));
+            }
+            Err(e) => ret.push((path, Err(e.into()))),
+        }
+    }
+    ret.sort_by(|a, b| a.0.cmp(&b.0));
+    ret
+}
+
+#[cfg(feature = "std")]
+fn detect_file(path: &Path) -> CytrynaResult<FileKind> {
+    let mut bytes = Vec::with_capacity(DETECT_SIZE);
+    File::open(path)?
+        .take(DETECT_SIZE as u64)
+        .read_to_end(&mut bytes)?;
+    detect(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect, open, scan_dir, FileKind};
+    use crate::CytrynaError;
+
+    #[test]
+    fn detect_magic() {
+        assert_eq!(detect(b"SMDH\0\0\0\0").unwrap(), FileKind::Smdh);
+        let mut ncch = vec![0u8; 0x200];
+        ncch[0x100..0x104].copy_from_slice(b"NCCH");
+        assert_eq!(detect(&ncch).unwrap(), FileKind::Ncch);
+        assert!(matches!(detect(b"ab"), Err(CytrynaError::SliceTooSmall)));
+    }
+
+    #[cfg(feature = "cia")]
+    #[test]
+    fn scan_temp_dir() {
+        use crate::cia::tests::minimal_cia;
+
+        let dir = crate::tests::temp_path("scan-dir");
+        let scanned = scan_dir(&dir);
+        assert_eq!(scanned.len(), 1);
+        assert_eq!(scanned[0].0, dir);
+        assert!(matches!(scanned[0].1, Err(CytrynaError::Io(_))));
+        std::fs::create_dir(&dir).unwrap();
+
+        std::fs::write(dir.join("a.cia"), minimal_cia()).unwrap();
+        std::fs::write(dir.join("b.bin"), [0x55; 0x200]).unwrap();
+
+        let scanned = scan_dir(&dir);
+        assert_eq!(scanned.len(), 2);
+        assert_eq!(scanned[0].0, dir.join("a.cia"));
+        assert_eq!(*scanned[0].1.as_ref().unwrap(), FileKind::Cia);
+        assert!(matches!(scanned[1].1, Err(CytrynaError::InvalidMagic)));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(all(
+        feature = "cia",
+        feature = "ncch",
+        feature = "firm",
+        feature = "3dsx",
+        feature = "smdh"
+    ))]
+    #[test]
+    fn open_by_magic() {
+        use super::Container;
+        use crate::cia::tests::minimal_cia;
+        use crate::hb3dsx::Hb3dsx;
+        use crate::ncch::tests::{ncch_header, Aligned};
+        use crate::smdh::tests::test_smdh;
+
+        let cia = minimal_cia();
+        assert!(matches!(open(&cia).unwrap(), Container::Cia(_)));
+
+        let ncch = Aligned(ncch_header());
+        assert!(matches!(open(&ncch.0).unwrap(), Container::Ncch(_)));
+
+        let firm = include_bytes!("../testdata/fastboot3DS.firm");
+        assert!(matches!(open(firm).unwrap(), Container::Firm(_)));
+
+        let hb3dsx = Hb3dsx::builder().with_code(vec![0; 0x10]).build().unwrap();
+        assert!(matches!(open(&hb3dsx).unwrap(), Container::Hb3dsx(_)));
+
+        let smdh = test_smdh();
+        let container = open(smdh.as_bytes()).unwrap();
+        assert!(matches!(container, Container::Smdh(_)));
+        assert_eq!(container.kind(), FileKind::Smdh);
+
+        assert!(matches!(
+            open(&[0xff; 0x200]),
+            Err(CytrynaError::InvalidMagic)
+        ));
+    }
+}
@@ -1,6 +1,7 @@
 use core::fmt;
 use core::mem;
 use core::slice;
+use std::path::Path;
 
 use crate::string::{SizedCString, SizedCStringError, SizedCStringUtf16};
 use crate::{CytrynaError, CytrynaResult, FromBytes};
@@ -9,6 +10,7 @@ use bitfield_struct::bitfield;
 use bitflags::bitflags;
 use bmp::{px, Pixel};
 use derive_more::{Display, Error, From};
+use image::imageops::FilterType;
 use static_assertions::assert_eq_size;
 
 /// SMDH error type
@@ -28,6 +30,8 @@ pub enum SmdhError {
     InvalidImageSize { got: u32, expected: u32 },
     #[display(fmt = "Only square images can be SMDH icons")]
     OnlySquaresAllowed,
+    #[display(fmt = "Image error: {_0}")]
+    ImageErr(image::ImageError),
 }
 
 type SmdhResult<T> = Result<T, SmdhError>;
@@ -55,9 +59,48 @@ pub struct SmdhBuilder {
     publisher: Option<SizedCStringUtf16<0x40>>,
     big_icon: Option<Box<IconData<0x900>>>,
     small_icon: Option<Box<IconData<0x240>>>,
+    titles: [Option<SmdhTitle>; 0x10],
+    age_ratings: [AgeRating; 0x10],
+    region_lockout: RegionLockout,
+    matchmaker_id: MatchmakerId,
+    flags: SmdhFlags,
+    eula_version: EulaVersion,
+    version: u16,
+    optimal_animation_default_frame: f32,
+    cec_id: u32,
+}
+
+impl Default for SmdhBuilder {
+    fn default() -> Self {
+        Smdh::builder()
+    }
 }
 
 impl SmdhBuilder {
+    /// Makes an SMDH builder, same as `Smdh::builder()`
+    #[must_use]
+    pub fn new() -> Self {
+        Smdh::builder()
+    }
+    /// Sets the title of a single language, overriding one set with with_short_desc,
+    /// with_long_desc and with_publisher for all languages
+    ///
+    /// Languages without their own title fall back to the one set for all languages, if that
+    /// isn't set either they are left zero-filled
+    pub fn with_title_for(
+        &mut self,
+        lang: Language,
+        short: &str,
+        long: &str,
+        publisher: &str,
+    ) -> SmdhResult<&mut Self> {
+        self.titles[lang as usize] = Some(SmdhTitle {
+            short_desc: short.try_into()?,
+            long_desc: long.try_into()?,
+            publisher: publisher.try_into()?,
+        });
+        Ok(self)
+    }
     /// Sets the short description
     pub fn with_short_desc(&mut self, desc: &str) -> SmdhResult<&mut Self> {
         let _ = self.short_desc.insert(desc.try_into()?);
@@ -83,84 +126,79 @@ impl SmdhBuilder {
         let _ = self.big_icon.insert(Box::new(icon));
         self
     }
+    /// Sets the region lockout, defaults to region free
+    pub fn with_region_lockout(&mut self, region_lockout: RegionLockout) -> &mut Self {
+        self.region_lockout = region_lockout;
+        self
+    }
+    /// Sets the flags, defaults to visible in home menu, region rating required and record usage
+    pub fn with_flags(&mut self, flags: SmdhFlags) -> &mut Self {
+        self.flags = flags;
+        self
+    }
+    /// Sets age rating of a given region, defaults to no age restriction in all regions
+    pub fn with_age_rating(&mut self, region: AgeRatingRegion, rating: AgeRating) -> &mut Self {
+        self.age_ratings[region as usize] = rating;
+        self
+    }
+    /// Sets the EULA version, defaults to 0.0
+    pub fn with_eula_version(&mut self, major: u8, minor: u8) -> &mut Self {
+        self.eula_version = EulaVersion { major, minor };
+        self
+    }
+    /// Sets the matchmaker ID and bit ID, defaults to 0
+    pub fn with_matchmaker_id(&mut self, id: u32, bit_id: u64) -> &mut Self {
+        self.matchmaker_id = MatchmakerId { id, bit_id };
+        self
+    }
     /// Builds the SMDH
     pub fn build(&mut self) -> Result<Smdh, SmdhError> {
-        let title = SmdhTitle {
-            short_desc: self.short_desc.take().ok_or(SmdhError::MissingShortDesc)?,
-            long_desc: self.long_desc.take().ok_or(SmdhError::MissingLongDesc)?,
-            publisher: self.publisher.take().ok_or(SmdhError::MissingPublisher)?,
+        let default = if self.short_desc.is_none()
+            && self.long_desc.is_none()
+            && self.publisher.is_none()
+        {
+            None
+        } else {
+            Some(SmdhTitle {
+                short_desc: self.short_desc.take().ok_or(SmdhError::MissingShortDesc)?,
+                long_desc: self.long_desc.take().ok_or(SmdhError::MissingLongDesc)?,
+                publisher: self.publisher.take().ok_or(SmdhError::MissingPublisher)?,
+            })
         };
-        // lol
-        let titles = [
-            title.clone(),
-            title.clone(),
-            title.clone(),
-            title.clone(),
-            title.clone(),
-            title.clone(),
-            title.clone(),
-            title.clone(),
-            title.clone(),
-            title.clone(),
-            title.clone(),
-            title.clone(),
-            title.clone(),
-            title.clone(),
-            title.clone(),
-            title,
-        ];
-
-        let mut age_ratings = [AgeRating::empty(); 16];
-        for (i, rating) in age_ratings.iter_mut().enumerate() {
-            if i == 2 || i == 5 || i >= 12 {
-                continue;
-            }
-            *rating = AgeRating::NO_AGE_RESTRICTION | AgeRating::ENABLED;
+        if default.is_none() && self.titles.iter().all(Option::is_none) {
+            return Err(SmdhError::MissingShortDesc);
         }
+        let empty = SmdhTitle {
+            short_desc: "".try_into()?,
+            long_desc: "".try_into()?,
+            publisher: "".try_into()?,
+        };
+        let titles = core::array::from_fn(|i| {
+            self.titles[i]
+                .take()
+                .or_else(|| default.clone())
+                .unwrap_or_else(|| empty.clone())
+        });
 
         let big = self.big_icon.take().ok_or(SmdhError::MissingIcon)?;
-        let small = self.small_icon.take().unwrap_or_else(|| {
-            let mut img_big = bmp::Image::new(48, 48);
-            for (x, y, rgb) in big.pixel_iter() {
-                img_big.set_pixel(
-                    x as u32,
-                    y as u32,
-                    px!(rgb.r() << 3, rgb.g() << 2, rgb.b() << 3),
-                );
-            }
-            let data: [Rgb565Pixel; 0x240] = [0u16; 0x240].map(|v| v.into());
-            let mut this = IconData { data };
-            for (x, y, rgb) in this.pixel_iter_mut() {
-                let one = img_big.get_pixel(x as u32, y as u32);
-                let two = img_big.get_pixel(x as u32, (y + 1) as u32);
-                let three = img_big.get_pixel((x + 1) as u32, y as u32);
-                let four = img_big.get_pixel((x + 1) as u32, (y + 1) as u32);
-                let r = (one.r as u32 + two.r as u32 + three.r as u32 + four.r as u32) / 4;
-                let g = (one.g as u32 + two.g as u32 + three.g as u32 + four.g as u32) / 4;
-                let b = (one.b as u32 + two.b as u32 + three.b as u32 + four.b as u32) / 4;
-                rgb.set_r(r as u8 >> 3);
-                rgb.set_g(g as u8 >> 2);
-                rgb.set_b(b as u8 >> 3);
-            }
-
-            Box::new(this)
-        });
+        let small = self
+            .small_icon
+            .take()
+            .unwrap_or_else(|| Box::new(big.downscale()));
 
         Ok(Smdh {
             magic: SizedCString::from(*b"SMDH"),
-            version: 0,
+            version: self.version,
             _reserved0: 0,
             titles,
-            age_ratings,
-            region_lockout: RegionLockout::REGION_FREE,
-            matchmaker_id: MatchmakerId { id: 0, bit_id: 0 },
-            flags: SmdhFlags::VISIBLE_IN_HOMEMENU
-                | SmdhFlags::REGION_RATING_REQUIRED
-                | SmdhFlags::RECORD_USAGE,
-            eula_version: EulaVersion { major: 0, minor: 0 },
+            age_ratings: self.age_ratings,
+            region_lockout: self.region_lockout,
+            matchmaker_id: self.matchmaker_id.clone(),
+            flags: self.flags,
+            eula_version: self.eula_version.clone(),
             _reserved1: 0,
-            optimal_animation_default_frame: 0f32,
-            cec_id: 0,
+            optimal_animation_default_frame: self.optimal_animation_default_frame,
+            cec_id: self.cec_id,
             _reserved2: 0,
             icon: SmdhIcon {
                 big: *big,
@@ -200,12 +238,25 @@ pub struct EulaVersion {
     minor: u8,
 }
 
+impl EulaVersion {
+    /// Returns the major version
+    #[must_use]
+    pub fn major(&self) -> u8 {
+        self.major
+    }
+    /// Returns the minor version
+    #[must_use]
+    pub fn minor(&self) -> u8 {
+        self.minor
+    }
+}
+
 impl FromBytes for Smdh {
     fn min_size() -> usize {
         mem::size_of::<Smdh>()
     }
     fn bytes_ok(bytes: &[u8]) -> CytrynaResult<()> {
-        if [bytes[0], bytes[1], bytes[2], bytes[3]] != *b"SMDH" {
+        if bytes.get(..4) != Some(b"SMDH") {
             return Err(CytrynaError::InvalidMagic);
         }
 
@@ -222,15 +273,66 @@ impl Smdh {
     pub fn as_bytes(&self) -> &[u8; 0x36c0] {
         unsafe { mem::transmute(self) }
     }
+    /// Writes raw bytes of this SMDH into `w`
+    pub fn write_to(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+        w.write_all(self.as_bytes())
+    }
     /// Returns the SMDH builder
     #[must_use]
     pub fn builder() -> SmdhBuilder {
+        let mut age_ratings = [AgeRating::empty(); 16];
+        for (i, rating) in age_ratings.iter_mut().enumerate() {
+            if i == 2 || i == 5 || i >= 12 {
+                continue;
+            }
+            *rating = AgeRating::NO_AGE_RESTRICTION | AgeRating::ENABLED;
+        }
+
         SmdhBuilder {
             big_icon: None,
             small_icon: None,
             long_desc: None,
             short_desc: None,
             publisher: None,
+            titles: Default::default(),
+            age_ratings,
+            region_lockout: RegionLockout::REGION_FREE,
+            matchmaker_id: MatchmakerId { id: 0, bit_id: 0 },
+            flags: SmdhFlags::VISIBLE_IN_HOMEMENU
+                | SmdhFlags::REGION_RATING_REQUIRED
+                | SmdhFlags::RECORD_USAGE,
+            eula_version: EulaVersion { major: 0, minor: 0 },
+            version: 0,
+            optimal_animation_default_frame: 0f32,
+            cec_id: 0,
+        }
+    }
+    /// Returns a builder seeded with all data of this SMDH, so that it can be edited and rebuilt
+    ///
+    /// Title of English language is used for with_short_desc, with_long_desc and with_publisher,
+    /// languages with a different title keep it as if it was set with with_title_for
+    #[must_use]
+    pub fn to_builder(&self) -> SmdhBuilder {
+        let default = self.title(Language::English);
+        let titles = core::array::from_fn(|i| {
+            Some(self.titles[i].clone()).filter(|title| title != default)
+        });
+
+        SmdhBuilder {
+            short_desc: Some(default.short_desc.clone()),
+            long_desc: Some(default.long_desc.clone()),
+            publisher: Some(default.publisher.clone()),
+            big_icon: Some(Box::new(self.big_icon_data())),
+            small_icon: Some(Box::new(self.small_icon_data())),
+            titles,
+            age_ratings: self.age_ratings,
+            region_lockout: self.region_lockout,
+            matchmaker_id: self.matchmaker_id.clone(),
+            flags: self.flags,
+            eula_version: self.eula_version.clone(),
+            version: self.version,
+            optimal_animation_default_frame: self.optimal_animation_default_frame,
+            cec_id: self.cec_id,
         }
     }
     /// Returns title data(in a given language)
@@ -238,6 +340,11 @@ impl Smdh {
     pub fn title(&self, lang: Language) -> &SmdhTitle {
         &self.titles[lang as usize]
     }
+    /// Returns title data(in a given language) converted to owned strings
+    #[must_use]
+    pub fn title_strings(&self, lang: Language) -> TitleStrings {
+        self.title(lang).to_owned_strings()
+    }
     /// Returns age rating data(of a given region)
     #[must_use]
     pub fn age_rating(&self, region: AgeRatingRegion) -> AgeRating {
@@ -285,6 +392,16 @@ impl Smdh {
     pub fn small_icon(&self) -> &IconData<0x240> {
         &self.icon.small
     }
+    /// Returns a copy of big icon data, for use with [SmdhBuilder::with_icon]
+    #[must_use]
+    pub fn big_icon_data(&self) -> IconData<0x900> {
+        self.icon.big.clone()
+    }
+    /// Returns a copy of small icon data, for use with [SmdhBuilder::with_small_icon]
+    #[must_use]
+    pub fn small_icon_data(&self) -> IconData<0x240> {
+        self.icon.small.clone()
+    }
 }
 
 /// Age Rating Region index
@@ -392,7 +509,7 @@ pub enum Language {
 
 /// SMDH Application title data
 /// <https://www.3dbrew.org/wiki/SMDH#Application_Titles>
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 #[repr(C)]
 pub struct SmdhTitle {
     short_desc: SizedCStringUtf16<0x40>,
@@ -417,6 +534,31 @@ impl SmdhTitle {
     pub fn publisher(&self) -> &SizedCStringUtf16<0x40> {
         &self.publisher
     }
+    /// Converts title data into owned strings, cutting them off at first NUL character
+    #[must_use]
+    pub fn to_owned_strings(&self) -> TitleStrings {
+        fn convert<const SIZE: usize>(string: &SizedCStringUtf16<SIZE>) -> String {
+            let lossy = string.to_string_lossy();
+            match lossy.split_once('\0') {
+                Some((before, _)) => before.to_string(),
+                None => lossy,
+            }
+        }
+
+        TitleStrings {
+            short: convert(&self.short_desc),
+            long: convert(&self.long_desc),
+            publisher: convert(&self.publisher),
+        }
+    }
+}
+
+/// SMDH Application title data, as owned strings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TitleStrings {
+    pub short: String,
+    pub long: String,
+    pub publisher: String,
 }
 
 /// SMDH Icon data wrapper
@@ -430,7 +572,7 @@ pub struct SmdhIcon {
 assert_eq_size!([u8; 0x1680], SmdhIcon);
 
 /// SMDH Icon Data(actual)
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
 #[repr(C)]
 pub struct IconData<const SIZE: usize> {
     data: [Rgb565Pixel; SIZE],
@@ -445,6 +587,7 @@ impl<const SIZE: usize> fmt::Debug for IconData<SIZE> {
 
 /// SMDH Pixel data, it's actually BGR and not RGB
 #[bitfield(u16)]
+#[derive(PartialEq, Eq)]
 pub struct Rgb565Pixel {
     #[bits(5)]
     b: u8,
@@ -463,9 +606,9 @@ impl Rgb565Pixel {
     {
         let rgb = pixel.to_rgb();
         Self::new()
-            .with_r(rgb.0[0] << 3)
-            .with_g(rgb.0[1] << 4)
-            .with_b(rgb.0[2] << 3)
+            .with_r(rgb.0[0] >> 3)
+            .with_g(rgb.0[1] >> 2)
+            .with_b(rgb.0[2] >> 3)
     }
     /*fn from_image_pixel_subpixel_f32<T>(pixel: T) -> Self
     where
@@ -531,6 +674,22 @@ impl<const SIZE: usize> IconData<SIZE> {
             k: 0,
         }
     }
+    /// Loads an icon from an image file in any format supported by the image crate
+    ///
+    /// A 48x48 image is downscaled when loading a small icon
+    pub fn from_image_path(path: impl AsRef<Path>) -> SmdhResult<Self> {
+        let img = image::open(path)?;
+        if img.width() != img.height() {
+            return Err(SmdhError::OnlySquaresAllowed);
+        }
+        let width = Self::width() as u32;
+        let img = if img.width() != width && img.width() == IconData::<0x900>::width() as u32 {
+            img.resize_exact(width, width, FilterType::Triangle)
+        } else {
+            img
+        };
+        Self::try_from(&img)
+    }
     /// Copies this icon into a new BMP Image
     #[must_use]
     pub fn to_bmp(&self) -> bmp::Image {
@@ -544,6 +703,23 @@ impl<const SIZE: usize> IconData<SIZE> {
         }
         img
     }
+    /// Copies this icon into a new image crate RgbImage, scaling channels up to 8 bits
+    #[must_use]
+    pub fn to_rgb_image(&self) -> image::RgbImage {
+        let mut img = image::RgbImage::new(Self::width() as u32, Self::width() as u32);
+        for (x, y, rgb) in self.pixel_iter() {
+            img.put_pixel(
+                x as u32,
+                y as u32,
+                image::Rgb([
+                    rgb.r() << 3 | rgb.r() >> 2,
+                    rgb.g() << 2 | rgb.g() >> 4,
+                    rgb.b() << 3 | rgb.b() >> 2,
+                ]),
+            );
+        }
+        img
+    }
 }
 
 impl<const SIZE: usize> TryFrom<&bmp::Image> for IconData<SIZE> {
@@ -597,6 +773,15 @@ impl<const SIZE: usize> TryFrom<&image::DynamicImage> for IconData<SIZE> {
     }
 }
 
+impl IconData<0x900> {
+    fn downscale(&self) -> IconData<0x240> {
+        let img = self.to_rgb_image();
+        let small = image::imageops::resize(&img, 24, 24, FilterType::Triangle);
+        IconData::try_from(&image::DynamicImage::ImageRgb8(small))
+            .expect("downscaled icon should be 24x24")
+    }
+}
+
 /// An iterator over x and y coordinates and a mutable refernce to Rgb565Pixel in that coordinates
 #[derive(Debug)]
 pub struct PixelIteratorMut<'a, const SIZE: usize> {
@@ -662,11 +847,28 @@ impl<'a, const SIZE: usize> Iterator for PixelIterator<'a, SIZE> {
 }
 
 #[cfg(test)]
-mod tests {
-    use super::IconData;
+pub(crate) mod tests {
+    use super::{
+        AgeRating, AgeRatingRegion, IconData, Language, RegionLockout, Rgb565Pixel, Smdh,
+        SmdhBuilder, SmdhError, SmdhFlags,
+    };
+    use crate::{CytrynaError, FromBytes};
     use bmp::Pixel;
     use std::{fs, mem};
 
+    pub(crate) fn test_smdh() -> Smdh {
+        Smdh::builder()
+            .with_short_desc("Short")
+            .unwrap()
+            .with_long_desc("A longer description")
+            .unwrap()
+            .with_publisher("Someone")
+            .unwrap()
+            .with_icon((&bmp::Image::new(48, 48)).try_into().unwrap())
+            .build()
+            .unwrap()
+    }
+
     fn random_bmp_image(size: u32) -> bmp::Image {
         let mut img = bmp::Image::new(size, size);
         for (x, y) in img.coordinates() {
@@ -695,6 +897,15 @@ mod tests {
         assert_eq!(test_smdh_u16, good_smdh_u16);
     }
 
+    #[test]
+    fn write_to_vec() {
+        let mut out = Vec::new();
+        test_smdh().write_to(&mut out).unwrap();
+
+        assert_eq!(out.len(), 0x36c0);
+        assert_eq!(&out[..4], b"SMDH");
+    }
+
     #[test]
     fn bmp_to_smdh_to_bmp_24() {
         let src = random_bmp_image(24);
@@ -712,4 +923,224 @@ mod tests {
 
         assert_eq!(src, other_src);
     }
+
+    #[test]
+    fn title_strings() {
+        let smdh = test_smdh();
+        let strings = smdh.title_strings(Language::English);
+
+        assert_eq!(strings.short, "Short");
+        assert_eq!(strings.long, "A longer description");
+        assert_eq!(strings.publisher, "Someone");
+        assert_eq!(smdh.title(Language::Japanese).to_owned_strings(), strings);
+    }
+
+    #[test]
+    fn default_constructors() {
+        let mut builder: SmdhBuilder = Default::default();
+        let smdh = builder
+            .with_short_desc("Short")
+            .unwrap()
+            .with_long_desc("A longer description")
+            .unwrap()
+            .with_publisher("Someone")
+            .unwrap()
+            .with_icon((&bmp::Image::new(48, 48)).try_into().unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(smdh.as_bytes(), test_smdh().as_bytes());
+        assert!(SmdhBuilder::new().build().is_err());
+    }
+
+    #[test]
+    fn builder_metadata() {
+        let default = test_smdh();
+        assert_eq!(
+            default.region_lockout().bits(),
+            RegionLockout::REGION_FREE.bits()
+        );
+
+        let smdh = Smdh::builder()
+            .with_short_desc("Short")
+            .unwrap()
+            .with_long_desc("A longer description")
+            .unwrap()
+            .with_publisher("Someone")
+            .unwrap()
+            .with_icon((&bmp::Image::new(48, 48)).try_into().unwrap())
+            .with_region_lockout(RegionLockout::EUROPE | RegionLockout::AUSTRALIA)
+            .with_flags(SmdhFlags::VISIBLE_IN_HOMEMENU | SmdhFlags::ALLOW_3D)
+            .with_age_rating(
+                AgeRatingRegion::PegiGen,
+                AgeRating::ENABLED | AgeRating::from_bits_retain(12),
+            )
+            .with_eula_version(1, 2)
+            .with_matchmaker_id(0x1234, 0x5678)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            smdh.region_lockout().bits(),
+            (RegionLockout::EUROPE | RegionLockout::AUSTRALIA).bits()
+        );
+        assert_eq!(
+            smdh.flags().bits(),
+            (SmdhFlags::VISIBLE_IN_HOMEMENU | SmdhFlags::ALLOW_3D).bits()
+        );
+        assert_eq!(smdh.age_rating(AgeRatingRegion::PegiGen).bits(), 0x8c);
+        assert_eq!(
+            smdh.age_rating(AgeRatingRegion::Cero).bits(),
+            default.age_rating(AgeRatingRegion::Cero).bits()
+        );
+        assert_eq!(smdh.eula_version().major(), 1);
+        assert_eq!(smdh.eula_version().minor(), 2);
+        assert_eq!(smdh.matchmaker_id().id(), 0x1234);
+        assert_eq!(smdh.matchmaker_id().bit_id(), 0x5678);
+    }
+
+    #[test]
+    fn per_language_titles() {
+        let smdh = Smdh::builder()
+            .with_title_for(Language::English, "Short", "Long", "Someone")
+            .unwrap()
+            .with_icon((&bmp::Image::new(48, 48)).try_into().unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(smdh.title_strings(Language::English).short, "Short");
+        let japanese = smdh.title(Language::Japanese);
+        assert!(japanese.short_desc().is_zero());
+        assert!(japanese.long_desc().is_zero());
+        assert!(japanese.publisher().is_zero());
+
+        let smdh = Smdh::builder()
+            .with_short_desc("Default")
+            .unwrap()
+            .with_long_desc("Default long")
+            .unwrap()
+            .with_publisher("Someone")
+            .unwrap()
+            .with_title_for(Language::Japanese, "Nihongo", "Nihongo long", "Someone")
+            .unwrap()
+            .with_icon((&bmp::Image::new(48, 48)).try_into().unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(smdh.title_strings(Language::Japanese).short, "Nihongo");
+        assert_eq!(smdh.title_strings(Language::English).short, "Default");
+
+        let ret = Smdh::builder()
+            .with_icon((&bmp::Image::new(48, 48)).try_into().unwrap())
+            .build();
+        assert!(matches!(ret, Err(SmdhError::MissingShortDesc)));
+    }
+
+    #[test]
+    fn icons_from_png() {
+        // alternating black and white columns should average out to gray
+        let img = image::RgbImage::from_fn(48, 48, |x, _| {
+            if x % 2 == 0 {
+                image::Rgb([0xff, 0xff, 0xff])
+            } else {
+                image::Rgb([0, 0, 0])
+            }
+        });
+        let path = crate::tests::temp_path("icons-from-png.png");
+        img.save(&path).unwrap();
+
+        let big = IconData::<0x900>::from_image_path(&path).unwrap();
+        for (x, _, rgb) in big.pixel_iter() {
+            let expected = if x % 2 == 0 { 0x1f } else { 0 };
+            assert_eq!(rgb.r(), expected);
+        }
+        let small = IconData::<0x240>::from_image_path(&path).unwrap();
+        for (_, _, rgb) in small.pixel_iter() {
+            assert!((12..=20).contains(&rgb.r()), "{}", rgb.r());
+            assert!((24..=40).contains(&rgb.g()), "{}", rgb.g());
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rgb_image_untiled() {
+        let mut icon: IconData<0x900> = (&bmp::Image::new(48, 48)).try_into().unwrap();
+        // third pixel of the second tile
+        icon.data[0x40 + 2] = Rgb565Pixel::new().with_r(0x1f).with_g(0x20);
+        let img = icon.to_rgb_image();
+        assert_eq!(img.get_pixel(8, 1).0, [0xff, 0x82, 0x00]);
+        assert_eq!(img.get_pixel(2, 0).0, [0, 0, 0]);
+    }
+
+    #[test]
+    fn too_small() {
+        assert!(matches!(Smdh::from_bytes(b"SMD"), Err(CytrynaError::SliceTooSmall)));
+    }
+
+    #[test]
+    fn icon_without_header() {
+        let icons = test_smdh().as_bytes()[0x2040..].to_vec();
+        assert_eq!(icons.len(), 0x1680);
+        assert!(matches!(Smdh::from_bytes(&icons), Err(CytrynaError::SliceTooSmall)));
+        assert!(matches!(Smdh::bytes_ok(&icons[..2]), Err(CytrynaError::InvalidMagic)));
+
+        let mut padded = icons.clone();
+        padded.resize(mem::size_of::<Smdh>(), 0);
+        assert!(matches!(Smdh::from_bytes(&padded), Err(CytrynaError::InvalidMagic)));
+    }
+
+    #[test]
+    fn to_builder_round_trip() {
+        let mut smdh = test_smdh()
+            .to_builder()
+            .with_title_for(Language::Japanese, "Jp", "Japanese", "Someone")
+            .unwrap()
+            .build()
+            .unwrap();
+        smdh.version = 3;
+        smdh.optimal_animation_default_frame = 1.5;
+        smdh.cec_id = 0x1234;
+
+        let rebuilt = smdh.to_builder().build().unwrap();
+        assert_eq!(rebuilt.as_bytes(), smdh.as_bytes());
+
+        let edited = smdh
+            .to_builder()
+            .with_short_desc("Edited")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(edited.title_strings(Language::English).short, "Edited");
+        assert_eq!(edited.title_strings(Language::French).short, "Edited");
+        assert_eq!(edited.title_strings(Language::Japanese).short, "Jp");
+        assert_eq!(edited.cec_id(), 0x1234);
+    }
+
+    #[test]
+    fn transplant_icon() {
+        let mut icon: IconData<0x900> = (&bmp::Image::new(48, 48)).try_into().unwrap();
+        icon.data[0x123] = Rgb565Pixel::new().with_r(0x1f);
+        let source = Smdh::builder()
+            .with_short_desc("Source")
+            .unwrap()
+            .with_long_desc("Source")
+            .unwrap()
+            .with_publisher("Someone")
+            .unwrap()
+            .with_icon(icon)
+            .build()
+            .unwrap();
+
+        let copy = Smdh::builder()
+            .with_short_desc("Copy")
+            .unwrap()
+            .with_long_desc("Copy")
+            .unwrap()
+            .with_publisher("Someone else")
+            .unwrap()
+            .with_icon(source.big_icon_data())
+            .with_small_icon(source.small_icon_data())
+            .build()
+            .unwrap();
+        assert_eq!(copy.big_icon_data(), source.big_icon_data());
+        assert_eq!(copy.small_icon_data(), source.small_icon_data());
+        assert_ne!(copy.big_icon_data(), test_smdh().big_icon_data());
+    }
 }
@@ -1,10 +1,14 @@
-use alloc::{borrow::Cow, string};
+use alloc::string::{self, String};
+use alloc::{borrow::Cow, vec::Vec};
 use core::{fmt, str};
 
-use derive_more::{Display, Error};
+use derive_more::Display;
+#[cfg(feature = "std")]
+use derive_more::Error;
 
 /// An error for SizedCString construction
-#[derive(Debug, Display, Error)]
+#[derive(Debug, Display)]
+#[cfg_attr(feature = "std", derive(Error))]
 pub enum SizedCStringError {
     #[display(fmt = "Input string too big to fit into storage")]
     TooBig,
@@ -38,6 +42,12 @@ impl<const SIZE: usize> SizedCString<SIZE> {
     pub fn data(&self) -> &[u8] {
         &self.0
     }
+    /// Replaces the string stored inside, padding it with zeroes. Contents are left untouched
+    /// if it doesn't fit
+    pub fn set_str(&mut self, value: &str) -> Result<(), SizedCStringError> {
+        *self = Self::try_from(value)?;
+        Ok(())
+    }
 }
 
 impl<const SIZE: usize> From<[u8; SIZE]> for SizedCString<SIZE> {
@@ -46,8 +56,22 @@ impl<const SIZE: usize> From<[u8; SIZE]> for SizedCString<SIZE> {
     }
 }
 
+impl<const SIZE: usize> TryFrom<&str> for SizedCString<SIZE> {
+    type Error = SizedCStringError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let bytes = value.as_bytes();
+        if bytes.len() > SIZE {
+            return Err(SizedCStringError::TooBig);
+        }
+        let mut data = [0u8; SIZE];
+        data[..bytes.len()].copy_from_slice(bytes);
+        Ok(Self(data))
+    }
+}
+
 /// A UTF-16 version of SizedCString
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
 #[repr(C)]
 pub struct SizedCStringUtf16<const SIZE: usize> {
     data: [u16; SIZE],
@@ -102,3 +126,32 @@ impl<const SIZE: usize> fmt::Debug for SizedCStringUtf16<SIZE> {
         fmt.write_fmt(format_args!("\"{}\"", self.to_string_lossy()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{SizedCString, SizedCStringError};
+
+    #[test]
+    fn cstring_from_str() {
+        let exact = SizedCString::<4>::try_from("CTR-").unwrap();
+        assert_eq!(exact.data(), b"CTR-");
+
+        let padded = SizedCString::<0x10>::try_from("CTR-P-CTAP").unwrap();
+        assert_eq!(padded.data(), b"CTR-P-CTAP\0\0\0\0\0\0");
+        assert_eq!(padded.as_str().unwrap().trim_end_matches('\0'), "CTR-P-CTAP");
+
+        assert!(matches!(
+            SizedCString::<2>::try_from("00a"),
+            Err(SizedCStringError::TooBig)
+        ));
+    }
+
+    #[test]
+    fn cstring_set_str() {
+        let mut string = SizedCString::<4>::try_from("abcd").unwrap();
+        string.set_str("01").unwrap();
+        assert_eq!(string.data(), b"01\0\0");
+        assert!(string.set_str("01234").is_err());
+        assert_eq!(string.data(), b"01\0\0");
+    }
+}
@@ -1,10 +1,15 @@
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::mem;
 
-use crate::crypto::{aes128_ctr::*, KeyBag, KeyIndex, SignedData};
-use crate::titleid::MaybeTitleIdBe;
-use crate::{CytrynaResult, FromBytes};
+use crate::crypto::{aes128_ctr::*, build_envelope, KeyBag, SignatureType, SignedBody, SignedData};
+use crate::titleid::{MaybeTitleIdBe, TitleId, TitleVersion};
+use crate::{CytrynaError, CytrynaResult, FromBytes};
 
 use derivative::Derivative;
+use derive_more::Display;
+#[cfg(feature = "std")]
+use derive_more::Error;
 
 /// Ticket Data, excluding "Issuer" field
 /// <https://www.3dbrew.org/wiki/Ticket#Ticket_Data>
@@ -55,32 +60,348 @@ impl FromBytes for TicketInner {
     }
 }
 
+impl SignedBody for TicketInner {
+    /// Signature covers the whole ticket, including content index of size stored in its header
+    fn signed_size(bytes: &[u8]) -> usize {
+        let content_index_size = bytes
+            .get(0x128..0x12c)
+            .map_or(0, |size| u32::from_be_bytes(size.try_into().unwrap()));
+        0x124 + content_index_size as usize
+    }
+}
+
 /// Type alias for convienent usage of TicketInner
 pub type Ticket<'a> = SignedData<'a, TicketInner>;
 
 impl Ticket<'_> {
+    /// Returns the builder for creating tickets
+    #[must_use]
+    pub fn builder() -> TicketBuilder {
+        TicketBuilder {
+            title_id: None,
+            title_key: None,
+            key_index: 0,
+            console_id: 0,
+            license_type: 0,
+            title_version: 0,
+        }
+    }
     /// Returns the decrypted title key
     pub fn title_key(&self) -> CytrynaResult<[u8; 0x10]> {
         let mut iv = [0u8; 0x10];
         iv[..0x8].copy_from_slice(&self.data().title_id.to_bytes());
 
         let mut title_key = self.data().title_key;
-        let idx = self.data().key_index;
-        let key = KeyBag::global()?.get_key(KeyIndex::CommonN(idx))?;
+        let key = common_key(self.data().key_index)?;
 
-        Aes128CbcDec::new(key.into(), &iv.into())
+        Aes128CbcDec::new(&key.into(), &iv.into())
             .decrypt_padded_mut::<NoPadding>(&mut title_key)
             .unwrap();
         Ok(title_key)
     }
+    /// Returns the decrypted title key as a lowercase hex string
+    pub fn title_key_hex(&self) -> CytrynaResult<String> {
+        Ok(hex::encode(self.title_key()?))
+    }
+    /// Encrypts a title key with given common key, for use in tickets of given title
+    pub fn encrypt_title_key(
+        title_id: &TitleId,
+        decrypted: [u8; 0x10],
+        common_idx: u8,
+    ) -> CytrynaResult<[u8; 0x10]> {
+        let mut iv = [0u8; 0x10];
+        iv[..0x8].copy_from_slice(&title_id.to_u64().to_be_bytes());
+
+        let mut title_key = decrypted;
+        let key = common_key(common_idx)?;
+
+        Aes128CbcEnc::new(&key.into(), &iv.into())
+            .encrypt_padded_mut::<NoPadding>(&mut title_key, 0x10)
+            .unwrap();
+        Ok(title_key)
+    }
     /// Returns the un-decrypted title key
     #[must_use]
     pub fn title_key_raw(&self) -> &[u8; 0x10] {
         &self.data().title_key
     }
+    /// Returns the ECC public key of console a personalized ticket is for, it is zeroed out in
+    /// common tickets
+    #[must_use]
+    pub fn ecc_public_key(&self) -> &[u8; 0x3c] {
+        &self.data().ecc_pubkey
+    }
     /// Returns the common key index
     #[must_use]
     pub fn key_index(&self) -> u8 {
         self.data().key_index
     }
+    /// Returns the version of title this ticket is for
+    #[must_use]
+    pub fn title_version(&self) -> TitleVersion {
+        TitleVersion(u16::from_be_bytes(self.data().ticket_title_version))
+    }
+}
+
+/// Amount of common keys used by retail consoles
+const COMMON_KEY_COUNT: u8 = 6;
+
+fn common_key(idx: u8) -> CytrynaResult<[u8; 0x10]> {
+    if idx >= COMMON_KEY_COUNT {
+        return Err(CytrynaError::EnumValueOutOfRange("common key index"));
+    }
+    KeyBag::global()?.common_key(idx)
+}
+
+/// Signature issuer used by retail tickets
+const TICKET_ISSUER: &str = "Root-CA00000003-XS0000000c";
+
+/// Content index section granting rights to all contents, as used by retail tickets
+/// <https://www.3dbrew.org/wiki/Ticket#Content_Index>
+const CONTENT_INDEX_HEADER: [u8; 0x2c] = [
+    0x00, 0x01, 0x00, 0x14, 0x00, 0x00, 0x00, 0xac, 0x00, 0x00, 0x00, 0x14, 0x00, 0x01, 0x00, 0x14,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x28, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x84,
+    0x00, 0x00, 0x00, 0x84, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// An error type for TicketBuilder
+#[derive(Debug, Display)]
+#[cfg_attr(feature = "std", derive(Error))]
+pub enum TicketBuilderError {
+    #[display(fmt = "Title ID is missing")]
+    NoTitleId,
+    #[display(fmt = "Title key is missing")]
+    NoTitleKey,
+}
+
+/// Builder for tickets, signed with a zeroed out RSA_2048 SHA256 signature
+#[derive(Debug, Clone)]
+pub struct TicketBuilder {
+    title_id: Option<TitleId>,
+    title_key: Option<[u8; 0x10]>,
+    key_index: u8,
+    console_id: u32,
+    license_type: u8,
+    title_version: u16,
+}
+
+impl TicketBuilder {
+    /// Sets the Title ID
+    pub fn with_title_id(&mut self, title_id: TitleId) -> &mut Self {
+        self.title_id = Some(title_id);
+        self
+    }
+    /// Sets the title key, encrypted with common key
+    pub fn with_title_key(&mut self, title_key: [u8; 0x10]) -> &mut Self {
+        self.title_key = Some(title_key);
+        self
+    }
+    /// Sets the common key index, defaults to 0
+    pub fn with_key_index(&mut self, key_index: u8) -> &mut Self {
+        self.key_index = key_index;
+        self
+    }
+    /// Sets the console ID, defaults to 0 for tickets that aren't console-unique
+    pub fn with_console_id(&mut self, console_id: u32) -> &mut Self {
+        self.console_id = console_id;
+        self
+    }
+    /// Sets the license type
+    pub fn with_license_type(&mut self, license_type: u8) -> &mut Self {
+        self.license_type = license_type;
+        self
+    }
+    /// Sets the ticket title version
+    pub fn with_title_version(&mut self, title_version: u16) -> &mut Self {
+        self.title_version = title_version;
+        self
+    }
+    /// Builds the ticket
+    pub fn build(&mut self) -> Result<Vec<u8>, TicketBuilderError> {
+        let title_id = self.title_id.ok_or(TicketBuilderError::NoTitleId)?;
+        let title_key = self.title_key.ok_or(TicketBuilderError::NoTitleKey)?;
+
+        let mut inner = [0u8; 0x124];
+        inner[0x3c] = 1; // version
+        inner[0x3f..0x4f].copy_from_slice(&title_key);
+        inner[0x58..0x5c].copy_from_slice(&self.console_id.to_be_bytes());
+        inner[0x5c..0x64].copy_from_slice(&title_id.to_u64().to_be_bytes());
+        inner[0x66..0x68].copy_from_slice(&self.title_version.to_be_bytes());
+        inner[0x70] = self.license_type;
+        inner[0x71] = self.key_index;
+
+        let mut buf = build_envelope(SignatureType::Rsa2048Sha256, &[], TICKET_ISSUER, &inner)
+            .expect("retail issuer fits in the issuer field");
+
+        buf.extend_from_slice(&CONTENT_INDEX_HEADER);
+        buf.extend_from_slice(&[0xff; 0x80]);
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Ticket;
+    use crate::crypto::tests::test_keybag;
+    use crate::tests::TEST_MODULUS;
+    use crate::titleid::TitleId;
+    use crate::CytrynaError;
+
+    use hex_literal::hex;
+
+    // signed with the private key of TEST_MODULUS, covers `signed_ticket()`
+    static TEST_SIGNATURE: [u8; 0x100] = hex!("a2359ef433a1114a1b272e37d6f0d56bdad0028b3b52bb22acbbbb2746e16428c7354be0190f24005b335c5926abc30fba3cbffbd413222aae52338af4be006863b644b8e3003e7c91060fc0a43f00f9f35503812773a64adab3b2aec053e018b211e9114e3af9df867dc2131116bea130c06eb96b79cbdbc614a60938efc847095c7803fa6b4f4f649339f63e830b382ac34cc84e5a1d2e45e24e201077a6052fc6a344cbb390eb3dd156c55910fe2eb11ab5727f39412272513c0d92e6c695d84e92878bc0301a01140eb8b1fbb0b6a9be58e5bd51fd03d4ed75d629e6b2f6c2fbcda5492b724fb9c2c2040a430d5a07b5f96a2f9cb9e3dfa1acf3c2ecdc4a");
+
+    fn signed_ticket() -> Vec<u8> {
+        let mut ticket = Ticket::builder()
+            .with_title_id(TitleId::from_u64(0x0004000000123400).unwrap())
+            .with_title_key([0x42; 0x10])
+            .with_key_index(1)
+            .build()
+            .unwrap();
+        ticket[0x4..0x104].copy_from_slice(&TEST_SIGNATURE);
+        ticket
+    }
+
+    #[test]
+    fn build_and_read_back() {
+        let title_id = TitleId::from_u64(0x0004000000123400).unwrap();
+        let ticket = Ticket::builder()
+            .with_title_id(title_id)
+            .with_title_key([0x42; 0x10])
+            .with_key_index(1)
+            .with_title_version(0x0810)
+            .build()
+            .unwrap();
+
+        let ticket = Ticket::from_bytes(&ticket).unwrap();
+        assert!(matches!(ticket, Ticket::Rsa2048Sha256(_)));
+        assert_eq!(ticket.key_index(), 1);
+        assert_eq!(ticket.title_version().to_string(), "2.1.0");
+        assert_eq!(ticket.title_key_raw(), &[0x42; 0x10]);
+        let data_title_id = ticket.data().title_id;
+        assert_eq!(data_title_id.to_titleid().unwrap(), title_id);
+    }
+
+    #[test]
+    fn ecc_public_key() {
+        let common = Ticket::builder()
+            .with_title_id(TitleId::from_u64(0x0004000000123400).unwrap())
+            .with_title_key([0x42; 0x10])
+            .build()
+            .unwrap();
+        assert_eq!(
+            Ticket::from_bytes(&common).unwrap().ecc_public_key(),
+            &[0; 0x3c]
+        );
+
+        let mut personalized = Ticket::builder()
+            .with_title_id(TitleId::from_u64(0x0004000000123400).unwrap())
+            .with_title_key([0x42; 0x10])
+            .with_console_id(0x12345678)
+            .build()
+            .unwrap();
+        // data starts after signature type, RSA-2048 signature with padding and issuer
+        let data = 0x4 + 0x13c + 0x40;
+        personalized[data..data + 0x3c].fill(0xec);
+        let ticket = Ticket::from_bytes(&personalized).unwrap();
+        assert_eq!(ticket.ecc_public_key(), &[0xec; 0x3c]);
+    }
+
+    #[test]
+    fn encrypt_then_decrypt_title_key() {
+        test_keybag();
+        let title_id = TitleId::from_u64(0x0004000000123400).unwrap();
+        let title_key = [0x42; 0x10];
+        let encrypted = Ticket::encrypt_title_key(&title_id, title_key, 0).unwrap();
+        assert_ne!(encrypted, title_key);
+
+        let ticket = Ticket::builder()
+            .with_title_id(title_id)
+            .with_title_key(encrypted)
+            .build()
+            .unwrap();
+        let ticket = Ticket::from_bytes(&ticket).unwrap();
+        assert_eq!(ticket.title_key().unwrap(), title_key);
+    }
+
+    #[test]
+    fn title_key_with_derived_common_key() {
+        test_keybag();
+        let title_id = TitleId::from_u64(0x0004000000123400).unwrap();
+        let title_key = [0x42; 0x10];
+        let encrypted = Ticket::encrypt_title_key(&title_id, title_key, 1).unwrap();
+        assert_ne!(
+            encrypted,
+            Ticket::encrypt_title_key(&title_id, title_key, 0).unwrap()
+        );
+
+        let ticket = Ticket::builder()
+            .with_title_id(title_id)
+            .with_title_key(encrypted)
+            .with_key_index(1)
+            .build()
+            .unwrap();
+        let ticket = Ticket::from_bytes(&ticket).unwrap();
+        assert_eq!(ticket.title_key().unwrap(), title_key);
+    }
+
+    #[test]
+    fn verify_signature() {
+        let ticket = signed_ticket();
+        let parsed = Ticket::from_bytes(&ticket).unwrap();
+        assert_eq!(parsed.signature_bytes(), TEST_SIGNATURE);
+        parsed.verify(&TEST_MODULUS).unwrap();
+
+        let mut padded = ticket.clone();
+        padded.resize(padded.len().next_multiple_of(0x40) + 0x40, 0);
+        Ticket::from_bytes(&padded)
+            .unwrap()
+            .verify(&TEST_MODULUS)
+            .unwrap();
+
+        let mut tampered = ticket;
+        tampered[0x180 + 0x71] = 0;
+        assert!(matches!(
+            Ticket::from_bytes(&tampered).unwrap().verify(&TEST_MODULUS),
+            Err(CytrynaError::SignatureCorrupted)
+        ));
+    }
+
+    #[test]
+    fn title_key_as_hex() {
+        test_keybag();
+        let title_id = TitleId::from_u64(0x0004000000123400).unwrap();
+        let title_key = [
+            0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0xfe, 0xdc, 0xba, 0x98, 0x76, 0x54,
+            0x32, 0x10,
+        ];
+        let ticket = Ticket::builder()
+            .with_title_id(title_id)
+            .with_title_key(Ticket::encrypt_title_key(&title_id, title_key, 0).unwrap())
+            .build()
+            .unwrap();
+
+        let hex = Ticket::from_bytes(&ticket)
+            .unwrap()
+            .title_key_hex()
+            .unwrap();
+        assert_eq!(hex.len(), 32);
+        assert_eq!(hex, "0123456789abcdeffedcba9876543210");
+    }
+
+    #[test]
+    fn out_of_range_key_index() {
+        test_keybag();
+        let ticket = Ticket::builder()
+            .with_title_id(TitleId::from_u64(0x0004000000123400).unwrap())
+            .with_title_key([0x42; 0x10])
+            .with_key_index(7)
+            .build()
+            .unwrap();
+        assert!(matches!(
+            Ticket::from_bytes(&ticket).unwrap().title_key(),
+            Err(CytrynaError::EnumValueOutOfRange("common key index"))
+        ));
+    }
 }
@@ -1,4 +1,8 @@
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
 use core::mem;
+use core::str::FromStr;
 
 use crate::{CytrynaError, CytrynaResult};
 use bitflags::bitflags;
@@ -14,6 +18,28 @@ impl MaybeTitleId {
     pub fn to_titleid(self) -> CytrynaResult<TitleId> {
         TitleId::from_u64(self.raw)
     }
+    /// Makes an instance from little-endian bytes, as stored in data
+    #[must_use]
+    pub const fn from_bytes(bytes: [u8; 8]) -> Self {
+        Self {
+            raw: u64::from_le_bytes(bytes),
+        }
+    }
+    /// Returns little-endian bytes, as stored in data
+    #[must_use]
+    pub const fn to_bytes(self) -> [u8; 8] {
+        self.raw.to_le_bytes()
+    }
+    /// Returns the value without checking if it's a valid title ID
+    #[must_use]
+    pub const fn raw(self) -> u64 {
+        self.raw
+    }
+    /// Checks if this is an all-zero value, used to mark unused entries
+    #[must_use]
+    pub fn is_null(self) -> bool {
+        self.raw == 0
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -26,6 +52,23 @@ impl MaybeTitleIdBe {
     pub fn to_titleid(self) -> CytrynaResult<TitleId> {
         TitleId::from_u64(self.raw.swap_bytes())
     }
+    /// Makes an instance from big-endian bytes, as stored in data
+    #[must_use]
+    pub const fn from_bytes(bytes: [u8; 8]) -> Self {
+        Self {
+            raw: u64::from_ne_bytes(bytes),
+        }
+    }
+    /// Returns the value without checking if it's a valid title ID
+    #[must_use]
+    pub const fn raw(self) -> u64 {
+        u64::from_be_bytes(self.raw.to_ne_bytes())
+    }
+    /// Checks if this is an all-zero value, used to mark unused entries
+    #[must_use]
+    pub fn is_null(self) -> bool {
+        self.raw == 0
+    }
     #[must_use]
     pub fn to_le(self) -> MaybeTitleId {
         MaybeTitleId {
@@ -48,6 +91,16 @@ pub struct TitleId {
 assert_eq_size!(u64, TitleId);
 
 impl TitleId {
+    /// Assembles a title ID from its components, `unique_id` is the whole low half, including
+    /// the variation byte
+    #[must_use]
+    pub const fn new(platform: Platform, category: Category, unique_id: u32) -> TitleId {
+        TitleId {
+            id: unique_id,
+            category,
+            plat: platform,
+        }
+    }
     #[must_use]
     pub fn is_null(&self) -> bool {
         self.to_u64() == 0
@@ -57,14 +110,24 @@ impl TitleId {
         unsafe { mem::transmute(self) }
     }
     pub fn from_u64(what: u64) -> CytrynaResult<TitleId> {
-        let platform = (what & 0x0000_0000_0000_ffff) as u16;
+        let platform = (what >> 48) as u16;
 
-        if platform >= 6 || platform == 0 {
+        if !matches!(platform, 1 | 3 | 4 | 5) {
             return Err(CytrynaError::EnumValueOutOfRange("smdh::Platform"));
         }
 
         Ok(unsafe { mem::transmute(what) })
     }
+    /// Returns the high half of title ID, containing platform and category
+    #[must_use]
+    pub fn high(&self) -> u32 {
+        (self.to_u64() >> 32) as u32
+    }
+    /// Returns the low half of title ID, containing unique ID and variation
+    #[must_use]
+    pub fn low(&self) -> u32 {
+        self.to_u64() as u32
+    }
     #[must_use]
     pub fn id(&self) -> u32 {
         self.id
@@ -77,6 +140,103 @@ impl TitleId {
     pub fn plat(&self) -> Platform {
         self.plat
     }
+    /// Returns the kind of title this title ID belongs to, judging by its category
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        // https://www.3dbrew.org/wiki/Title_list#TWL_System_Titles
+        if self.category.contains(Category::TWL) {
+            return match (self.category - Category::TWL).bits() {
+                0x4 => "DSiWare",
+                0x5 => "system application",
+                0xf => "system data archive",
+                _ => "title",
+            };
+        }
+        match self.category {
+            Category::SYSTEM_APPLICATION => return "system application",
+            Category::APPLET => return "system applet",
+            Category::BASE => return "system module",
+            Category::FIRMWARE => return "firmware",
+            Category::SYSTEM_CONTENT | Category::AUTO_UPDATE_CONTENT => {
+                return "system data archive"
+            }
+            Category::SHARED_CONTENT => return "shared data archive",
+            _ => (),
+        }
+        // lowest bits are a type rather than flags
+        match self.category.bits() & 0x7 {
+            0x0 => "application",
+            0x1 => "download play child",
+            0x2 => "demo",
+            0x3 => "data archive",
+            0x4 => "DLC",
+            0x6 => "update",
+            _ => "title",
+        }
+    }
+    /// Returns a human-readable description of this title ID, like
+    /// "0004000000123400 (CTR application)"
+    #[must_use]
+    pub fn describe(&self) -> String {
+        if self.category.contains(Category::TWL) {
+            format!("{self} ({} TWL {})", self.plat, self.kind())
+        } else {
+            format!("{self} ({} {})", self.plat, self.kind())
+        }
+    }
+}
+
+impl FromStr for TitleId {
+    type Err = CytrynaError;
+
+    /// Parses a title ID from 16 hex digits, optionally prefixed by "0x"
+    fn from_str(from: &str) -> CytrynaResult<Self> {
+        let digits = from.strip_prefix("0x").unwrap_or(from);
+        if digits.len() != 16 {
+            return Err(CytrynaError::InvalidLength {
+                what: "title id string",
+                actual: digits.len(),
+                expected: 16,
+            });
+        }
+        let bytes: [u8; 8] = hex::decode(digits)?.try_into().unwrap();
+        Self::from_u64(u64::from_be_bytes(bytes))
+    }
+}
+
+impl fmt::Display for TitleId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.to_u64())
+    }
+}
+
+/// Title version, packed as 6 bits of major, 6 bits of minor and 4 bits of micro version
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct TitleVersion(pub u16);
+
+impl TitleVersion {
+    /// Returns the major version, stored in top 6 bits
+    #[must_use]
+    pub fn major(self) -> u8 {
+        (self.0 >> 10) as u8
+    }
+    /// Returns the minor version, stored in the 6 bits after major version
+    #[must_use]
+    pub fn minor(self) -> u8 {
+        ((self.0 >> 4) & 0x3f) as u8
+    }
+    /// Returns the micro version, stored in bottom 4 bits
+    #[must_use]
+    pub fn micro(self) -> u8 {
+        (self.0 & 0xf) as u8
+    }
+}
+
+impl fmt::Display for TitleVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major(), self.minor(), self.micro())
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -88,6 +248,18 @@ pub enum Platform {
     Wiiu = 5,
 }
 
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let string = match self {
+            Self::Wii => "Wii",
+            Self::Dsi => "DSi",
+            Self::Ctr => "CTR",
+            Self::Wiiu => "Wii U",
+        };
+        f.write_str(string)
+    }
+}
+
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq)]
     pub struct Category: u16 {
@@ -117,3 +289,119 @@ bitflags! {
         const _ = !0;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Category, MaybeTitleId, MaybeTitleIdBe, Platform, TitleId, TitleVersion};
+    use crate::CytrynaError;
+
+    #[test]
+    fn parse_system_title() {
+        let title_id: TitleId = "0004001000021000".parse().unwrap();
+        assert_eq!(title_id.plat(), Platform::Ctr);
+        assert_eq!(title_id.category(), Category::SYSTEM_APPLICATION);
+        assert_eq!(title_id.high(), 0x00040010);
+        assert_eq!(title_id.low(), 0x00021000);
+        assert_eq!(title_id.to_string(), "0004001000021000");
+    }
+
+    #[test]
+    fn from_components() {
+        let title_id = TitleId::new(Platform::Ctr, Category::SYSTEM_APPLICATION, 0x00021000);
+        assert_eq!(title_id.to_u64(), 0x0004001000021000);
+        assert_eq!(title_id, "0004001000021000".parse().unwrap());
+    }
+
+    #[test]
+    fn title_version_components() {
+        let version = TitleVersion(0x0810);
+        assert_eq!(version.major(), 2);
+        assert_eq!(version.minor(), 1);
+        assert_eq!(version.micro(), 0);
+        assert_eq!(version.to_string(), "2.1.0");
+        assert_eq!(TitleVersion(0xffff).to_string(), "63.63.15");
+    }
+
+    #[test]
+    fn maybe_title_id_endianness() {
+        let bytes = 0x0004000000123400u64.to_le_bytes();
+        let mut swapped = bytes;
+        swapped.reverse();
+
+        let le = MaybeTitleId::from_bytes(bytes);
+        let be = MaybeTitleIdBe::from_bytes(swapped);
+        assert_eq!(le.raw(), 0x0004000000123400);
+        assert_eq!(be.raw(), le.raw());
+        assert_eq!(le.to_bytes(), bytes);
+        assert_eq!(be.to_bytes(), swapped);
+        assert_eq!(be.to_le(), le);
+        assert_eq!(be.to_titleid().unwrap(), le.to_titleid().unwrap());
+
+        assert!(MaybeTitleId::from_bytes([0; 8]).is_null());
+        assert!(MaybeTitleIdBe::from_bytes([0; 8]).is_null());
+        assert!(!le.is_null() && !be.is_null());
+    }
+
+    #[test]
+    fn parse_twl_title() {
+        let title_id: TitleId = "0x0004800542383841".parse().unwrap();
+        assert!(title_id.category().contains(Category::TWL));
+        assert_eq!(title_id.high(), 0x00048005);
+        assert_eq!(title_id.low(), 0x42383841);
+        assert_eq!(title_id.to_string(), "0004800542383841");
+    }
+
+    #[test]
+    fn describe() {
+        let app: TitleId = "0004000000123400".parse().unwrap();
+        assert_eq!(app.describe(), "0004000000123400 (CTR application)");
+        let update: TitleId = "0004000e00123400".parse().unwrap();
+        assert_eq!(update.describe(), "0004000e00123400 (CTR update)");
+        let dlc: TitleId = "0004008c00123400".parse().unwrap();
+        assert_eq!(dlc.describe(), "0004008c00123400 (CTR DLC)");
+        let applet: TitleId = "0004003000008f02".parse().unwrap();
+        assert_eq!(applet.describe(), "0004003000008f02 (CTR system applet)");
+        let system: TitleId = "0004001000021000".parse().unwrap();
+        assert_eq!(
+            system.describe(),
+            "0004001000021000 (CTR system application)"
+        );
+        let twl: TitleId = "0004800542383841".parse().unwrap();
+        assert_eq!(
+            twl.describe(),
+            "0004800542383841 (CTR TWL system application)"
+        );
+    }
+
+    #[test]
+    fn reject_malformed() {
+        assert!(matches!(
+            "00040010".parse::<TitleId>(),
+            Err(CytrynaError::InvalidLength { actual: 8, .. })
+        ));
+        assert!(matches!(
+            "000400100002100g".parse::<TitleId>(),
+            Err(CytrynaError::HexError(_))
+        ));
+        assert!(matches!(
+            "0x0004001000021000 ".parse::<TitleId>(),
+            Err(CytrynaError::InvalidLength { .. })
+        ));
+        assert!(matches!(
+            "0009001000021000".parse::<TitleId>(),
+            Err(CytrynaError::EnumValueOutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn platform_gap() {
+        // there's no platform 2, it must not get transmuted into Platform
+        assert!(matches!(
+            TitleId::from_u64(0x0002000000123400),
+            Err(CytrynaError::EnumValueOutOfRange(_))
+        ));
+        for (raw, plat) in [(1, Platform::Wii), (3, Platform::Dsi), (5, Platform::Wiiu)] {
+            assert_eq!(TitleId::from_u64(raw << 48).unwrap().plat(), plat);
+        }
+    }
+}
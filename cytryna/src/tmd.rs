@@ -1,8 +1,9 @@
 use core::{fmt, mem, ptr, slice};
 
-use crate::crypto::SignedData;
-use crate::titleid::{MaybeTitleIdBe, TitleId};
-use crate::{CytrynaResult, FromBytes};
+use crate::crypto::{SignedBody, SignedData};
+use crate::hash::sha256;
+use crate::titleid::{MaybeTitleIdBe, TitleId, TitleVersion};
+use crate::{CytrynaError, CytrynaResult, FromBytes, Verify};
 
 use bitflags::bitflags;
 use derivative::Derivative;
@@ -45,10 +46,27 @@ pub struct TmdInner {
 impl FromBytes for TmdInner {
     fn min_size() -> usize {
         // https://www.3dbrew.org/wiki/Title_metadata#Header
-        0x64
+        // header followed by content info records
+        0x984
     }
     // TODO: check validity of content indexes
-    fn bytes_ok(_: &[u8]) -> CytrynaResult<()> {
+    fn bytes_ok(bytes: &[u8]) -> CytrynaResult<()> {
+        if bytes.len() < Self::min_size() {
+            return Err(CytrynaError::InvalidLength {
+                what: "TMD",
+                actual: bytes.len(),
+                expected: Self::min_size(),
+            });
+        }
+        let count = u16::from_be_bytes([bytes[0x5e], bytes[0x5f]]) as usize;
+        let expected = Self::min_size() + count * mem::size_of::<ContentChunk>();
+        if bytes.len() < expected {
+            return Err(CytrynaError::InvalidLength {
+                what: "TMD content chunks",
+                actual: bytes.len(),
+                expected,
+            });
+        }
         Ok(())
     }
     fn cast(bytes: &[u8]) -> &Self {
@@ -56,6 +74,13 @@ impl FromBytes for TmdInner {
     }
 }
 
+impl SignedBody for TmdInner {
+    /// Signature only covers the header, content info and chunk records are covered by hashes
+    fn signed_size(_: &[u8]) -> usize {
+        0x84
+    }
+}
+
 /// A type alias for convienency
 pub type Tmd<'a> = SignedData<'a, TmdInner>;
 
@@ -64,6 +89,42 @@ impl<'a> Tmd<'a> {
     pub fn title_id(&self) -> CytrynaResult<TitleId> {
         self.data().title_id.to_titleid()
     }
+    /// Returns the title version
+    #[must_use]
+    pub fn title_version(&self) -> TitleVersion {
+        TitleVersion(u16::from_be_bytes(self.data().title_version))
+    }
+    /// Returns the title ID of system title this title requires, or zero if there is none
+    #[must_use]
+    pub fn system_version(&self) -> u64 {
+        u64::from_be_bytes(self.data().system_version)
+    }
+    /// Returns the title type
+    #[must_use]
+    pub fn title_type(&self) -> u32 {
+        u32::from_be_bytes(self.data().title_type)
+    }
+    /// Returns size of save data in bytes
+    #[must_use]
+    pub fn save_data_size(&self) -> u32 {
+        // unlike everything else in TMD it's little-endian
+        u32::from_le_bytes(self.data().save_data_size)
+    }
+    /// Returns the SRL flag, used by DSiWare titles
+    #[must_use]
+    pub fn srl_flag(&self) -> u8 {
+        self.data().srl_flag
+    }
+    /// Returns size of DSiWare private save data in bytes
+    #[must_use]
+    pub fn srl_private_save_size(&self) -> u32 {
+        u32::from_le_bytes(self.data().srl_private_save_size)
+    }
+    /// Returns the access rights
+    #[must_use]
+    pub fn access_rights(&self) -> u32 {
+        u32::from_be_bytes(self.data().access_rights)
+    }
     /// Returns the contet count
     #[must_use]
     pub fn content_count(&self) -> u16 {
@@ -72,20 +133,105 @@ impl<'a> Tmd<'a> {
     /// Returns slice of content chunk
     #[must_use]
     pub fn content_chunks(&self) -> &[ContentChunk] {
+        // content chunks are made of byte arrays, so they can't be misaligned
         let ptr = ptr::addr_of!(self.data().content_chunk_records);
         let amount = self.content_count();
-        assert_eq!(
-            ptr as *const u8 as usize % mem::align_of::<ContentChunk>(),
-            0
-        );
 
         unsafe { slice::from_raw_parts(ptr as *const ContentChunk, amount as usize) }
     }
+    /// Returns an iterator over content info records that are used
+    pub fn content_infos(&self) -> impl Iterator<Item = &ContentInfo> {
+        self.data()
+            .content_info_records
+            .iter()
+            .filter(|info| !info.is_nil())
+    }
+    /// Checks the hash of content info records stored in the header, then if all used content
+    /// info records match hashes of content chunks they cover
+    #[must_use]
+    pub fn verify_info_hashes(&self) -> bool {
+        let infos = &self.data().content_info_records;
+        // content info records are made of byte arrays, so they can't be misaligned
+        let info_bytes =
+            unsafe { slice::from_raw_parts(infos.as_ptr().cast::<u8>(), mem::size_of_val(infos)) };
+        if sha256(info_bytes) != self.data().hash {
+            return false;
+        }
+
+        let chunks = self.content_chunks();
+        self.content_infos().all(|info| {
+            let start = info.index_offset() as usize;
+            let Some(covered) = chunks.get(start..start + info.cmd_count() as usize) else {
+                return false;
+            };
+            // content chunks are laid out back to back with no padding
+            let bytes = unsafe {
+                slice::from_raw_parts(covered.as_ptr().cast::<u8>(), mem::size_of_val(covered))
+            };
+            sha256(bytes) == info.hash
+        })
+    }
+    /// Returns the index of content that boots first
+    #[must_use]
+    pub fn boot_content(&self) -> u16 {
+        u16::from_be_bytes(self.data().boot_content)
+    }
+    /// Returns the content chunk of content that boots first
+    #[must_use]
+    pub fn boot_chunk(&self) -> Option<&ContentChunk> {
+        let boot_content = self.boot_content();
+        self.content_chunks()
+            .iter()
+            .find(|c| c.index() == boot_content)
+    }
+    /// Heuristically checks if this TMD belongs to an update title, by checking if it only has
+    /// main and manual contents with the main one booting first.
+    ///
+    /// Base applications without a Download Play child look the same, so title ID category
+    /// should be preferred when it's available
+    #[must_use]
+    pub fn is_update(&self) -> bool {
+        let boot_content = self.boot_content();
+        let chunks = self.content_chunks();
+
+        boot_content == 0
+            && (1..=2).contains(&chunks.len())
+            && chunks
+                .iter()
+                .all(|c| c.index() <= ContentIndex::Manual as u16)
+            && !chunks
+                .iter()
+                .any(|c| c.ty().contains(ContentType::OPTIONAL))
+    }
+    /// Heuristically checks if this TMD belongs to DLC, by checking if it has contents with
+    /// indices past the ones used by applications
+    #[must_use]
+    pub fn is_dlc(&self) -> bool {
+        let boot_content = self.boot_content();
+        let chunks = self.content_chunks();
+
+        boot_content == 0
+            && chunks.len() > 1
+            && chunks.iter().any(|c| c.index() > ContentIndex::Dlp as u16)
+    }
+}
+
+impl Verify for Tmd<'_> {
+    /// Checks the hash of content info records in the header and hashes of content chunks they
+    /// cover, the signature needs issuer's public key so it's not checked here, see
+    /// [SignedData::verify]
+    fn verify(&self) -> CytrynaResult<()> {
+        if self.verify_info_hashes() {
+            Ok(())
+        } else {
+            Err(CytrynaError::InvalidHash)
+        }
+    }
 }
 
 /// Content Index
 /// <https://www.3dbrew.org/wiki/Title_metadata#Content_Index>
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum ContentIndex {
     Main = 0,
@@ -93,14 +239,26 @@ pub enum ContentIndex {
     Dlp = 2,
 }
 
+impl TryFrom<u16> for ContentIndex {
+    type Error = CytrynaError;
+
+    fn try_from(value: u16) -> CytrynaResult<Self> {
+        match value {
+            0 => Ok(Self::Main),
+            1 => Ok(Self::Manual),
+            2 => Ok(Self::Dlp),
+            _ => Err(CytrynaError::EnumValueOutOfRange("tmd::ContentIndex")),
+        }
+    }
+}
+
 /// Content chunk record
 /// <https://www.3dbrew.org/wiki/Title_metadata#Content_chunk_records>
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct ContentChunk {
     id: [u8; 0x4],
-    _pad: u8,
-    idx: ContentIndex,
+    index: [u8; 0x2],
     ty: [u8; 0x2],
     size: [u8; 8], // actually u64be
     hash: [u8; 0x20],
@@ -113,10 +271,20 @@ impl ContentChunk {
     pub fn id(&self) -> u32 {
         u32::from_be_bytes(self.id)
     }
-    /// Returns ContentIndex of this content chunk
+    /// Returns raw content index of this content chunk, which may be outside of ContentIndex
+    /// range for DLC contents
+    #[must_use]
+    pub fn index(&self) -> u16 {
+        u16::from_be_bytes(self.index)
+    }
+    /// Returns ContentIndex of this content chunk, fails for DLC contents
+    pub fn idx(&self) -> CytrynaResult<ContentIndex> {
+        ContentIndex::try_from(self.index())
+    }
+    /// Returns raw bytes of this content chunk, as they are stored in the TMD
     #[must_use]
-    pub fn idx(&self) -> ContentIndex {
-        self.idx
+    pub fn raw_bytes(&self) -> &[u8; 0x30] {
+        unsafe { &*(self as *const Self).cast() }
     }
     /// Returns the content type
     #[must_use]
@@ -145,19 +313,41 @@ impl ContentChunk {
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct ContentInfo {
-    _pad: u8,
-    idx: ContentIndex,
+    index_offset: [u8; 0x2],
     cmd_count: [u8; 0x2],
     hash: [u8; 0x20],
 }
 assert_eq_size!([u8; 0x24], ContentInfo);
 
+impl ContentInfo {
+    /// Returns index of the first content chunk covered by this record
+    #[must_use]
+    pub fn index_offset(&self) -> u16 {
+        u16::from_be_bytes(self.index_offset)
+    }
+    /// Returns how many content chunks are covered by this record
+    #[must_use]
+    pub fn cmd_count(&self) -> u16 {
+        u16::from_be_bytes(self.cmd_count)
+    }
+    /// Returns the hash of covered content chunks
+    #[must_use]
+    pub fn hash(&self) -> &[u8; 0x20] {
+        &self.hash
+    }
+    /// Checks if this content info record is unused
+    #[must_use]
+    pub fn is_nil(&self) -> bool {
+        self.cmd_count == [0, 0] && self.hash.iter().all(|v| *v == 0)
+    }
+}
+
 impl fmt::Debug for ContentInfo {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if self.cmd_count != [0, 0] || self.hash.iter().any(|v| *v != 0) {
+        if !self.is_nil() {
             f.debug_struct("ContentInfo")
-                .field("idx", &self.idx)
-                .field("cmd_count", &self.cmd_count)
+                .field("index_offset", &self.index_offset())
+                .field("cmd_count", &self.cmd_count())
                 .field("hash", &self.hash)
                 .finish()
         } else {
@@ -178,3 +368,204 @@ bitflags! {
         const SHARED = 0x8000;
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{TitleVersion, Tmd};
+    use crate::crypto::{build_envelope, SignatureType};
+    use crate::hash::sha256;
+    use crate::tests::verify_any;
+    use crate::CytrynaError;
+
+    /// Makes an RSA-2048 signed TMD with given content chunks, as (id, index, type, size)
+    pub(crate) fn test_tmd(title_id: u64, boot_content: u16, chunks: &[(u32, u16, u16, u64)]) -> Vec<u8> {
+        let mut inner = vec![0u8; 0x984];
+        inner[0xc..0x14].copy_from_slice(&title_id.to_be_bytes());
+        inner[0x5e..0x60].copy_from_slice(&(chunks.len() as u16).to_be_bytes());
+        inner[0x60..0x62].copy_from_slice(&boot_content.to_be_bytes());
+
+        for (id, idx, ty, size) in chunks {
+            let mut chunk = [0u8; 0x30];
+            chunk[0x0..0x4].copy_from_slice(&id.to_be_bytes());
+            chunk[0x4..0x6].copy_from_slice(&idx.to_be_bytes());
+            chunk[0x6..0x8].copy_from_slice(&ty.to_be_bytes());
+            chunk[0x8..0x10].copy_from_slice(&size.to_be_bytes());
+            inner.extend_from_slice(&chunk);
+        }
+        let hash = sha256(&inner[0x84..0x984]);
+        inner[0x64..0x84].copy_from_slice(&hash);
+        build_envelope(SignatureType::Rsa2048Sha256, &[], "", &inner).unwrap()
+    }
+
+    /// Updates the content info records hash after they were modified
+    fn rehash_infos(tmd: &mut [u8]) {
+        let hash = sha256(&tmd[0x180 + 0x84..0x180 + 0x984]);
+        tmd[0x180 + 0x64..0x180 + 0x84].copy_from_slice(&hash);
+    }
+
+    #[test]
+    fn update_and_dlc_heuristics() {
+        let update = test_tmd(0x0004000e00123400, 0, &[(0, 0, 0x1, 0x100)]);
+        let update = Tmd::from_bytes(&update).unwrap();
+        assert!(update.is_update());
+        assert!(!update.is_dlc());
+
+        let dlc = test_tmd(
+            0x0004008c00123400,
+            0,
+            &[
+                (0, 0, 0x1, 0x100),
+                (1, 0x3, 0x4001, 0x100),
+                (2, 0x4, 0x4001, 0x100),
+            ],
+        );
+        let dlc = Tmd::from_bytes(&dlc).unwrap();
+        assert!(dlc.is_dlc());
+        assert!(!dlc.is_update());
+        assert_eq!(dlc.content_chunks()[2].index(), 0x4);
+    }
+
+    #[test]
+    fn boot_chunk() {
+        let tmd = test_tmd(0x0004000000123400, 0, &[(5, 1, 0, 0x10), (7, 0, 0, 0x20)]);
+        let tmd = Tmd::from_bytes(&tmd).unwrap();
+        assert_eq!(tmd.boot_content(), 0);
+        let chunk = tmd.boot_chunk().unwrap();
+        assert_eq!(chunk.id(), 7);
+        assert_eq!(chunk.size(), 0x20);
+
+        let tmd = test_tmd(0x0004000000123400, 2, &[(7, 0, 0, 0x20)]);
+        assert!(Tmd::from_bytes(&tmd).unwrap().boot_chunk().is_none());
+    }
+
+    #[test]
+    fn chunk_raw_bytes() {
+        let tmd = test_tmd(0x0004008c00123400, 0, &[(0x12345678, 0x1f, 0x4001, 0x1020)]);
+        let chunks = &tmd[0x180 + 0x984..];
+        let tmd = Tmd::from_bytes(&tmd).unwrap();
+        let chunk = &tmd.content_chunks()[0];
+        assert_eq!(chunk.raw_bytes(), &chunks[..0x30]);
+        assert_eq!(chunk.id(), 0x12345678);
+        assert_eq!(chunk.index(), 0x1f);
+        assert!(matches!(
+            chunk.idx(),
+            Err(CytrynaError::EnumValueOutOfRange(_))
+        ));
+        assert_eq!(chunk.ty().bits(), 0x4001);
+        assert_eq!(chunk.size(), 0x1020);
+    }
+
+    #[test]
+    fn access_rights() {
+        let mut tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0, 0x10)]);
+        tmd[0x180 + 0x58..][..4].copy_from_slice(&[0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(Tmd::from_bytes(&tmd).unwrap().access_rights(), 0x12345678);
+    }
+
+    #[test]
+    fn header_fields() {
+        let mut tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0, 0x10)]);
+        let inner = &mut tmd[0x180..];
+        inner[0x4..0xc].copy_from_slice(&[0x00, 0x04, 0x01, 0x38, 0x00, 0x00, 0x00, 0x02]);
+        inner[0x14..0x18].copy_from_slice(&[0x00, 0x00, 0x00, 0x40]);
+        inner[0x1a..0x1e].copy_from_slice(&[0x00, 0x00, 0x08, 0x00]);
+        inner[0x5c..0x5e].copy_from_slice(&[0x04, 0x10]);
+
+        let tmd = Tmd::from_bytes(&tmd).unwrap();
+        assert_eq!(tmd.system_version(), 0x0004013800000002);
+        assert_eq!(tmd.title_type(), 0x40);
+        assert_eq!(tmd.save_data_size(), 0x80000);
+        assert_eq!(tmd.title_version(), TitleVersion(0x0410));
+    }
+
+    #[test]
+    fn info_hashes() {
+        let mut tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0, 0x10), (1, 1, 0, 0x20)]);
+        let hash = sha256(&tmd[0x180 + 0x984..][..0x60]);
+        let info = &mut tmd[0x180 + 0x84..][..0x24];
+        info[0x2..0x4].copy_from_slice(&2u16.to_be_bytes());
+        info[0x4..].copy_from_slice(&hash);
+        // info records changed without updating their hash in the header
+        assert!(!Tmd::from_bytes(&tmd).unwrap().verify_info_hashes());
+
+        rehash_infos(&mut tmd);
+        let parsed = Tmd::from_bytes(&tmd).unwrap();
+        assert_eq!(parsed.content_infos().count(), 1);
+        assert!(parsed.verify_info_hashes());
+
+        // info record covering only the first chunk doesn't match anymore
+        tmd[0x180 + 0x84 + 0x3] = 1;
+        rehash_infos(&mut tmd);
+        assert!(!Tmd::from_bytes(&tmd).unwrap().verify_info_hashes());
+
+        // info record covering chunks past the end
+        tmd[0x180 + 0x84 + 0x3] = 3;
+        rehash_infos(&mut tmd);
+        assert!(!Tmd::from_bytes(&tmd).unwrap().verify_info_hashes());
+    }
+
+    #[test]
+    fn oversized_content_count() {
+        let mut tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0, 0x10)]);
+        tmd[0x180 + 0x5e..0x180 + 0x60].copy_from_slice(&2u16.to_be_bytes());
+        assert!(matches!(
+            Tmd::from_bytes(&tmd),
+            Err(CytrynaError::InvalidLength {
+                what: "TMD content chunks",
+                actual: 0x9b4,
+                expected: 0x9e4
+            })
+        ));
+    }
+
+    #[test]
+    fn too_short_for_info_records() {
+        let tmd = test_tmd(0x0004000000123400, 0, &[]);
+        assert!(matches!(
+            Tmd::from_bytes(&tmd[..0x180 + 0x100]),
+            Err(CytrynaError::InvalidLength {
+                what: "TMD",
+                actual: 0x100,
+                expected: 0x984
+            })
+        ));
+    }
+
+    #[test]
+    fn verify_trait() {
+        let mut tmd = test_tmd(0x0004000000123400, 0, &[(0, 0, 0, 0x10)]);
+        let hash = sha256(&tmd[0x180 + 0x984..][..0x30]);
+        let info = &mut tmd[0x180 + 0x84..][..0x24];
+        info[0x2..0x4].copy_from_slice(&1u16.to_be_bytes());
+        info[0x4..].copy_from_slice(&hash);
+        rehash_infos(&mut tmd);
+        assert!(verify_any(&Tmd::from_bytes(&tmd).unwrap()).is_ok());
+
+        // header hash of content info records
+        tmd[0x180 + 0x64] ^= 0x1;
+        assert!(matches!(
+            verify_any(&Tmd::from_bytes(&tmd).unwrap()),
+            Err(CytrynaError::InvalidHash)
+        ));
+        tmd[0x180 + 0x64] ^= 0x1;
+
+        tmd[0x180 + 0x84 + 0x4] ^= 0x1;
+        rehash_infos(&mut tmd);
+        assert!(matches!(
+            verify_any(&Tmd::from_bytes(&tmd).unwrap()),
+            Err(CytrynaError::InvalidHash)
+        ));
+    }
+
+    #[test]
+    fn srl_fields() {
+        let mut tmd = test_tmd(0x0004800442383841, 0, &[(0, 0, 0, 0x10)]);
+        let inner = &mut tmd[0x180..];
+        inner[0x1e..0x22].copy_from_slice(&[0x00, 0x40, 0x00, 0x00]);
+        inner[0x26] = 0x3;
+
+        let tmd = Tmd::from_bytes(&tmd).unwrap();
+        assert_eq!(tmd.srl_flag(), 0x3);
+        assert_eq!(tmd.srl_private_save_size(), 0x4000);
+    }
+}
@@ -1,8 +1,9 @@
 use anyhow::{bail, ensure, Context, Result};
 use clap::{Subcommand, Parser};
 use cytryna::prelude::*;
+use cytryna::hb3dsx::{RelocationType, Segment};
 use std::{fs, path::PathBuf};
-use goblin::elf::{Elf, header, program_header};
+use goblin::elf::{Elf, header, program_header, reloc, section_header};
 
 #[derive(Debug, Parser)]
 struct Args {
@@ -26,9 +27,12 @@ fn main() -> Result<()> {
 
     let mut base_addr = 0;
     let mut top_addr = 0;
-    let mut code_slice;
-    let mut rodata_slice;
-    let mut data_slice;
+    let mut code_slice: &[u8] = &[];
+    let mut rodata_slice: &[u8] = &[];
+    let mut data_slice: &[u8] = &[];
+    let mut bss_size = 0;
+    let mut segment_addrs = [0; 3];
+    let mut segment_count = 0;
 
     let iter = elf.program_headers.iter()
         .filter(|hdr| hdr.p_type == program_header::PT_LOAD)
@@ -54,10 +58,13 @@ fn main() -> Result<()> {
             6 => {
                 ensure!(i == 2, "Data must be the third segment");
                 data_slice = &input_bytes[hdr.p_offset as usize..][..hdr.p_filesz as usize];
+                bss_size = hdr.p_memsz - hdr.p_filesz;
             },
             _ if i > 2 => bail!("Too many segments"),
             other => bail!("Invalid segment {:x}", other),
         }
+        segment_addrs[i] = hdr.p_vaddr;
+        segment_count = i + 1;
 
         top_addr = hdr.p_vaddr + (hdr.p_memsz + 0xfff) & !0xfff;
 
@@ -70,6 +77,91 @@ fn main() -> Result<()> {
 
     let mut abs_reloc_map = vec![false; (len/4) as usize];
     let mut rel_reloc_map = vec![false; (len/4) as usize];
+    let mut segment_data = [code_slice.to_vec(), rodata_slice.to_vec(), data_slice.to_vec()];
+
+    // segments are contigous, so the last one starting at or below the address contains it
+    let segment_of = |addr: u64| {
+        (base_addr..top_addr).contains(&addr)
+            .then(|| segment_addrs[..segment_count].iter().rposition(|&start| addr >= start))
+            .flatten()
+    };
+
+    // Needs the ELF to be linked with --emit-relocs, addresses get rewritten to be relative to the
+    // start of the image, same as 3dsxtool does
+    for (shdr_idx, relocs) in &elf.shdr_relocs {
+        let target = elf.section_headers.get(elf.section_headers[*shdr_idx].sh_info as usize)
+            .context("Relocation section targets an invalid section")?;
+        // debug info and such isn't loaded
+        if target.sh_flags & section_header::SHF_ALLOC as u64 == 0 {
+            continue;
+        }
+
+        for rel in relocs.iter() {
+            let src = rel.r_offset;
+            ensure!(src & 3 == 0, "Relocation at {:#x} is not word-aligned", src);
+            let Some(segment) = segment_of(src) else {
+                bail!("Relocation at {:#x} is outside of the executable", src);
+            };
+            let pos = (src - segment_addrs[segment]) as usize;
+            let Some(bytes) = segment_data[segment].get_mut(pos..pos + 4) else {
+                bail!("Relocation at {:#x} is outside of loadable data", src);
+            };
+            let word = u32::from_le_bytes(bytes.try_into().unwrap());
+
+            let patched = match rel.r_type {
+                reloc::R_ARM_ABS32 | reloc::R_ARM_TARGET1 => {
+                    abs_reloc_map[((src - base_addr) / 4) as usize] = true;
+                    word.wrapping_sub(base_addr as u32)
+                },
+                reloc::R_ARM_REL32 | reloc::R_ARM_TARGET2 | reloc::R_ARM_PREL31 => {
+                    let addend = if rel.r_type == reloc::R_ARM_PREL31 {
+                        ((word << 1) as i32) >> 1
+                    } else {
+                        word as i32
+                    };
+                    let dst = (src as u32).wrapping_add(addend as u32);
+                    // offsets within a segment don't change after loading
+                    if segment_of(dst as u64) == Some(segment) {
+                        continue;
+                    }
+                    rel_reloc_map[((src - base_addr) / 4) as usize] = true;
+                    let offset = dst.wrapping_sub(base_addr as u32);
+                    if rel.r_type == reloc::R_ARM_PREL31 {
+                        (word & 0x8000_0000) | (offset & 0x7fff_ffff)
+                    } else {
+                        offset
+                    }
+                },
+                _ => continue,
+            };
+            bytes.copy_from_slice(&patched.to_le_bytes());
+        }
+    }
+
+    let mut builder = Hb3dsx::builder();
+    let segments = [Segment::Code, Segment::Rodata, Segment::Data];
+    for ((segment, data), addr) in segments.into_iter().zip(segment_data).zip(segment_addrs) {
+        let start = ((addr - base_addr) / 4) as usize;
+        for word in 0..data.len() / 4 {
+            if abs_reloc_map[start + word] {
+                builder.add_relocation(segment, RelocationType::Absolute, word as u32);
+            }
+            if rel_reloc_map[start + word] {
+                builder.add_relocation(segment, RelocationType::Relative, word as u32);
+            }
+        }
+        match segment {
+            Segment::Code => builder.with_code(data),
+            Segment::Rodata => builder.with_rodata(data),
+            Segment::Data => builder.with_data(data),
+        };
+    }
+    builder.with_bss_size(bss_size as u32);
+
+    let output = builder.build()
+        .context("Failed to build 3DSX")?;
+    fs::write(&args.output_file, output)
+        .context("Failed to write 3DSX file")?;
 
     Ok(())
 }
@@ -1,6 +1,7 @@
 use anyhow::{Result, Context};
 use clap::{Subcommand, Parser};
 use cytryna::prelude::*;
+use cytryna::smdh::IconData;
 use std::{fs, path::PathBuf};
 
 #[derive(Parser)]
@@ -42,12 +43,13 @@ fn main() -> Result<()> {
         Commands::Create {
             short_desc, long_desc, publisher, icon, small_icon, output,
         } => {
-            let icon_big = bmp::open(&icon)
+            let icon_big = IconData::from_image_path(&icon)
                 .context("Failed to open big icon")?;
 
             let icon_small;
             if let Some(path) = small_icon {
-                icon_small = Some(bmp::open(&path)?);
+                icon_small = Some(IconData::from_image_path(&path)
+                    .context("Failed to open small icon")?);
             } else {
                 icon_small = None;
             }
@@ -59,10 +61,10 @@ fn main() -> Result<()> {
                     .context("Failed to set long description")?
                 .with_publisher(&publisher)
                     .context("Filed to set publisher info")?
-                .with_icon((&icon_big).try_into().context("Failed to set big icon info")?);
+                .with_icon(icon_big);
 
             if let Some(icon) = icon_small {
-                builder.with_small_icon((&icon).try_into().context("Failed to set small icon data")?);
+                builder.with_small_icon(icon);
             }
 
             let smdh = builder.build() .context("Failed to build SMDH")?;